@@ -0,0 +1,168 @@
+//! Grinds block headers' proof of work on every core.
+
+use std::num::NonZero;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use bitcoin::block::Header;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::{Hash, HashEngine, sha256, sha256d};
+use bitcoin::{BlockHash, Target};
+use eyre::{Result, eyre};
+use log::debug;
+use thiserror::Error;
+
+/// How many nonces a thread tries between looking for a reason to stop.
+const CHECK_EVERY: u64 = 1 << 16;
+
+/// No nonce solves the header at its time.
+#[derive(Debug, Error)]
+#[error("No nonce solves the header at time {0}")]
+pub struct NoncesExhausted(pub u32);
+
+/// Stops a grind from another thread, say when a new tip makes the block stale.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Find a nonce that makes `header` meet its target, with one thread per core.
+///
+/// When no nonce does, `roll_time` moves the header's time on a second and tries again, which
+/// won't do for signet blocks: their solution signs the time, so they fail with
+/// [`NoncesExhausted`] to be signed again. Returns `None` if cancelled.
+pub fn grind(header: &Header, roll_time: bool, cancel: &Cancel) -> Result<Option<Header>> {
+    let threads = thread::available_parallelism().map_or(1, NonZero::get);
+    grind_with(header, threads, roll_time, cancel)
+}
+
+/// [`grind`] with `threads` threads.
+pub fn grind_with(
+    header: &Header,
+    threads: usize,
+    roll_time: bool,
+    cancel: &Cancel,
+) -> Result<Option<Header>> {
+    let target = header.target();
+    let mut header = *header;
+    loop {
+        if let Some(nonce) = search_nonces(&header, target, threads, cancel) {
+            header.nonce = nonce;
+            return Ok(Some(header));
+        }
+        if cancel.is_cancelled() {
+            return Ok(None);
+        }
+        if !roll_time {
+            return Err(NoncesExhausted(header.time).into());
+        }
+        header.time = header
+            .time
+            .checked_add(1)
+            .ok_or_else(|| eyre!("Ran out of times to roll the header to"))?;
+        debug!("Ran out of nonces, rolling the time to {}", header.time);
+    }
+}
+
+/// Split the nonces between `threads` threads and return the first one found to solve `header`.
+fn search_nonces(header: &Header, target: Target, threads: usize, cancel: &Cancel) -> Option<u32> {
+    let bytes = serialize(header);
+    // Only the last 16 bytes change with the nonce, so the first 64 are hashed once
+    let mut midstate = sha256d::Hash::engine();
+    midstate.input(&bytes[..64]);
+    let tail: [u8; 16] = bytes[64..].try_into().expect("headers are 80 bytes");
+
+    let threads = threads.max(1) as u64;
+    let found = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let searches: Vec<_> = (0..threads)
+            .map(|i| {
+                let nonces = (i << 32) / threads..((i + 1) << 32) / threads;
+                let (midstate, found) = (&midstate, &found);
+                scope.spawn(move || search_range(midstate, tail, nonces, target, found, cancel))
+            })
+            .collect();
+        searches
+            .into_iter()
+            .filter_map(|search| search.join().ok().flatten())
+            .next()
+    })
+}
+
+fn search_range(
+    midstate: &sha256::HashEngine,
+    mut tail: [u8; 16],
+    nonces: Range<u64>,
+    target: Target,
+    found: &AtomicBool,
+    cancel: &Cancel,
+) -> Option<u32> {
+    let start = nonces.start;
+    for nonce in nonces {
+        if (nonce - start).is_multiple_of(CHECK_EVERY)
+            && (found.load(Ordering::Relaxed) || cancel.is_cancelled())
+        {
+            return None;
+        }
+        let nonce = nonce as u32;
+        tail[12..].copy_from_slice(&nonce.to_le_bytes());
+        let mut engine = midstate.clone();
+        engine.input(&tail);
+        let hash = BlockHash::from_raw_hash(sha256d::Hash::from_engine(engine));
+        if target.is_met_by(hash) {
+            found.store(true, Ordering::Relaxed);
+            return Some(nonce);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::block::Version;
+    use bitcoin::{CompactTarget, TxMerkleNode};
+
+    fn header(bits: u32) -> Header {
+        Header {
+            version: Version::from_consensus(0x2000_0000),
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 1_598_918_400,
+            bits: CompactTarget::from_consensus(bits),
+            nonce: 0,
+        }
+    }
+
+    #[test]
+    fn solves_the_header_it_was_given() {
+        // About 65k hashes, solved in well under a second
+        let unsolved = header(0x1f00ffff);
+        for threads in [1, 4] {
+            let solved = grind_with(&unsolved, threads, false, &Cancel::default())
+                .unwrap()
+                .unwrap();
+            assert!(solved.validate_pow(solved.target()).is_ok());
+            assert_eq!(solved.time, unsolved.time);
+            assert_eq!(solved.merkle_root, unsolved.merkle_root);
+        }
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let cancel = Cancel::default();
+        cancel.cancel();
+        // Far too hard to solve before the first check
+        assert!(grind(&header(0x1d00ffff), true, &cancel).unwrap().is_none());
+    }
+}
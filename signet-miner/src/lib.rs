@@ -0,0 +1,1808 @@
+//! A port of Bitcoin Core's `contrib/signet/miner`, which builds signet blocks, has a signer
+//! satisfy the network's challenge and grinds the result. Both the game and the `signet-miner`
+//! binary mine with it.
+
+pub mod grinder;
+pub mod rpc_client;
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bitcoin::absolute::LockTime;
+use bitcoin::bip32::{ChildNumber, KeySource};
+use bitcoin::block::{self, Header};
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{Hash, sha256d};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::opcodes::OP_0;
+use bitcoin::opcodes::all::{OP_PUSHNUM_1, OP_RETURN};
+use bitcoin::psbt::raw::ProprietaryKey;
+use bitcoin::psbt::{GetKey, KeyRequest};
+use bitcoin::script::{Builder, PushBytes};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, Block, BlockHash, CompactTarget, EcdsaSighashType, OutPoint, PrivateKey, Psbt, Script,
+    ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Witness,
+};
+use eyre::{Result, eyre};
+use log::{debug, info, warn};
+use miniscript::descriptor::{
+    DefiniteDescriptorKey, Descriptor, DescriptorPublicKey, DescriptorSecretKey, Wildcard,
+};
+use miniscript::psbt::PsbtExt;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::grinder::{Cancel, NoncesExhausted};
+use crate::rpc_client::RpcClient;
+
+/// Starts the push in the coinbase's last output that carries the signet solution.
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+/// Starts the push in the coinbase output that commits to the block's witnesses.
+const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+const WITNESS_RESERVED_VALUE: [u8; 32] = [0; 32];
+
+/// 10 minutes, adjusted for the off-by-one in the difficulty retarget.
+const INTERVAL: f64 = 600.0 * 2016.0 / 2015.0;
+/// The default signet's nBits, which is as easy as a signet gets.
+const MIN_NBITS: &str = "1e0377ae";
+/// How often to look for a new tip while grinding.
+const TIP_POLL: Duration = Duration::from_secs(1);
+/// How long to wait for a template on the right tip after getting one on another.
+const GBT_RETRY: Duration = Duration::from_secs(1);
+/// The game stamps its blocks this far apart. A retarget measures 2015 gaps against 2016 blocks'
+/// worth of time, so a hair over ten minutes keeps the difficulty at its minimum.
+const BLOCK_SPACING: i64 = 601;
+/// A block can't be more than 2h in the future, so 1h55m for some safety.
+const MAX_FUTURE: i64 = 6900;
+
+/// What `getblocktemplate` returns on a signet, or the parts of it the miner uses.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockTemplate {
+    version: i32,
+    previousblockhash: BlockHash,
+    bits: String,
+    curtime: u32,
+    mintime: i64,
+    coinbasevalue: u64,
+    height: u32,
+    transactions: Vec<TemplateTx>,
+    signet_challenge: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct TemplateTx {
+    data: String,
+}
+
+/// `getblockheader`'s view of the chain tip.
+#[derive(Clone, Debug, Deserialize)]
+pub struct HeaderInfo {
+    hash: BlockHash,
+    height: u32,
+    bits: String,
+    time: i64,
+}
+
+#[derive(Deserialize)]
+pub struct AddressInfo {
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: String,
+}
+
+/// What `walletprocesspsbt` returns.
+#[derive(Deserialize)]
+pub struct ProcessedPsbt {
+    pub psbt: String,
+    pub complete: bool,
+}
+
+/// The miner's flags, named after the Python miner's, and what it remembers between blocks.
+pub struct MinerArgs {
+    pub address: Option<String>,
+    pub descriptor: Option<String>,
+    pub derived_addresses: BTreeMap<u32, String>,
+    pub reward_spk: Option<ScriptBuf>,
+    pub poolid: Option<String>,
+    pub poolnum: Option<u32>,
+    pub nbits: Option<String>,
+    pub min_nbits: bool,
+    pub set_block_time: Option<i64>,
+    pub max_blocks: Option<u32>,
+    pub ongoing: bool,
+    pub multiminer: Option<String>,
+    pub max_interval: i64,
+    pub poisson: bool,
+    pub standby_delay: i64,
+    pub backup_delay: i64,
+    pub grind_cmd: Option<String>,
+    /// Signs blocks in process; without it the node's wallet does.
+    pub signer: Option<BlockSigner>,
+}
+
+/// The node calls the miner makes, so it can run against bitcoind or a script.
+pub trait Node: Sync {
+    fn get_block_template(&self) -> Result<BlockTemplate>;
+
+    fn get_best_block_hash(&self) -> Result<BlockHash>;
+
+    fn get_block_header(&self, hash: &BlockHash) -> Result<HeaderInfo>;
+
+    /// Have the node's wallet sign `psbt`.
+    fn wallet_process_psbt(&self, psbt: &str) -> Result<ProcessedPsbt>;
+
+    /// `None` if the node took the block, or why it didn't.
+    fn submit_block(&self, block: &Block) -> Result<Option<String>>;
+
+    fn derive_addresses(&self, descriptor: &str, range: Option<(u32, u32)>) -> Result<Vec<String>>;
+
+    fn get_address_info(&self, address: &str) -> Result<AddressInfo>;
+}
+
+/// bitcoind over JSON-RPC, signing with `wallet` if given and its only wallet otherwise.
+pub struct RpcNode {
+    pub rpc: RpcClient,
+    pub wallet: Option<RpcClient>,
+}
+
+impl Node for RpcNode {
+    fn get_block_template(&self) -> Result<BlockTemplate> {
+        Ok(self.rpc.call(
+            "getblocktemplate",
+            &[json!({"rules": ["signet", "segwit"]})],
+        )?)
+    }
+
+    fn get_best_block_hash(&self) -> Result<BlockHash> {
+        Ok(self.rpc.call("getbestblockhash", &[])?)
+    }
+
+    fn get_block_header(&self, hash: &BlockHash) -> Result<HeaderInfo> {
+        Ok(self.rpc.call("getblockheader", &[json!(hash)])?)
+    }
+
+    fn wallet_process_psbt(&self, psbt: &str) -> Result<ProcessedPsbt> {
+        let wallet = self.wallet.as_ref().unwrap_or(&self.rpc);
+        Ok(wallet.call(
+            "walletprocesspsbt",
+            &[json!(psbt), json!(true), json!("ALL")],
+        )?)
+    }
+
+    fn submit_block(&self, block: &Block) -> Result<Option<String>> {
+        Ok(self
+            .rpc
+            .call("submitblock", &[json!(serialize_hex(block))])?)
+    }
+
+    fn derive_addresses(&self, descriptor: &str, range: Option<(u32, u32)>) -> Result<Vec<String>> {
+        let params = match range {
+            Some((start, end)) => vec![json!(descriptor), json!([start, end])],
+            None => vec![json!(descriptor)],
+        };
+        Ok(self.rpc.call("deriveaddresses", &params)?)
+    }
+
+    fn get_address_info(&self, address: &str) -> Result<AddressInfo> {
+        // Any wallet can describe an address, but there has to be one
+        let wallet = self.wallet.as_ref().unwrap_or(&self.rpc);
+        Ok(wallet.call("getaddressinfo", &[json!(address)])?)
+    }
+}
+
+/// Satisfies the signet's challenge in process, with the private keys of a descriptor for it.
+pub struct BlockSigner {
+    descriptor: Descriptor<DefiniteDescriptorKey>,
+    keys: BTreeMap<KeySource, PrivateKey>,
+}
+
+impl BlockSigner {
+    /// `descriptor` is the challenge with its private keys in, such as `multi(1,tprv.../0/0)` for
+    /// a bare multisig or `tr(tprv...)` for a taproot key path. Ranged descriptors use index 0.
+    pub fn new(descriptor: &str) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let (descriptor, key_map) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)?;
+
+        // Keyed the way `update_input_with_descriptor` records each key's origin
+        let mut keys = BTreeMap::new();
+        for (public, secret) in key_map {
+            let private = match secret {
+                DescriptorSecretKey::Single(single) => single.key,
+                DescriptorSecretKey::XPrv(xprv) => {
+                    let path = match xprv.wildcard {
+                        Wildcard::None => xprv.derivation_path,
+                        Wildcard::Unhardened => {
+                            xprv.derivation_path.child(ChildNumber::Normal { index: 0 })
+                        }
+                        Wildcard::Hardened => xprv
+                            .derivation_path
+                            .child(ChildNumber::Hardened { index: 0 }),
+                    };
+                    xprv.xkey.derive_priv(&secp, &path)?.to_priv()
+                }
+                DescriptorSecretKey::MultiXPrv(_) => {
+                    return Err(eyre!("Multipath keys can't sign blocks"));
+                }
+            };
+            let fingerprint = public.master_fingerprint();
+            let path = public
+                .at_derivation_index(0)?
+                .full_derivation_path()
+                .ok_or_else(|| eyre!("{public} has no single derivation path"))?;
+            keys.insert((fingerprint, path), private);
+        }
+
+        Ok(Self {
+            descriptor: descriptor.at_derivation_index(0)?,
+            keys,
+        })
+    }
+
+    /// The challenge this signer can satisfy.
+    pub fn challenge(&self) -> ScriptBuf {
+        self.descriptor.script_pubkey()
+    }
+
+    /// Sign and finalize a PSBT from `generate_psbt`.
+    pub fn sign(&self, psbt: &str) -> Result<String> {
+        let secp = Secp256k1::new();
+        let mut psbt = Psbt::from_str(psbt)?;
+        psbt.update_input_with_descriptor(0, &self.descriptor)
+            .map_err(|err| eyre!("The descriptor isn't for this signet's challenge: {err}"))?;
+        psbt.sign(self, &secp)
+            .map_err(|(_, errors)| eyre!("Couldn't sign the block: {errors:?}"))?;
+        psbt.finalize_mut(&secp)
+            .map_err(|errors| eyre!("Couldn't satisfy the challenge: {errors:?}"))?;
+        Ok(psbt.to_string())
+    }
+}
+
+impl GetKey for BlockSigner {
+    type Error = Infallible;
+
+    fn get_key<C: Signing>(
+        &self,
+        key_request: KeyRequest,
+        secp: &Secp256k1<C>,
+    ) -> Result<Option<PrivateKey>, Self::Error> {
+        Ok(match key_request {
+            KeyRequest::Bip32(source) => self.keys.get(&source).copied(),
+            KeyRequest::Pubkey(public) => self
+                .keys
+                .values()
+                .find(|private| private.public_key(secp) == public)
+                .copied(),
+            _ => None,
+        })
+    }
+}
+
+/// The BIP325 `spend` and `to_spend` transactions for `block`. Signing `spend` for the
+/// `challenge` gives the block's signet solution.
+///
+/// The commitment in the coinbase's last output is left empty, as the solution can't sign itself.
+pub fn signet_txs(block: &Block, challenge: &Script) -> (Transaction, Transaction) {
+    let mut txdata = block.txdata.clone();
+    txdata[0]
+        .output
+        .last_mut()
+        .expect("the coinbase has an output")
+        .script_pubkey
+        .push_slice(push_bytes(&SIGNET_HEADER));
+    let merkle_root =
+        bitcoin::merkle_tree::calculate_root(txdata.iter().map(|tx| tx.compute_txid()))
+            .expect("blocks have a coinbase");
+
+    let mut block_data = Vec::with_capacity(72);
+    block_data.extend(block.header.version.to_consensus().to_le_bytes());
+    block_data.extend(block.header.prev_blockhash.to_byte_array());
+    block_data.extend(merkle_root.to_byte_array());
+    block_data.extend(block.header.time.to_le_bytes());
+
+    let to_spend = Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new()
+                .push_opcode(OP_0)
+                .push_slice(push_bytes(&block_data))
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: challenge.to_owned(),
+        }],
+    };
+
+    let spend = Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    };
+
+    (spend, to_spend)
+}
+
+/// Build a block from `tmpl` paying `reward_spk`, and a base64 PSBT for the signer that carries it.
+pub fn generate_psbt(
+    tmpl: &BlockTemplate,
+    reward_spk: ScriptBuf,
+    blocktime: Option<u32>,
+    poolid: Option<&[u8]>,
+) -> Result<String> {
+    let challenge = ScriptBuf::from_hex(&tmpl.signet_challenge)?;
+
+    let mut script_sig = bip34_height(tmpl.height);
+    if let Some(poolid) = poolid {
+        script_sig = script_sig.push_slice(push_bytes(poolid));
+    }
+    let coinbase = Transaction {
+        version: Version::ONE,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: script_sig.into_script(),
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            witness: Witness::from_slice(&[WITNESS_RESERVED_VALUE]),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(tmpl.coinbasevalue),
+            script_pubkey: reward_spk,
+        }],
+    };
+
+    let mut txdata = vec![coinbase];
+    for tx in &tmpl.transactions {
+        txdata.push(deserialize::<Transaction>(&Vec::<u8>::from_hex(&tx.data)?)?);
+    }
+
+    let mut block = Block {
+        header: Header {
+            version: block::Version::from_consensus(tmpl.version),
+            prev_blockhash: tmpl.previousblockhash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: blocktime.unwrap_or(tmpl.curtime),
+            bits: CompactTarget::from_consensus(u32::from_str_radix(&tmpl.bits, 16)?),
+            nonce: 0,
+        },
+        txdata,
+    };
+
+    let witness_root = block.witness_root().expect("blocks have a coinbase");
+    let commitment = Block::compute_witness_commitment(&witness_root, &WITNESS_RESERVED_VALUE);
+    let commitment = [
+        WITNESS_COMMITMENT_HEADER.as_slice(),
+        commitment.as_byte_array(),
+    ]
+    .concat();
+    block.txdata[0].output.push(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(push_bytes(&commitment))
+            .into_script(),
+    });
+    block.header.merkle_root = block.compute_merkle_root().expect("blocks have a coinbase");
+
+    let (spend, to_spend) = signet_txs(&block, &challenge);
+    let mut psbt = Psbt::from_unsigned_tx(spend)?;
+    psbt.inputs[0].non_witness_utxo = Some(to_spend);
+    psbt.inputs[0].sighash_type = Some(EcdsaSighashType::All.into());
+    psbt.proprietary
+        .insert(signet_block_key(), serialize(&block));
+
+    Ok(psbt.to_string())
+}
+
+/// The block a signed signet PSBT carries, and its signet solution: the serialized scriptSig and
+/// witness stack that spend the challenge.
+pub fn decode_psbt(psbt: &str) -> Result<(Block, Vec<u8>)> {
+    let psbt = Psbt::from_str(psbt.trim())?;
+    if psbt.unsigned_tx.input.len() != 1 || psbt.unsigned_tx.output.len() != 1 {
+        return Err(eyre!("A signet PSBT spends one input to one output"));
+    }
+    let block = psbt
+        .proprietary
+        .get(&signet_block_key())
+        .ok_or_else(|| eyre!("The PSBT doesn't carry a signet block"))?;
+    let block: Block = deserialize(block)?;
+
+    let input = &psbt.inputs[0];
+    let mut solution = serialize(&input.final_script_sig.clone().unwrap_or_default());
+    match &input.final_script_witness {
+        Some(witness) => solution.extend(serialize(witness)),
+        // An empty witness stack
+        None => solution.push(0),
+    }
+
+    Ok((block, solution))
+}
+
+/// Commit to the signet solution in the coinbase and grind the header, with `grind_cmd` if given
+/// and on every core otherwise. Returns `None` if `cancel` stops the grind, and fails with
+/// [`NoncesExhausted`] if no nonce solves the header at its signed time.
+pub fn finish_block(
+    mut block: Block,
+    signet_solution: &[u8],
+    grind_cmd: Option<&str>,
+    cancel: &Cancel,
+) -> Result<Option<Block>> {
+    let commitment = [SIGNET_HEADER.as_slice(), signet_solution].concat();
+    block
+        .txdata
+        .first_mut()
+        .and_then(|coinbase| coinbase.output.last_mut())
+        .ok_or_else(|| eyre!("The block has no coinbase output"))?
+        .script_pubkey
+        .push_slice(push_bytes(&commitment));
+    block.header.merkle_root = block.compute_merkle_root().expect("blocks have a coinbase");
+
+    let solved = match grind_cmd {
+        Some(grind_cmd) => Some(grind(grind_cmd, &block.header)?),
+        // The solution signs the block's time, so only the nonce can change
+        None => grinder::grind(&block.header, false, cancel)?,
+    };
+    Ok(solved.map(|header| {
+        block.header.nonce = header.nonce;
+        block
+    }))
+}
+
+/// Have `grind_cmd` solve `header`. It takes the header's hex as its last argument and prints
+/// the solved header's hex.
+fn grind(grind_cmd: &str, header: &Header) -> Result<Header> {
+    let mut parts = grind_cmd.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| eyre!("The grind command is empty"))?;
+    let output = Command::new(program)
+        .args(parts)
+        .arg(serialize_hex(header))
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{grind_cmd} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let header = Vec::<u8>::from_hex(String::from_utf8(output.stdout)?.trim())?;
+    Ok(deserialize(&header)?)
+}
+
+/// The coinbase scriptSig's BIP34 height. Consensus wants at least 2 bytes, so low heights get
+/// some padding.
+fn bip34_height(height: u32) -> Builder {
+    let builder = Builder::new().push_int(height.into());
+    if height <= 16 {
+        builder.push_opcode(OP_PUSHNUM_1)
+    } else {
+        builder
+    }
+}
+
+/// The global PSBT field the block travels in: proprietary, identifier "signet", subtype 'b'.
+fn signet_block_key() -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: b"signet".to_vec(),
+        subtype: b'b',
+        key: vec![],
+    }
+}
+
+/// `data` as a script push. Only a push over 4GB wouldn't fit.
+fn push_bytes(data: &[u8]) -> &PushBytes {
+    data.try_into().expect("the push is under 4GB")
+}
+
+/// The pool tag to put in the coinbase.
+pub fn get_poolid(args: &MinerArgs) -> Option<Vec<u8>> {
+    match (&args.poolid, args.poolnum) {
+        (Some(poolid), _) => Some(poolid.as_bytes().to_vec()),
+        (None, Some(poolnum)) => Some(format!("/signet:{poolnum}/").into_bytes()),
+        (None, None) => None,
+    }
+}
+
+/// The address and scriptPubKey to pay the block at `height` to. A ranged descriptor pays each
+/// height to its own address.
+pub fn get_reward_addr_spk(
+    node: &dyn Node,
+    args: &mut MinerArgs,
+    height: u32,
+) -> Result<(String, ScriptBuf)> {
+    if let (Some(address), Some(reward_spk)) = (&args.address, &args.reward_spk) {
+        return Ok((address.clone(), reward_spk.clone()));
+    }
+
+    let reward_addr = match (&args.address, &args.descriptor) {
+        (Some(address), _) => address.clone(),
+        (None, Some(descriptor)) if !descriptor.contains('*') => {
+            let address = node
+                .derive_addresses(descriptor, None)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| eyre!("{descriptor} derived no address"))?;
+            args.address = Some(address.clone());
+            address
+        }
+        (None, Some(descriptor)) => {
+            args.derived_addresses
+                .retain(|derived, _| *derived + 20 > height);
+            if !args.derived_addresses.contains_key(&height) {
+                let derived = node.derive_addresses(descriptor, Some((height, height + 20)))?;
+                for (offset, address) in derived.into_iter().enumerate() {
+                    args.derived_addresses
+                        .insert(height + offset as u32, address);
+                }
+            }
+            args.derived_addresses
+                .get(&height)
+                .ok_or_else(|| eyre!("{descriptor} derived no address for height {height}"))?
+                .clone()
+        }
+        (None, None) => return Err(eyre!("Must specify --address or --descriptor")),
+    };
+
+    let info = node.get_address_info(&reward_addr)?;
+    let reward_spk = ScriptBuf::from_hex(&info.script_pubkey)?;
+
+    // A fixed address always pays the same script
+    if args.address.is_some() {
+        args.reward_spk = Some(reward_spk.clone());
+    }
+    Ok((reward_addr, reward_spk))
+}
+
+/// Read a block template from stdin and print the PSBT to sign for it.
+pub fn do_genpsbt(node: &dyn Node, args: &mut MinerArgs) -> Result<()> {
+    let poolid = get_poolid(args);
+    let tmpl: BlockTemplate = serde_json::from_reader(std::io::stdin())?;
+    let (_, reward_spk) = get_reward_addr_spk(node, args, tmpl.height)?;
+    println!(
+        "{}",
+        generate_psbt(&tmpl, reward_spk, None, poolid.as_deref())?
+    );
+    Ok(())
+}
+
+/// Read a signed PSBT from stdin and print the finished block's hex.
+pub fn do_solvepsbt(grind_cmd: Option<&str>) -> Result<()> {
+    let psbt = std::io::read_to_string(std::io::stdin())?;
+    let (block, signet_solution) = decode_psbt(&psbt)?;
+    let block = finish_block(block, &signet_solution, grind_cmd, &Cancel::default())?
+        .ok_or_else(|| eyre!("The grind was cancelled"))?;
+    println!("{}", serialize_hex(&block));
+    Ok(())
+}
+
+pub fn nbits_to_target(nbits: u32) -> f64 {
+    let shift = (nbits >> 24) & 0xff;
+    f64::from(nbits & 0x00ff_ffff) * 2f64.powi(8 * (shift as i32 - 3))
+}
+
+/// The nBits for `target`, rounding the mantissa up so the target is never made harder.
+pub fn target_to_nbits(target: f64) -> u32 {
+    let mut size = (target.log2() / 8.0).floor() as i32 + 1;
+    // A top byte with its high bit set would read as negative
+    if target / 256f64.powi(size - 1) >= 128.0 {
+        size += 1;
+    }
+    let size = size.max(3);
+    let mantissa = (target / 256f64.powi(size - 3)).ceil() as u32;
+    ((size as u32) << 24) | mantissa
+}
+
+pub fn seconds_to_hms(seconds: i64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let mut s = seconds.abs();
+    let mut out = String::new();
+    if s % 60 > 0 {
+        out = format!("{}s", s % 60);
+    }
+    s /= 60;
+    if s % 60 > 0 {
+        out = format!("{}m{out}", s % 60);
+    }
+    s /= 60;
+    if s > 0 {
+        out = format!("{s}h{out}");
+    }
+    if seconds < 0 {
+        out.insert(0, '-');
+    }
+    out
+}
+
+/// When the next block is due and whether it's ours to mine, so several miners can share a
+/// signet with backups for each other.
+pub struct Generate {
+    multi_low: u32,
+    multi_high: u32,
+    multi_period: u32,
+    ultimate_target: f64,
+    poisson: bool,
+    max_interval: f64,
+    standby_delay: i64,
+    backup_delay: i64,
+    set_block_time: Option<i64>,
+    poolid: Option<Vec<u8>>,
+    gbt_retry: Duration,
+
+    pub mine_time: i64,
+    pub action_time: i64,
+    pub is_mine: bool,
+}
+
+impl Generate {
+    /// `multiminer` is the `[low, high)` range of the `period` block slots that are ours.
+    pub fn new(args: &MinerArgs, multiminer: (u32, u32, u32), ultimate_target: f64) -> Self {
+        let (multi_low, multi_high, multi_period) = multiminer;
+        Self {
+            multi_low,
+            multi_high,
+            multi_period,
+            ultimate_target,
+            poisson: args.poisson,
+            max_interval: args.max_interval as f64,
+            standby_delay: args.standby_delay,
+            backup_delay: args.backup_delay,
+            set_block_time: args.set_block_time,
+            poolid: get_poolid(args),
+            gbt_retry: GBT_RETRY,
+            mine_time: 0,
+            action_time: 0,
+            is_mine: false,
+        }
+    }
+
+    /// Seconds from the last block to the next. The average steers the difficulty toward the
+    /// ultimate target, as far as one retarget can, and Poisson mode varies it by the last hash.
+    pub fn next_block_delta(&self, last_nbits: u32, last_hash: &BlockHash) -> f64 {
+        let retarget_factor = (self.ultimate_target / nbits_to_target(last_nbits)).clamp(0.25, 4.0);
+        let avg_interval = INTERVAL * retarget_factor;
+
+        let interval_variance = if self.poisson {
+            let hash = last_hash.to_string();
+            let det_rand = f64::from(hex_u32(&hash[hash.len() - 8..])) * 2f64.powi(-32);
+            -(-det_rand).ln_1p()
+        } else {
+            1.0
+        };
+
+        (avg_interval * interval_variance).clamp(1.0, self.max_interval)
+    }
+
+    pub fn next_block_is_mine(&self, last_hash: &BlockHash) -> bool {
+        let hash = last_hash.to_string();
+        let det_rand = hex_u32(&hash[hash.len() - 16..hash.len() - 8]);
+        (self.multi_low..self.multi_high).contains(&(det_rand % self.multi_period))
+    }
+
+    pub fn next_block_time(&mut self, now: i64, bestheader: &HeaderInfo, is_first_block: bool) {
+        if let Some(set_block_time) = self.set_block_time {
+            debug!("Setting start time to {set_block_time}");
+            self.mine_time = set_block_time;
+            self.action_time = now;
+            self.is_mine = true;
+        } else if bestheader.height == 0 {
+            // Plenty of time to mine 100 blocks
+            let time_delta = (INTERVAL * 100.0) as i64;
+            info!(
+                "Backdating time for first block to {} minutes ago",
+                time_delta / 60
+            );
+            self.mine_time = now - time_delta;
+            self.action_time = now;
+            self.is_mine = true;
+        } else {
+            let delta = self.next_block_delta(hex_u32(&bestheader.bits), &bestheader.hash);
+            self.mine_time = bestheader.time + delta as i64;
+            self.is_mine = self.next_block_is_mine(&bestheader.hash);
+
+            self.action_time = self.mine_time;
+            if !self.is_mine {
+                self.action_time += self.backup_delay;
+            }
+            if self.standby_delay > 0 {
+                self.action_time += self.standby_delay;
+            } else if is_first_block {
+                // Without a standby delay, mine right away on startup even if the block isn't ours
+                self.action_time = now;
+            }
+        }
+
+        self.action_time = self.action_time.max(self.mine_time - MAX_FUTURE);
+    }
+
+    /// A template on top of `bestblockhash`, or `None` to try again later.
+    pub fn gbt(
+        &mut self,
+        node: &dyn Node,
+        bestblockhash: &BlockHash,
+        now: i64,
+    ) -> Result<Option<BlockTemplate>> {
+        let tmpl = node.get_block_template()?;
+        if tmpl.previousblockhash != *bestblockhash {
+            warn!(
+                "GBT based off unexpected block ({} not {bestblockhash}), retrying",
+                tmpl.previousblockhash
+            );
+            sleep(self.gbt_retry);
+            return Ok(None);
+        }
+
+        if tmpl.mintime > self.mine_time {
+            info!(
+                "Updating block time from {} to {}",
+                self.mine_time, tmpl.mintime
+            );
+            self.mine_time = tmpl.mintime;
+            if self.mine_time > now {
+                warn!(
+                    "GBT mintime is in the future: {} is {} seconds later than {now}",
+                    self.mine_time,
+                    self.mine_time - now
+                );
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(tmpl))
+    }
+
+    /// Build the block, have the node's wallet sign it and grind it, or `None` if `cancel` stops
+    /// the grind.
+    pub fn mine(
+        &self,
+        node: &dyn Node,
+        signer: Option<&BlockSigner>,
+        grind_cmd: Option<&str>,
+        cancel: &Cancel,
+        tmpl: &BlockTemplate,
+        reward_spk: ScriptBuf,
+    ) -> Result<Option<Block>> {
+        sign_and_grind(
+            node,
+            signer,
+            self.mine_time as u32,
+            |time| generate_psbt(tmpl, reward_spk.clone(), Some(time), self.poolid.as_deref()),
+            |block, solution| finish_block(block, solution, grind_cmd, cancel),
+        )
+    }
+}
+
+/// Sign the block `psbt_at` builds at `time` and `grind` it. The signet solution signs the time,
+/// so when no nonce solves the block it's built a second later and signed again.
+fn sign_and_grind(
+    node: &dyn Node,
+    signer: Option<&BlockSigner>,
+    mut time: u32,
+    psbt_at: impl Fn(u32) -> Result<String>,
+    grind: impl Fn(Block, &[u8]) -> Result<Option<Block>>,
+) -> Result<Option<Block>> {
+    loop {
+        let signed = sign_block(node, signer, &psbt_at(time)?)?;
+        let (block, signet_solution) = decode_psbt(&signed)?;
+        match grind(block, &signet_solution) {
+            Err(err) if err.is::<NoncesExhausted>() => {
+                time = time
+                    .checked_add(1)
+                    .ok_or_else(|| eyre!("Ran out of times to sign the block at"))?;
+                debug!("Ran out of nonces, signing the block again at {time}");
+            }
+            ground => return ground,
+        }
+    }
+}
+
+/// Have `signer`, or the node's wallet without one, satisfy the challenge for a block's PSBT.
+fn sign_block(node: &dyn Node, signer: Option<&BlockSigner>, psbt: &str) -> Result<String> {
+    if let Some(signer) = signer {
+        return signer.sign(psbt);
+    }
+    let signed = node.wallet_process_psbt(psbt)?;
+    if !signed.complete {
+        debug!("Generated PSBT: {psbt}");
+        return Err(eyre!("The node's wallet couldn't sign the block"));
+    }
+    Ok(signed.psbt)
+}
+
+/// The p2p message start of the signet with `challenge`, in hex, for software that can't work
+/// it out itself.
+pub fn signet_magic(challenge: &ScriptBuf) -> String {
+    let hash = sha256d::Hash::hash(&serialize(challenge));
+    hash.as_byte_array()[..4].to_lower_hex_string()
+}
+
+/// When to stamp a block on `prev` that's mined `now`: [`BLOCK_SPACING`] after it, but no
+/// further ahead of the clock than blocks may be.
+///
+/// The game mines in bursts, so its blocks run ahead of the clock until they reach that limit.
+/// From then on they're stamped with the clock, and the next retarget raises the difficulty, by
+/// at most 4×. The first block is backdated so the funding blocks start off behind the clock.
+fn paced_block_time(prev: &HeaderInfo, mintime: i64, now: i64) -> i64 {
+    let paced = match prev.height {
+        // Plenty of time to mine 100 blocks
+        0 => now - 100 * BLOCK_SPACING,
+        _ => prev.time + BLOCK_SPACING,
+    };
+    paced.min(now + MAX_FUTURE).max(mintime)
+}
+
+/// Mine one block paying `reward_spk` on top of the node's tip, as soon as it's solved, or
+/// `None` if `cancel` stops the grind. Its time is paced off the tip's, not taken from the clock.
+pub fn mine_block(
+    node: &dyn Node,
+    signer: Option<&BlockSigner>,
+    reward_spk: ScriptBuf,
+    cancel: &Cancel,
+) -> Result<Option<BlockHash>> {
+    let tmpl = node.get_block_template()?;
+    let prev = node.get_block_header(&tmpl.previousblockhash)?;
+    let time = paced_block_time(&prev, tmpl.mintime, unix_now());
+    let mined = sign_and_grind(
+        node,
+        signer,
+        time as u32,
+        |time| generate_psbt(&tmpl, reward_spk.clone(), Some(time), None),
+        |block, solution| finish_block(block, solution, None, cancel),
+    )?;
+    let Some(block) = mined else {
+        return Ok(None);
+    };
+    if let Some(reason) = node.submit_block(&block)? {
+        return Err(eyre!("Block {} was rejected: {reason}", tmpl.height));
+    }
+    Ok(Some(block.block_hash()))
+}
+
+/// Mine blocks on schedule, one unless `--ongoing` or `--max-blocks` says otherwise.
+pub fn do_generate(node: &dyn Node, args: &mut MinerArgs) -> Result<()> {
+    let max_blocks = if args.set_block_time.is_some() {
+        Some(1)
+    } else if let Some(max_blocks) = args.max_blocks {
+        if max_blocks < 1 {
+            return Err(eyre!("--max-blocks must specify a positive integer"));
+        }
+        Some(max_blocks)
+    } else if args.ongoing {
+        None
+    } else {
+        Some(1)
+    };
+
+    if args.set_block_time.is_some_and(|time| time < 0) {
+        let time = unix_now();
+        info!("Treating negative block time as current time ({time})");
+        args.set_block_time = Some(time);
+    }
+
+    if args.min_nbits {
+        args.nbits = Some(MIN_NBITS.to_string());
+        info!("Using nbits={MIN_NBITS}");
+    }
+    if args.set_block_time.is_none() && args.nbits.as_ref().is_none_or(|nbits| nbits.len() != 8) {
+        return Err(eyre!(
+            "Must specify --nbits (use calibrate command to determine value)"
+        ));
+    }
+
+    let my_blocks = match &args.multiminer {
+        None => (0, 1, 1),
+        Some(_) if !args.ongoing => {
+            return Err(eyre!("Cannot specify --multiminer without --ongoing"));
+        }
+        Some(multiminer) => parse_multiminer(multiminer)?,
+    };
+
+    if args.max_interval < 960 {
+        return Err(eyre!("--max-interval must be at least 960 (16 minutes)"));
+    }
+
+    // With a set block time, only the delays in the log need it
+    let nbits = hex_u32(args.nbits.as_deref().unwrap_or(MIN_NBITS));
+    let mut generator = Generate::new(args, my_blocks, nbits_to_target(nbits));
+
+    let mut mined_blocks = 0;
+    let mut bestheader: Option<HeaderInfo> = None;
+    let mut lastheader: Option<BlockHash> = None;
+    while max_blocks.is_none_or(|max_blocks| mined_blocks < max_blocks) {
+        let bestblockhash = node.get_best_block_hash()?;
+        let header = match bestheader.take() {
+            Some(header) if header.hash == bestblockhash => header,
+            _ => node.get_block_header(&bestblockhash)?,
+        };
+
+        if lastheader.is_some_and(|last| last != header.hash) {
+            let delta = generator.next_block_delta(hex_u32(&header.bits), &header.hash)
+                + (header.time - unix_now()) as f64;
+            let next_is_mine = generator.next_block_is_mine(&header.hash);
+            info!(
+                "Received new block at height {}; next in {} ({})",
+                header.height,
+                seconds_to_hms(delta as i64),
+                if next_is_mine { "mine" } else { "backup" }
+            );
+        }
+        lastheader = Some(header.hash);
+
+        let now = unix_now();
+        generator.next_block_time(now, &header, mined_blocks == 0);
+
+        if now < generator.action_time {
+            let mut sleep_for = (generator.action_time - now).min(60);
+            // Someone else may have mined the block, so check often to not be late with ours
+            if generator.mine_time < now {
+                sleep_for = sleep_for.min(20);
+            }
+            debug!(
+                "Sleeping for {}, next block due in {} ({})",
+                seconds_to_hms(sleep_for),
+                seconds_to_hms(generator.mine_time - now),
+                if generator.is_mine { "mine" } else { "backup" }
+            );
+            sleep(Duration::from_secs(sleep_for as u64));
+            bestheader = Some(header);
+            continue;
+        }
+
+        let Some(tmpl) = generator.gbt(node, &bestblockhash, now)? else {
+            bestheader = Some(header);
+            continue;
+        };
+        debug!("GBT template: {tmpl:?}");
+
+        let (reward_addr, reward_spk) = get_reward_addr_spk(node, args, tmpl.height)?;
+        debug!(
+            "Mining block delta={} start={} mine={}",
+            seconds_to_hms(generator.mine_time - header.time),
+            generator.mine_time,
+            generator.is_mine
+        );
+        let mined = cancel_on_new_tip(node, &tmpl.previousblockhash, |cancel| {
+            generator.mine(
+                node,
+                args.signer.as_ref(),
+                args.grind_cmd.as_deref(),
+                cancel,
+                &tmpl,
+                reward_spk,
+            )
+        })?;
+        let Some(block) = mined else {
+            info!("New tip while mining block {}, starting over", tmpl.height);
+            continue;
+        };
+        mined_blocks += 1;
+
+        let rejected = node.submit_block(&block)?;
+
+        let hash = block.block_hash();
+        let delta = generator.next_block_delta(block.header.bits.to_consensus(), &hash)
+            + (i64::from(block.header.time) - unix_now()) as f64;
+        debug!("Block hash {hash} payout to {reward_addr}");
+        info!(
+            "Mined {} at height {}; next in {} ({})",
+            if generator.is_mine {
+                "block"
+            } else {
+                "backup block"
+            },
+            tmpl.height,
+            seconds_to_hms(delta as i64),
+            if generator.next_block_is_mine(&hash) {
+                "mine"
+            } else {
+                "backup"
+            }
+        );
+        if let Some(reason) = rejected {
+            warn!(
+                "submitblock returned {reason} for height {} hash {hash}",
+                tmpl.height
+            );
+        }
+        lastheader = Some(hash);
+        bestheader = Some(header);
+    }
+
+    Ok(())
+}
+
+/// Run `work`, cancelling it if the node's tip moves on from `tip`.
+fn cancel_on_new_tip<T>(node: &dyn Node, tip: &BlockHash, work: impl FnOnce(&Cancel) -> T) -> T {
+    let cancel = Cancel::default();
+    let finished = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let watcher = scope.spawn(|| {
+            loop {
+                thread::park_timeout(TIP_POLL);
+                if finished.load(Ordering::Relaxed) {
+                    break;
+                }
+                match node.get_best_block_hash() {
+                    Ok(best) if best != *tip => {
+                        cancel.cancel();
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(err) => debug!("Couldn't check the tip: {err}"),
+                }
+            }
+        });
+        let result = work(&cancel);
+        finished.store(true, Ordering::Relaxed);
+        watcher.thread().unpark();
+        result
+    })
+}
+
+/// Parse `k/m` or `j-k/m` into the `[low, high)` range of the `m` block slots that are ours.
+fn parse_multiminer(multiminer: &str) -> Result<(u32, u32, u32)> {
+    let invalid = || eyre!("--multiminer argument must be k/m or j-k/m");
+    let (slots, total) = multiminer.split_once('/').ok_or_else(invalid)?;
+    let (start, stop) = slots.split_once('-').unwrap_or((slots, slots));
+    let parse = |n: &str| {
+        if n.bytes().all(|b| b.is_ascii_digit()) {
+            n.parse::<u32>().map_err(|_| invalid())
+        } else {
+            Err(invalid())
+        }
+    };
+    let (start, stop, total) = (parse(start)?, parse(stop)?, parse(total)?);
+    if stop < start || start == 0 || total < stop {
+        return Err(eyre!("Inconsistent values for --multiminer"));
+    }
+    Ok((start - 1, stop, total))
+}
+
+/// Time how long grinding takes, with `grind_cmd` if given, and print the nBits that gives
+/// `seconds` per block on average, or how long `nbits` would take.
+pub fn do_calibrate(
+    grind_cmd: Option<&str>,
+    nbits: Option<&str>,
+    seconds: Option<u32>,
+) -> Result<()> {
+    if nbits.is_some() && seconds.is_some() {
+        return Err(eyre!("Can only specify one of --nbits or --seconds"));
+    }
+    if nbits.is_some_and(|nbits| nbits.len() != 8) {
+        return Err(eyre!("Must specify 8 hex digits for --nbits"));
+    }
+
+    // Gets the variance down pretty low
+    const TRIALS: u32 = 600;
+    // About 5 minutes for the 600 trials
+    const TRIAL_BITS: u32 = 0x1e3ea75f;
+
+    let mut header = Header {
+        version: block::Version::from_consensus(4),
+        prev_blockhash: BlockHash::all_zeros(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: 0,
+        bits: CompactTarget::from_consensus(TRIAL_BITS),
+        nonce: 0,
+    };
+    let target = nbits_to_target(TRIAL_BITS);
+
+    let start = Instant::now();
+    for trial in 0..TRIALS {
+        header.time = trial;
+        match grind_cmd {
+            Some(grind_cmd) => grind(grind_cmd, &header)?,
+            None => grinder::grind(&header, true, &Cancel::default())?
+                .ok_or_else(|| eyre!("The grind was cancelled"))?,
+        };
+    }
+    let avg = start.elapsed().as_secs_f64() / f64::from(TRIALS);
+
+    let (want_time, want_target) = match nbits {
+        Some(nbits) => {
+            let want_target = nbits_to_target(u32::from_str_radix(nbits, 16)?);
+            (avg * target / want_target, want_target)
+        }
+        None => {
+            let want_time = f64::from(seconds.unwrap_or(25));
+            (want_time, target * (avg / want_time))
+        }
+    };
+
+    println!(
+        "nbits={:08x} for {}s average mining time",
+        target_to_nbits(want_target),
+        want_time as u64
+    );
+    Ok(())
+}
+
+/// Hex from the node, which is known to be well formed.
+fn hex_u32(hex: &str) -> u32 {
+    u32::from_str_radix(hex, 16).unwrap_or_default()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::bip32::{DerivationPath, Xpriv};
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::consensus::{Decodable, Params};
+    use bitcoin::opcodes::all::OP_CHECKMULTISIG;
+    use bitcoin::script::Instruction;
+    use bitcoin::secp256k1::Message;
+    use bitcoin::sighash::{Prevouts, SighashCache};
+    use bitcoin::{Network, Target, XOnlyPublicKey, ecdsa, taproot};
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    const SIGNET_GENESIS: &str = "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6";
+
+    /// `getblockheader` for the genesis block every signet shares.
+    const GENESIS_HEADER: &str = r#"{"hash":"00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6","confirmations":1,"height":0,"version":1,"versionHex":"00000001","merkleroot":"4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b","time":1598918400,"mediantime":1598918400,"nonce":52613770,"bits":"1e0377ae","difficulty":0.001126515290698186,"chainwork":"000000000000000000000000000000000000000000000000000000000049d414","nTx":1}"#;
+    /// Hand-built in the shape of `getblocktemplate`, for a signet whose challenge is OP_TRUE so any
+    /// block is signed. Its bits are regtest's, which half of all hashes meet.
+    const TEMPLATE: &str = r#"{"capabilities":["proposal"],"version":536870912,"rules":["csv","!signet","!segwit","taproot"],"vbavailable":{},"vbrequired":0,"previousblockhash":"00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6","transactions":[],"coinbaseaux":{},"coinbasevalue":5000000000,"longpollid":"00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef60","target":"7fffff0000000000000000000000000000000000000000000000000000000000","mintime":1598918401,"mutable":["time","transactions","prevblock"],"noncerange":"00000000ffffffff","sigoplimit":80000,"sizelimit":4000000,"weightlimit":4000000,"curtime":1598919000,"bits":"207fffff","height":1,"signet_challenge":"51"}"#;
+    const ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    const ADDRESS_INFO: &str = r#"{"address":"tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx","scriptPubKey":"0014751e76e8199196d454941c45d1b3a323f1433bd6","ismine":false,"solvable":false,"iswatchonly":false,"isscript":false,"iswitness":true,"witness_version":0,"witness_program":"751e76e8199196d454941c45d1b3a323f1433bd6","ischange":false,"labels":[]}"#;
+
+    /// Plays back recorded responses, one per call, and keeps the blocks it's sent.
+    #[derive(Default)]
+    struct ScriptedNode {
+        responses: Mutex<BTreeMap<&'static str, VecDeque<Value>>>,
+        submitted: Mutex<Vec<Block>>,
+    }
+
+    impl ScriptedNode {
+        fn respond(self, method: &'static str, response: &str) -> Self {
+            self.responses
+                .lock()
+                .unwrap()
+                .entry(method)
+                .or_default()
+                .push_back(serde_json::from_str(response).unwrap());
+            self
+        }
+
+        fn next<T: DeserializeOwned>(&self, method: &str) -> Result<T> {
+            let response = self
+                .responses
+                .lock()
+                .unwrap()
+                .get_mut(method)
+                .and_then(VecDeque::pop_front)
+                .ok_or_else(|| eyre!("No response left for {method}"))?;
+            Ok(serde_json::from_value(response)?)
+        }
+
+        fn unused(&self) -> usize {
+            self.responses
+                .lock()
+                .unwrap()
+                .values()
+                .map(VecDeque::len)
+                .sum()
+        }
+    }
+
+    impl Node for ScriptedNode {
+        fn get_block_template(&self) -> Result<BlockTemplate> {
+            self.next("getblocktemplate")
+        }
+
+        fn get_best_block_hash(&self) -> Result<BlockHash> {
+            self.next("getbestblockhash")
+        }
+
+        fn get_block_header(&self, _hash: &BlockHash) -> Result<HeaderInfo> {
+            self.next("getblockheader")
+        }
+
+        /// The OP_TRUE challenge needs no signature, so a complete PSBT is the one it was given.
+        fn wallet_process_psbt(&self, psbt: &str) -> Result<ProcessedPsbt> {
+            Ok(ProcessedPsbt {
+                psbt: psbt.to_string(),
+                complete: self.next("walletprocesspsbt")?,
+            })
+        }
+
+        fn submit_block(&self, block: &Block) -> Result<Option<String>> {
+            self.submitted.lock().unwrap().push(block.clone());
+            self.next("submitblock")
+        }
+
+        fn derive_addresses(
+            &self,
+            _descriptor: &str,
+            _range: Option<(u32, u32)>,
+        ) -> Result<Vec<String>> {
+            self.next("deriveaddresses")
+        }
+
+        fn get_address_info(&self, _address: &str) -> Result<AddressInfo> {
+            self.next("getaddressinfo")
+        }
+    }
+
+    fn miner_args() -> MinerArgs {
+        MinerArgs {
+            address: Some(ADDRESS.to_string()),
+            descriptor: None,
+            derived_addresses: BTreeMap::new(),
+            reward_spk: None,
+            poolid: None,
+            poolnum: None,
+            nbits: None,
+            min_nbits: false,
+            set_block_time: Some(1_598_919_000),
+            max_blocks: Some(1),
+            ongoing: false,
+            multiminer: None,
+            max_interval: 1800,
+            poisson: false,
+            standby_delay: 0,
+            backup_delay: 300,
+            grind_cmd: None,
+            signer: None,
+        }
+    }
+
+    /// The master key of the public "abandon ... about" mnemonic, a key only tests sign with.
+    const TEST_XPRIV: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    const TEST_KEY_PATH: &str = "86h/1h/0h/0/0";
+
+    fn test_key() -> PrivateKey {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str(&format!("m/{TEST_KEY_PATH}")).unwrap();
+        Xpriv::from_str(TEST_XPRIV)
+            .unwrap()
+            .derive_priv(&secp, &path)
+            .unwrap()
+            .to_priv()
+    }
+
+    /// A block's PSBT for the signer's challenge, signed, and the txs it spends and is spent by.
+    fn sign_for(signer: &BlockSigner) -> (Psbt, Transaction, Transaction) {
+        let tmpl = template(&signer.challenge().to_hex_string());
+        let psbt = generate_psbt(&tmpl, ScriptBuf::new(), None, None).unwrap();
+        let signed = Psbt::from_str(&signer.sign(&psbt).unwrap()).unwrap();
+        let to_spend = signed.inputs[0].non_witness_utxo.clone().unwrap();
+        let spend = signed.unsigned_tx.clone();
+        (signed, spend, to_spend)
+    }
+
+    /// The default signet's 1-of-2 multisig challenge.
+    const SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+    /// Take the solution out of the signet commitment, leaving just its header, the way Core
+    /// does before it checks a block's solution.
+    fn clear_commitment(block: &mut Block) -> Vec<u8> {
+        let commitment = &mut block.txdata[0].output.last_mut().unwrap().script_pubkey;
+        let mut cleared = Builder::new();
+        let mut solution = None;
+        for instruction in commitment.instructions() {
+            cleared = match instruction.unwrap() {
+                Instruction::PushBytes(data)
+                    if solution.is_none()
+                        && data.len() > SIGNET_HEADER.len()
+                        && data.as_bytes().starts_with(&SIGNET_HEADER) =>
+                {
+                    solution = Some(data.as_bytes()[SIGNET_HEADER.len()..].to_vec());
+                    cleared.push_slice(push_bytes(&SIGNET_HEADER))
+                }
+                Instruction::PushBytes(data) => cleared.push_slice(data),
+                Instruction::Op(op) => cleared.push_opcode(op),
+            };
+        }
+        *commitment = cleared.into_script();
+        solution.expect("the block has a signet commitment")
+    }
+
+    fn template(signet_challenge: &str) -> BlockTemplate {
+        BlockTemplate {
+            version: 0x2000_0000,
+            previousblockhash: genesis_block(Network::Signet).block_hash(),
+            bits: "207fffff".to_string(),
+            curtime: 1_598_919_000,
+            mintime: 1_598_918_401,
+            coinbasevalue: 5_000_000_000,
+            height: 1,
+            transactions: vec![],
+            signet_challenge: signet_challenge.to_string(),
+        }
+    }
+
+    #[test]
+    fn signet_txs_for_the_signet_genesis_block() {
+        let challenge = ScriptBuf::from_hex(SIGNET_CHALLENGE).unwrap();
+        let (spend, to_spend) = signet_txs(&genesis_block(Network::Signet), &challenge);
+
+        // Worked out by hand from BIP325 for the genesis coinbase with an empty commitment
+        assert_eq!(
+            to_spend.compute_txid().to_string(),
+            "f614199e239428194068a1cea07ad871f4d6001362cdca8e57a846296461334e"
+        );
+        assert_eq!(
+            spend.compute_txid().to_string(),
+            "efad60618e2b35cad81ca3144d40b48ec6a2442be0a8ff0d5c5dab88842fb933"
+        );
+        assert_eq!(to_spend.output[0].script_pubkey, challenge);
+        assert_eq!(spend.input[0].previous_output.txid, to_spend.compute_txid());
+    }
+
+    #[test]
+    fn finished_block_commits_to_its_solution() {
+        let reward_spk = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+        let psbt = generate_psbt(&template("51"), reward_spk, None, None).unwrap();
+
+        // Sign as a signer would, with a witness the anyone-can-spend challenge doesn't need
+        let mut psbt = Psbt::from_str(&psbt).unwrap();
+        let witness = Witness::from_slice(&[[0x01; 64]]);
+        psbt.inputs[0].final_script_witness = Some(witness.clone());
+        let to_spend = psbt.inputs[0].non_witness_utxo.clone().unwrap();
+        assert_eq!(
+            psbt.unsigned_tx.input[0].previous_output,
+            OutPoint::new(to_spend.compute_txid(), 0)
+        );
+
+        let (block, solution) = decode_psbt(&psbt.to_string()).unwrap();
+        let mut block = finish_block(block, &solution, None, &Cancel::default())
+            .unwrap()
+            .unwrap();
+        assert!(block.check_merkle_root());
+        assert!(block.check_witness_commitment());
+        assert!(block.header.validate_pow(block.header.target()).is_ok());
+
+        // Core finds the same solution in the block
+        assert_eq!(clear_commitment(&mut block), solution);
+        let mut solution = solution.as_slice();
+        assert!(
+            ScriptBuf::consensus_decode(&mut solution)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(Witness::consensus_decode(&mut solution).unwrap(), witness);
+
+        // and has the solution sign over the same header and merkle root
+        let script_sig = to_spend.input[0].script_sig.as_bytes();
+        assert_eq!(&script_sig[..2], &[0x00, 72]);
+        let block_data = &script_sig[2..];
+        let merkle_root = block.compute_merkle_root().unwrap();
+        assert_eq!(
+            &block_data[..4],
+            &block.header.version.to_consensus().to_le_bytes()
+        );
+        assert_eq!(
+            &block_data[4..36],
+            &block.header.prev_blockhash.to_byte_array()
+        );
+        assert_eq!(&block_data[36..68], &merkle_root.to_byte_array());
+        assert_eq!(&block_data[68..], &block.header.time.to_le_bytes());
+    }
+
+    /// Checks a block a real signet signed: Core's signature in the block's solution has to be
+    /// over the `spend` transaction `signet_txs` rebuilds from the block, or it won't verify.
+    /// No signet block is recorded in the repo, so it takes one from a node:
+    ///
+    /// ```text
+    /// SIGNET_BLOCK=$(bitcoin-cli -signet getblock $(bitcoin-cli -signet getblockhash 1) 0) \
+    ///     cargo test real_signet_block -- --ignored
+    /// ```
+    #[test]
+    #[ignore = "needs a default signet block in SIGNET_BLOCK"]
+    fn real_signet_block_solution_verifies() {
+        let hex = std::env::var("SIGNET_BLOCK").expect("SIGNET_BLOCK holds a block's hex");
+        let mut block: Block = deserialize(&Vec::<u8>::from_hex(hex.trim()).unwrap()).unwrap();
+        let challenge = ScriptBuf::from_hex(SIGNET_CHALLENGE).unwrap();
+
+        let solution = clear_commitment(&mut block);
+        let mut solution = solution.as_slice();
+        let script_sig = ScriptBuf::consensus_decode(&mut solution).unwrap();
+        assert!(Witness::consensus_decode(&mut solution).unwrap().is_empty());
+        assert!(solution.is_empty());
+
+        // `signet_txs` adds the commitment header back itself
+        let commitment = &mut block.txdata[0].output.last_mut().unwrap().script_pubkey;
+        let mut instructions: Vec<_> = commitment.instructions().map(Result::unwrap).collect();
+        assert_eq!(
+            instructions.pop(),
+            Some(Instruction::PushBytes(push_bytes(&SIGNET_HEADER)))
+        );
+        *commitment = instructions
+            .into_iter()
+            .fold(Builder::new(), |script, instruction| match instruction {
+                Instruction::PushBytes(data) => script.push_slice(data),
+                Instruction::Op(op) => script.push_opcode(op),
+            })
+            .into_script();
+        let (spend, to_spend) = signet_txs(&block, &challenge);
+        assert_eq!(spend.input[0].previous_output.txid, to_spend.compute_txid());
+
+        // The 1-of-2 multisig is satisfied by OP_0 <signature>
+        let signature = match script_sig.instructions().nth(1) {
+            Some(Ok(Instruction::PushBytes(data))) => {
+                ecdsa::Signature::from_slice(data.as_bytes()).unwrap()
+            }
+            other => panic!("unexpected solution {other:?}"),
+        };
+        let sighash = SighashCache::new(&spend)
+            .legacy_signature_hash(0, &challenge, signature.sighash_type.to_u32())
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        let secp = Secp256k1::verification_only();
+        let signed_by_a_challenge_key =
+            challenge
+                .instructions()
+                .any(|instruction| match instruction.unwrap() {
+                    Instruction::PushBytes(data) => bitcoin::PublicKey::from_slice(data.as_bytes())
+                        .is_ok_and(|key| {
+                            secp.verify_ecdsa(&message, &signature.signature, &key.inner)
+                                .is_ok()
+                        }),
+                    Instruction::Op(_) => false,
+                });
+        assert!(signed_by_a_challenge_key);
+    }
+
+    #[test]
+    fn nbits_round_trip() {
+        for nbits in [0x1e0377ae, 0x1e3ea75f, 0x207fffff, 0x1d00ffff] {
+            assert_eq!(target_to_nbits(nbits_to_target(nbits)), nbits);
+        }
+    }
+
+    #[test]
+    fn parses_multiminer_slots() {
+        assert_eq!(parse_multiminer("1/3").unwrap(), (0, 1, 3));
+        assert_eq!(parse_multiminer("2-3/5").unwrap(), (1, 3, 5));
+        assert!(parse_multiminer("0/3").is_err());
+        assert!(parse_multiminer("3-2/5").is_err());
+        assert!(parse_multiminer("+1/3").is_err());
+    }
+
+    #[test]
+    fn gbt_waits_for_the_template_to_catch_up() {
+        let node = ScriptedNode::default()
+            .respond("getblocktemplate", TEMPLATE)
+            .respond("getblocktemplate", TEMPLATE)
+            .respond("getblocktemplate", TEMPLATE);
+        let genesis = BlockHash::from_str(SIGNET_GENESIS).unwrap();
+        let mut generator = Generate::new(&miner_args(), (0, 1, 1), 1.0);
+        generator.gbt_retry = Duration::ZERO;
+
+        // Built on some other tip
+        let other_tip = BlockHash::all_zeros();
+        assert!(
+            generator
+                .gbt(&node, &other_tip, 1_598_919_000)
+                .unwrap()
+                .is_none()
+        );
+
+        // Due before the template's mintime, which is still in the future
+        generator.mine_time = 1_598_918_000;
+        assert!(
+            generator
+                .gbt(&node, &genesis, 1_598_918_100)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(generator.mine_time, 1_598_918_401);
+
+        generator.mine_time = 1_598_918_000;
+        let tmpl = generator
+            .gbt(&node, &genesis, 1_598_919_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tmpl.height, 1);
+        assert_eq!(generator.mine_time, 1_598_918_401);
+        assert_eq!(node.unused(), 0);
+    }
+
+    #[test]
+    fn mine_builds_the_block_at_the_mine_time() {
+        let node = ScriptedNode::default().respond("walletprocesspsbt", "true");
+        let tmpl: BlockTemplate = serde_json::from_str(TEMPLATE).unwrap();
+        let mut generator = Generate::new(&miner_args(), (0, 1, 1), 1.0);
+        generator.mine_time = 1_598_918_500;
+        let reward_spk =
+            ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+
+        let block = generator
+            .mine(
+                &node,
+                None,
+                None,
+                &Cancel::default(),
+                &tmpl,
+                reward_spk.clone(),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.header.time, 1_598_918_500);
+        assert_eq!(block.header.prev_blockhash.to_string(), SIGNET_GENESIS);
+        assert_eq!(block.txdata[0].output[0].script_pubkey, reward_spk);
+        assert_eq!(
+            block.txdata[0].output[0].value,
+            Amount::from_sat(5_000_000_000)
+        );
+        assert!(block.check_merkle_root());
+        assert!(block.check_witness_commitment());
+        assert!(block.header.validate_pow(block.header.target()).is_ok());
+    }
+
+    #[test]
+    fn mine_fails_when_the_wallet_cant_sign() {
+        let node = ScriptedNode::default().respond("walletprocesspsbt", "false");
+        let tmpl: BlockTemplate = serde_json::from_str(TEMPLATE).unwrap();
+        let generator = Generate::new(&miner_args(), (0, 1, 1), 1.0);
+
+        let mined = generator.mine(
+            &node,
+            None,
+            None,
+            &Cancel::default(),
+            &tmpl,
+            ScriptBuf::new(),
+        );
+        assert!(mined.is_err());
+    }
+
+    #[test]
+    fn generate_mines_and_submits_a_block() {
+        let node = ScriptedNode::default()
+            .respond("getbestblockhash", &format!("{SIGNET_GENESIS:?}"))
+            .respond("getblockheader", GENESIS_HEADER)
+            .respond("getblocktemplate", TEMPLATE)
+            .respond("getaddressinfo", ADDRESS_INFO)
+            .respond("walletprocesspsbt", "true")
+            .respond("submitblock", "null");
+
+        do_generate(&node, &mut miner_args()).unwrap();
+
+        let submitted = node.submitted.lock().unwrap();
+        assert_eq!(submitted.len(), 1);
+        let block = &submitted[0];
+        assert_eq!(block.header.time, 1_598_919_000);
+        assert_eq!(block.header.prev_blockhash.to_string(), SIGNET_GENESIS);
+        assert_eq!(
+            block.txdata[0].output[0].script_pubkey.to_hex_string(),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+        assert_eq!(node.unused(), 0);
+    }
+
+    #[test]
+    fn signer_satisfies_a_bare_multisig_challenge() {
+        let secp = Secp256k1::new();
+        let signer = BlockSigner::new(&format!("multi(1,{TEST_XPRIV}/{TEST_KEY_PATH})")).unwrap();
+        let public_key = test_key().public_key(&secp);
+        // The kind of challenge the game's private signet runs with
+        let challenge = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_key(&public_key)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(signer.challenge(), challenge);
+
+        let (signed, spend, _) = sign_for(&signer);
+        let script_sig = signed.inputs[0].final_script_sig.clone().unwrap();
+        let pushes: Vec<_> = script_sig.instructions().map(Result::unwrap).collect();
+        // CHECKMULTISIG pops an extra item, so the signature comes after an empty push
+        assert_eq!(pushes.len(), 2);
+        let Instruction::PushBytes(signature) = pushes[1] else {
+            panic!("The scriptSig doesn't push a signature");
+        };
+        let signature = ecdsa::Signature::from_slice(signature.as_bytes()).unwrap();
+        let sighash = SighashCache::new(&spend)
+            .legacy_signature_hash(0, &challenge, signature.sighash_type.to_u32())
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(
+            secp.verify_ecdsa(&message, &signature.signature, &public_key.inner)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn signer_satisfies_a_taproot_challenge() {
+        let secp = Secp256k1::new();
+        let signer = BlockSigner::new(&format!("tr({TEST_XPRIV}/{TEST_KEY_PATH})")).unwrap();
+        let challenge = signer.challenge();
+        assert!(challenge.is_p2tr());
+
+        let (signed, spend, to_spend) = sign_for(&signer);
+        let witness = signed.inputs[0].final_script_witness.clone().unwrap();
+        // A key path spend is just the signature
+        assert_eq!(witness.len(), 1);
+        let signature = taproot::Signature::from_slice(&witness[0]).unwrap();
+        let sighash = SighashCache::new(&spend)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[&to_spend.output[0]]),
+                signature.sighash_type,
+            )
+            .unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&challenge.as_bytes()[2..]).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(
+            secp.verify_schnorr(&signature.signature, &message, &output_key)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn signer_refuses_another_challenge() {
+        let signer = BlockSigner::new(&format!("multi(1,{TEST_XPRIV}/{TEST_KEY_PATH})")).unwrap();
+        let psbt = generate_psbt(&template("51"), ScriptBuf::new(), None, None).unwrap();
+        assert!(signer.sign(&psbt).is_err());
+    }
+
+    #[test]
+    fn mine_block_signs_without_the_wallet() {
+        let signer = BlockSigner::new(&format!("multi(1,{TEST_XPRIV}/{TEST_KEY_PATH})")).unwrap();
+        let challenge = signer.challenge().to_hex_string();
+        let tmpl = TEMPLATE.replace(
+            r#""signet_challenge":"51""#,
+            &format!(r#""signet_challenge":"{challenge}""#),
+        );
+        // No walletprocesspsbt response, so asking the wallet fails
+        let node = ScriptedNode::default()
+            .respond("getblocktemplate", &tmpl)
+            .respond("getblockheader", GENESIS_HEADER)
+            .respond("submitblock", "null");
+
+        let hash = mine_block(&node, Some(&signer), ScriptBuf::new(), &Cancel::default())
+            .unwrap()
+            .unwrap();
+
+        let mut submitted = node.submitted.lock().unwrap();
+        assert_eq!(submitted.len(), 1);
+        assert_eq!(submitted[0].block_hash(), hash);
+        let solution = clear_commitment(&mut submitted[0]);
+        let script_sig = ScriptBuf::consensus_decode(&mut solution.as_slice()).unwrap();
+        assert!(!script_sig.is_empty());
+        assert_eq!(node.unused(), 0);
+    }
+
+    #[test]
+    fn cancelled_mine_block_submits_nothing() {
+        let signer = BlockSigner::new(&format!("multi(1,{TEST_XPRIV}/{TEST_KEY_PATH})")).unwrap();
+        let challenge = signer.challenge().to_hex_string();
+        let tmpl = TEMPLATE.replace(
+            r#""signet_challenge":"51""#,
+            &format!(r#""signet_challenge":"{challenge}""#),
+        );
+        let node = ScriptedNode::default()
+            .respond("getblocktemplate", &tmpl)
+            .respond("getblockheader", GENESIS_HEADER);
+        let cancel = Cancel::default();
+        cancel.cancel();
+
+        let mined = mine_block(&node, Some(&signer), ScriptBuf::new(), &cancel).unwrap();
+        assert!(mined.is_none());
+        assert!(node.submitted.lock().unwrap().is_empty());
+        assert_eq!(node.unused(), 0);
+    }
+
+    #[test]
+    fn exhausted_nonces_sign_the_block_again_a_second_later() {
+        let signer = BlockSigner::new(&format!("multi(1,{TEST_XPRIV}/{TEST_KEY_PATH})")).unwrap();
+        let tmpl = template(&signer.challenge().to_hex_string());
+        let solutions = Mutex::new(vec![]);
+
+        let block = sign_and_grind(
+            &ScriptedNode::default(),
+            Some(&signer),
+            1_598_919_000,
+            |time| generate_psbt(&tmpl, ScriptBuf::new(), Some(time), None),
+            |block, solution| {
+                let mut solutions = solutions.lock().unwrap();
+                solutions.push(solution.to_vec());
+                match solutions.len() {
+                    1 => Err(NoncesExhausted(block.header.time).into()),
+                    _ => Ok(Some(block)),
+                }
+            },
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(block.header.time, 1_598_919_001);
+        // The solution signs the time, so the second block has a signature of its own
+        let solutions = solutions.into_inner().unwrap();
+        assert_eq!(solutions.len(), 2);
+        assert_ne!(solutions[0], solutions[1]);
+    }
+
+    fn header_at(height: u32, time: i64) -> HeaderInfo {
+        HeaderInfo {
+            hash: BlockHash::all_zeros(),
+            height,
+            bits: MIN_NBITS.to_string(),
+            time,
+        }
+    }
+
+    #[test]
+    fn game_blocks_are_paced_off_the_tip() {
+        let now = 1_700_000_000;
+        // The first block is backdated, and the rest follow the tip
+        let genesis = header_at(0, 1_598_918_400);
+        assert_eq!(
+            paced_block_time(&genesis, 1_598_918_401, now),
+            now - 100 * BLOCK_SPACING
+        );
+        let tip = header_at(5, now - 3000);
+        assert_eq!(
+            paced_block_time(&tip, now - 5000, now),
+            now - 3000 + BLOCK_SPACING
+        );
+        // Never too far ahead of the clock, nor before the median time past
+        let ahead = header_at(5, now + MAX_FUTURE);
+        assert_eq!(paced_block_time(&ahead, now, now), now + MAX_FUTURE);
+        assert_eq!(
+            paced_block_time(&ahead, now + MAX_FUTURE + 1, now),
+            now + MAX_FUTURE + 1
+        );
+    }
+
+    #[test]
+    fn retargets_stay_at_the_minimum_until_blocks_catch_up_with_the_clock() {
+        let min = CompactTarget::from_consensus(hex_u32(MIN_NBITS));
+        // The retarget at the end of a period of blocks mined at once, starting from `tip`
+        let retarget = |tip: HeaderInfo, now: i64| {
+            let mut prev = tip;
+            let mut times = vec![];
+            for _ in 0..2016 {
+                let time = paced_block_time(&prev, prev.time - 3000, now);
+                times.push(time);
+                prev = header_at(prev.height + 1, time);
+            }
+            let timespan = times[2015] - times[0];
+            CompactTarget::from_next_work_required(min, timespan as u64, &Params::SIGNET)
+        };
+        let now = 1_700_000_000;
+
+        // Far enough behind the clock, blocks keep their spacing
+        let behind = header_at(2015, now - 2016 * BLOCK_SPACING - MAX_FUTURE);
+        assert_eq!(retarget(behind, now), min);
+
+        // Already at the limit, they're all stamped alike and the difficulty quadruples
+        let ahead = header_at(2015, now + MAX_FUTURE);
+        let difficulty = |bits| Target::from_compact(bits).difficulty_float();
+        let raised = difficulty(retarget(ahead, now)) / difficulty(min);
+        assert!((raised - 4.0).abs() < 0.001, "{raised}");
+    }
+}
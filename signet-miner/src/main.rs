@@ -0,0 +1,244 @@
+//! The signet miner on its own, for running the game's signet without the game or Python.
+//! The subcommands and flags follow Bitcoin Core's `contrib/signet/miner`.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use eyre::{Result, eyre};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use signet_miner::rpc_client::RpcClient;
+use signet_miner::{BlockSigner, MinerArgs, RpcNode};
+
+#[derive(Parser)]
+#[command(about = "Mine blocks for a signet")]
+struct Cli {
+    #[command(flatten)]
+    rpc: Rpc,
+    /// Log more
+    #[arg(long, conflicts_with = "quiet")]
+    debug: bool,
+    /// Only log warnings and errors
+    #[arg(long)]
+    quiet: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Turn a block template on stdin into a PSBT for the signer
+    Genpsbt {
+        #[command(flatten)]
+        reward: Reward,
+    },
+    /// Turn a signed PSBT on stdin into a block
+    Solvepsbt {
+        #[command(flatten)]
+        grind: Grind,
+    },
+    /// Mine blocks
+    Generate {
+        #[command(flatten)]
+        reward: Reward,
+        #[command(flatten)]
+        grind: Grind,
+        /// Difficulty to aim for, as 8 hex digits (see calibrate)
+        #[arg(long)]
+        nbits: Option<String>,
+        /// Aim for the lowest difficulty a signet allows
+        #[arg(long, conflicts_with = "nbits")]
+        min_nbits: bool,
+        /// Use this block time instead of the current time; negative means now
+        #[arg(long, allow_negative_numbers = true)]
+        set_block_time: Option<i64>,
+        /// Stop after this many blocks
+        #[arg(long)]
+        max_blocks: Option<u32>,
+        /// Keep mining blocks
+        #[arg(long)]
+        ongoing: bool,
+        /// Mine the k-th (or j-th to k-th) of every m blocks, as k/m or j-k/m
+        #[arg(long)]
+        multiminer: Option<String>,
+        /// Longest time between blocks, in seconds
+        #[arg(long, default_value_t = 1800)]
+        max_interval: i64,
+        /// Space blocks out like real mining does
+        #[arg(long)]
+        poisson: bool,
+        /// Seconds to wait before mining, to be a standby miner
+        #[arg(long, default_value_t = 0)]
+        standby_delay: i64,
+        /// Seconds to wait before mining a block that isn't ours
+        #[arg(long, default_value_t = 300)]
+        backup_delay: i64,
+        /// Sign blocks with this descriptor's private keys instead of the node's wallet
+        #[arg(long, value_name = "DESCRIPTOR")]
+        block_signer: Option<String>,
+    },
+    /// Work out the nbits that gives blocks every few seconds on this machine
+    Calibrate {
+        #[command(flatten)]
+        grind: Grind,
+        /// Report the average time for this nbits instead
+        #[arg(long)]
+        nbits: Option<String>,
+        /// Average seconds per block to aim for [default: 25]
+        #[arg(long, conflicts_with = "nbits")]
+        seconds: Option<u32>,
+    },
+}
+
+/// How to reach bitcoind.
+#[derive(Args)]
+struct Rpc {
+    /// bitcoind's RPC address
+    #[arg(long, default_value = "http://127.0.0.1:38332")]
+    rpc_url: String,
+    /// bitcoind's `.cookie` file to log in with
+    #[arg(long, conflicts_with = "rpc_user")]
+    rpc_cookie: Option<PathBuf>,
+    /// RPC user to log in as, instead of the cookie
+    #[arg(long, requires = "rpc_password")]
+    rpc_user: Option<String>,
+    #[arg(long)]
+    rpc_password: Option<String>,
+    /// Wallet to sign blocks with, when bitcoind has more than one loaded and there's no
+    /// --block-signer
+    #[arg(long)]
+    rpc_wallet: Option<String>,
+}
+
+impl Rpc {
+    fn connect(&self) -> Result<RpcNode> {
+        let rpc = match (&self.rpc_cookie, &self.rpc_user, &self.rpc_password) {
+            (Some(cookie), _, _) => RpcClient::with_cookie(&self.rpc_url, cookie)?,
+            (None, Some(user), Some(password)) => RpcClient::new(&self.rpc_url, user, password),
+            _ => return Err(eyre!("Give --rpc-cookie or --rpc-user and --rpc-password")),
+        };
+        let wallet = self.rpc_wallet.as_deref().map(|name| rpc.for_wallet(name));
+        Ok(RpcNode { rpc, wallet })
+    }
+}
+
+/// Where the block rewards go.
+#[derive(Args)]
+struct Reward {
+    /// Address to pay the rewards to
+    #[arg(long)]
+    address: Option<String>,
+    /// Descriptor to pay the rewards to, a new address each block if ranged
+    #[arg(long, conflicts_with = "address")]
+    descriptor: Option<String>,
+    /// Identify as pool number n in the coinbase
+    #[arg(long)]
+    poolnum: Option<u32>,
+    /// Identify as this pool in the coinbase
+    #[arg(long, conflicts_with = "poolnum")]
+    poolid: Option<String>,
+}
+
+#[derive(Args)]
+struct Grind {
+    /// Command that grinds a header given as hex, instead of grinding on every core
+    #[arg(long)]
+    grind_cmd: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let level = match (cli.debug, cli.quiet) {
+        (true, _) => LevelFilter::Debug,
+        (_, true) => LevelFilter::Warn,
+        _ => LevelFilter::Info,
+    };
+    log::set_logger(&StderrLogger)?;
+    log::set_max_level(level);
+
+    match cli.command {
+        Command::Genpsbt { reward } => {
+            signet_miner::do_genpsbt(&cli.rpc.connect()?, &mut miner_args(reward))
+        }
+        Command::Solvepsbt { grind } => signet_miner::do_solvepsbt(grind.grind_cmd.as_deref()),
+        Command::Generate {
+            reward,
+            grind,
+            nbits,
+            min_nbits,
+            set_block_time,
+            max_blocks,
+            ongoing,
+            multiminer,
+            max_interval,
+            poisson,
+            standby_delay,
+            backup_delay,
+            block_signer,
+        } => {
+            let mut args = MinerArgs {
+                nbits,
+                min_nbits,
+                set_block_time,
+                max_blocks,
+                ongoing,
+                multiminer,
+                max_interval,
+                poisson,
+                standby_delay,
+                backup_delay,
+                grind_cmd: grind.grind_cmd,
+                signer: block_signer.as_deref().map(BlockSigner::new).transpose()?,
+                ..miner_args(reward)
+            };
+            signet_miner::do_generate(&cli.rpc.connect()?, &mut args)
+        }
+        Command::Calibrate {
+            grind,
+            nbits,
+            seconds,
+        } => signet_miner::do_calibrate(grind.grind_cmd.as_deref(), nbits.as_deref(), seconds),
+    }
+}
+
+/// The miner's args with the reward set and everything else at the Python miner's defaults.
+fn miner_args(reward: Reward) -> MinerArgs {
+    MinerArgs {
+        address: reward.address,
+        descriptor: reward.descriptor,
+        derived_addresses: BTreeMap::new(),
+        reward_spk: None,
+        poolid: reward.poolid,
+        poolnum: reward.poolnum,
+        nbits: None,
+        min_nbits: false,
+        set_block_time: None,
+        max_blocks: None,
+        ongoing: false,
+        multiminer: None,
+        max_interval: 1800,
+        poisson: false,
+        standby_delay: 0,
+        backup_delay: 300,
+        grind_cmd: None,
+        signer: None,
+    }
+}
+
+/// Logs to stderr like the Python miner, which the game's logging isn't around to do.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
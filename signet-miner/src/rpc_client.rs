@@ -0,0 +1,501 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{thread, time::Duration};
+
+use log::debug;
+use reqwest::blocking::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use thiserror::Error;
+
+/// Error codes returned by bitcoind, see `src/rpc/protocol.h` in Bitcoin Core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcErrorCode {
+    MiscError,
+    TypeError,
+    WalletError,
+    InvalidAddressOrKey,
+    InvalidParameter,
+    VerifyRejected,
+    VerifyAlreadyInChain,
+    InWarmup,
+    WalletNotFound,
+    WalletAlreadyLoaded,
+    WalletAlreadyExists,
+    MethodNotFound,
+    Other(i64),
+}
+
+impl From<i64> for RpcErrorCode {
+    fn from(code: i64) -> Self {
+        match code {
+            -1 => RpcErrorCode::MiscError,
+            -3 => RpcErrorCode::TypeError,
+            -4 => RpcErrorCode::WalletError,
+            -5 => RpcErrorCode::InvalidAddressOrKey,
+            -8 => RpcErrorCode::InvalidParameter,
+            -18 => RpcErrorCode::WalletNotFound,
+            -26 => RpcErrorCode::VerifyRejected,
+            -27 => RpcErrorCode::VerifyAlreadyInChain,
+            -28 => RpcErrorCode::InWarmup,
+            -35 => RpcErrorCode::WalletAlreadyLoaded,
+            -36 => RpcErrorCode::WalletAlreadyExists,
+            -32601 => RpcErrorCode::MethodNotFound,
+            other => RpcErrorCode::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RpcError {
+    #[error("could not reach bitcoind: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("bitcoind answered with HTTP {status}: {body}")]
+    Http { status: u16, body: String },
+    #[error("malformed RPC response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("RPC error {code:?}: {message}")]
+    Rpc { code: RpcErrorCode, message: String },
+    #[error("RPC auth: {0}")]
+    Auth(String),
+    #[error("bitcoind did not become ready in time")]
+    NotReady,
+}
+
+impl RpcError {
+    pub fn code(&self) -> Option<RpcErrorCode> {
+        match self {
+            RpcError::Rpc { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcResponseError>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponseError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockchainInfo {
+    pub chain: String,
+    pub blocks: u64,
+    pub headers: u64,
+    pub bestblockhash: String,
+    #[serde(default)]
+    pub initialblockdownload: bool,
+}
+
+/// What `getblock` returns at verbosity 1, or the parts of it the game uses.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockInfo {
+    pub hash: String,
+    pub height: u64,
+    /// The block's txids, coinbase first.
+    pub tx: Vec<String>,
+}
+
+/// How cautious `estimatesmartfee` is about fee spikes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EstimateMode {
+    Economical,
+    Conservative,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmartFeeEstimate {
+    /// BTC/kvB. Missing until bitcoind has seen enough blocks and transactions, as on a fresh
+    /// regtest chain.
+    pub feerate: Option<f64>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    pub blocks: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportDescriptorRequest {
+    pub desc: String,
+    pub timestamp: Value,
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub internal: Option<bool>,
+}
+
+impl ImportDescriptorRequest {
+    /// An active, ranged descriptor that only looks at blocks from now on.
+    pub fn active_now(desc: &str, range: (u32, u32)) -> Self {
+        Self {
+            desc: desc.to_string(),
+            timestamp: json!("now"),
+            active: true,
+            range: Some(range),
+            internal: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportDescriptorResult {
+    pub success: bool,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    pub error: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateWalletResult {
+    pub name: String,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// A blocking JSON-RPC client for bitcoind.
+pub struct RpcClient {
+    client: Client,
+    url: String,
+    user: String,
+    pass: String,
+    next_id: AtomicU64,
+}
+
+impl RpcClient {
+    pub fn new(url: &str, user: &str, pass: &str) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            user: user.to_string(),
+            pass: pass.to_string(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Authenticate with the `.cookie` file bitcoind writes into its data dir.
+    pub fn with_cookie(url: &str, cookie_path: &Path) -> Result<Self, RpcError> {
+        let contents = fs::read_to_string(cookie_path)
+            .map_err(|err| RpcError::Auth(format!("reading {}: {err}", cookie_path.display())))?;
+        let (user, pass) = contents
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| RpcError::Auth("malformed cookie file".to_string()))?;
+        Ok(Self::new(url, user, pass))
+    }
+
+    /// A client for the `/wallet/<name>` endpoint sharing this client's credentials.
+    pub fn for_wallet(&self, wallet_name: &str) -> Self {
+        Self {
+            client: self.client.clone(),
+            url: format!("{}/wallet/{wallet_name}", self.base_url()),
+            user: self.user.clone(),
+            pass: self.pass.clone(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    fn base_url(&self) -> &str {
+        match self.url.find("/wallet/") {
+            Some(idx) => &self.url[..idx],
+            None => &self.url,
+        }
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: &[Value]) -> Result<T, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let resp = self
+            .client
+            .post(&self.url)
+            .basic_auth(&self.user, Some(&self.pass))
+            .json(&json!({
+                "jsonrpc": "1.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            }))
+            .send()?;
+
+        // bitcoind reports RPC errors with a non-2xx status and a JSON body,
+        // so only fall back to the HTTP status when the body isn't JSON-RPC.
+        let status = resp.status();
+        let body = resp.text()?;
+        let response: RpcResponse = match serde_json::from_str(&body) {
+            Ok(response) => response,
+            Err(_) if !status.is_success() => {
+                return Err(RpcError::Http {
+                    status: status.as_u16(),
+                    body,
+                });
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Some(err) = response.error {
+            return Err(RpcError::Rpc {
+                code: err.code.into(),
+                message: err.message,
+            });
+        }
+
+        Ok(serde_json::from_value(
+            response.result.unwrap_or(Value::Null),
+        )?)
+    }
+
+    /// Poll `getblockchaininfo` until bitcoind answers, or give up after `attempts`.
+    pub fn wait_until_ready(
+        &self,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<BlockchainInfo, RpcError> {
+        for _ in 0..attempts {
+            match self.get_blockchain_info() {
+                Ok(info) => return Ok(info),
+                Err(RpcError::Auth(err)) => return Err(RpcError::Auth(err)),
+                Err(err) => debug!("bitcoind not ready yet: {err}"),
+            }
+            thread::sleep(delay);
+        }
+        Err(RpcError::NotReady)
+    }
+
+    pub fn get_blockchain_info(&self) -> Result<BlockchainInfo, RpcError> {
+        self.call("getblockchaininfo", &[])
+    }
+
+    pub fn get_block_info(&self, hash: &str) -> Result<BlockInfo, RpcError> {
+        self.call("getblock", &[json!(hash), json!(1)])
+    }
+
+    pub fn generate_to_address(&self, count: u32, address: &str) -> Result<Vec<String>, RpcError> {
+        self.call("generatetoaddress", &[json!(count), json!(address)])
+    }
+
+    pub fn estimate_smart_fee(
+        &self,
+        conf_target: u16,
+        mode: EstimateMode,
+    ) -> Result<SmartFeeEstimate, RpcError> {
+        self.call("estimatesmartfee", &[json!(conf_target), json!(mode)])
+    }
+
+    pub fn create_wallet(
+        &self,
+        wallet_name: &str,
+        disable_private_keys: bool,
+        blank: bool,
+    ) -> Result<CreateWalletResult, RpcError> {
+        // wallet_name disable_private_keys blank passphrase avoid_reuse descriptors load_on_startup
+        self.call(
+            "createwallet",
+            &[
+                json!(wallet_name),
+                json!(disable_private_keys),
+                json!(blank),
+                json!(""),
+                json!(false),
+                json!(true),
+                json!(true),
+            ],
+        )
+    }
+
+    pub fn load_wallet(&self, wallet_name: &str) -> Result<Value, RpcError> {
+        self.call("loadwallet", &[json!(wallet_name)])
+    }
+
+    pub fn import_descriptors(
+        &self,
+        requests: &[ImportDescriptorRequest],
+    ) -> Result<Vec<ImportDescriptorResult>, RpcError> {
+        self.call("importdescriptors", &[serde_json::to_value(requests)?])
+    }
+
+    pub fn stop(&self) -> Result<String, RpcError> {
+        self.call("stop", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
+    fn client(server: &Server) -> RpcClient {
+        RpcClient::new(&server.url(), "user", "pass")
+    }
+
+    #[test]
+    fn parses_blockchain_info() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("POST", "/")
+            .match_header("authorization", "Basic dXNlcjpwYXNz")
+            .match_body(Matcher::PartialJson(json!({"method": "getblockchaininfo"})))
+            .with_body(
+                r#"{"result":{"chain":"regtest","blocks":101,"headers":101,"bestblockhash":"00ab","initialblockdownload":false},"error":null,"id":0}"#,
+            )
+            .create();
+
+        let info = client(&server).get_blockchain_info().unwrap();
+        assert_eq!(info.chain, "regtest");
+        assert_eq!(info.blocks, 101);
+        assert_eq!(info.bestblockhash, "00ab");
+        mock.assert();
+    }
+
+    #[test]
+    fn maps_rpc_error_codes() {
+        let mut server = Server::new();
+        server
+            .mock("POST", "/")
+            .with_status(500)
+            .with_body(
+                r#"{"result":null,"error":{"code":-36,"message":"Database already exists."},"id":0}"#,
+            )
+            .create();
+
+        let err = client(&server)
+            .create_wallet("default", false, true)
+            .unwrap_err();
+        assert_eq!(err.code(), Some(RpcErrorCode::WalletAlreadyExists));
+    }
+
+    #[test]
+    fn non_json_error_is_http_error() {
+        let mut server = Server::new();
+        server
+            .mock("POST", "/")
+            .with_status(401)
+            .with_body("")
+            .create();
+
+        let err = client(&server).get_blockchain_info().unwrap_err();
+        assert!(matches!(err, RpcError::Http { status: 401, .. }));
+    }
+
+    #[test]
+    fn wallet_calls_use_wallet_url() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("POST", "/wallet/default")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "importdescriptors",
+                "params": [[{"desc": "tr(xpub)", "timestamp": "now", "active": true, "range": [0, 1000]}]],
+            })))
+            .with_body(r#"{"result":[{"success":true}],"error":null,"id":0}"#)
+            .create();
+
+        let wallet = client(&server).for_wallet("default");
+        let results = wallet
+            .import_descriptors(&[ImportDescriptorRequest::active_now("tr(xpub)", (0, 1000))])
+            .unwrap();
+        assert!(results[0].success);
+        mock.assert();
+
+        // A wallet client switches wallets rather than nesting them
+        let other = server
+            .mock("POST", "/wallet/other")
+            .with_body(r#"{"result":"bitcoind stopping","error":null,"id":0}"#)
+            .create();
+        wallet.for_wallet("other").stop().unwrap();
+        other.assert();
+    }
+
+    #[test]
+    fn creates_and_loads_descriptor_wallets() {
+        let mut server = Server::new();
+        let create = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "createwallet",
+                "params": ["watch", true, true, "", false, true, true],
+            })))
+            .with_body(r#"{"result":{"name":"watch","warnings":[]},"error":null,"id":0}"#)
+            .create();
+        let load = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "loadwallet",
+                "params": ["watch"],
+            })))
+            .with_body(r#"{"result":{"name":"watch"},"error":null,"id":0}"#)
+            .create();
+
+        let client = client(&server);
+        assert_eq!(
+            client.create_wallet("watch", true, true).unwrap().name,
+            "watch"
+        );
+        assert_eq!(client.load_wallet("watch").unwrap()["name"], "watch");
+        create.assert();
+        load.assert();
+    }
+
+    #[test]
+    fn generate_to_address_returns_hashes() {
+        let mut server = Server::new();
+        server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "generatetoaddress",
+                "params": [2, "bcrt1qaddress"],
+            })))
+            .with_body(r#"{"result":["aa","bb"],"error":null,"id":0}"#)
+            .create();
+
+        let hashes = client(&server)
+            .generate_to_address(2, "bcrt1qaddress")
+            .unwrap();
+        assert_eq!(hashes, vec!["aa".to_string(), "bb".to_string()]);
+    }
+
+    #[test]
+    fn block_info_lists_txids() {
+        let mut server = Server::new();
+        server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "getblock",
+                "params": ["aa", 1],
+            })))
+            .with_body(
+                r#"{"result":{"hash":"aa","confirmations":1,"height":102,"tx":["c0","t1"],"nTx":2},"error":null,"id":0}"#,
+            )
+            .create();
+
+        let block = client(&server).get_block_info("aa").unwrap();
+        assert_eq!(block.height, 102);
+        assert_eq!(block.tx, vec!["c0".to_string(), "t1".to_string()]);
+    }
+
+    #[test]
+    fn smart_fee_without_data_has_no_feerate() {
+        let mut server = Server::new();
+        server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "estimatesmartfee",
+                "params": [6, "economical"],
+            })))
+            .with_body(
+                r#"{"result":{"errors":["Insufficient data or no feerate found"],"blocks":0},"error":null,"id":0}"#,
+            )
+            .create();
+
+        let estimate = client(&server)
+            .estimate_smart_fee(6, EstimateMode::Economical)
+            .unwrap();
+        assert_eq!(estimate.feerate, None);
+        assert_eq!(estimate.errors.len(), 1);
+    }
+}
@@ -1,163 +1,96 @@
+use bdk_wallet::rusqlite::Connection;
+use bdk_wallet::{KeychainKind, PersistedWallet, Wallet};
 use bevy::prelude::*;
-use bip39::Mnemonic;
-use bitcoin::{
-    Address, CompressedPublicKey, KnownHrp, Network, PrivateKey, Script, ScriptBuf,
-    bip32::{DerivationPath, Xpriv},
-    key::{Secp256k1, UntweakedPublicKey},
-    opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1},
-};
+use bitcoin::{Network, Script, ScriptBuf};
 use directories::ProjectDirs;
 use eyre::{Result, eyre};
-use miniscript::descriptor::DescriptorSecretKey;
-use miniscript::descriptor::checksum::desc_checksum;
-use reqwest::blocking::Client;
-use serde_json::Value;
+use signet_miner::BlockSigner;
+use signet_miner::rpc_client::RpcClient;
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::str::FromStr;
 use std::{thread, time::Duration};
 
-use crate::constants::BITCOIN_DIR;
+use crate::keys::{SlotKeys, WalletDescriptors};
+use crate::node_config::{Backend, NodeConfig, chain_subdir};
 
-pub fn launch_bitcoind_process() -> Result<(Child, PathBuf, PathBuf)> {
-    //let challenge = get_segwit_challenge()?;
-    //let challenge = format!("{}", challenge.as_bytes().as_hex());
+/// Launch bitcoind. A private signet's challenge comes from the slot's keys, so it can't be
+/// launched before they're unlocked.
+pub fn launch_bitcoind_process(config: &NodeConfig, keys: Option<&SlotKeys>) -> Result<Child> {
+    let challenge = signet_challenge(config, keys)?;
 
-    let config_path = get_config_dir()?.join("bitcoin.conf");
-    let datadir = get_data_dir(Some(BITCOIN_DIR.into()))?;
+    let config_path = config.bitcoin_conf_path()?;
+    let datadir = config.bitcoind_dir()?;
 
-    fs::create_dir_all(&datadir)?;
+    // Always rewrite the conf so it follows the configured network and ports
+    write_bitcoin_conf(&config_path, config, challenge.as_deref())?;
+    info!("Wrote config to {}", config_path.display());
 
-    if !config_path.exists() {
-        write_bitcoin_conf(&config_path, "")?;
-        info!("Wrote config to {}", config_path.display());
-    }
-
-    let (child, data_dir, conf_path) = spawn_bitcoind(BITCOIN_DIR, &datadir, &config_path)?;
+    let child = spawn_bitcoind(config, &datadir, &config_path)?;
     info!("bitcoind launched with PID {}", child.id());
 
-    Ok((child, data_dir, conf_path))
+    Ok(child)
 }
 
-pub fn load_descriptor(descriptor: &str) -> Result<()> {
-    let datadir = get_data_dir(Some(BITCOIN_DIR.into()))?;
-    let (user, pass) = read_cookie_auth(&datadir)?;
-
-    wait_for_rpc_ready(&user, &pass)?;
-
-    load_descriptor_wallet("default", descriptor, &user, &pass)?;
-    Ok(())
+/// Signs the private signet's blocks with the slot's signet key, or `None` off a private signet.
+pub fn block_signer(config: &NodeConfig, keys: &SlotKeys) -> Result<Option<BlockSigner>> {
+    match config.private_signet {
+        true => Ok(Some(BlockSigner::new(&keys.signet)?)),
+        false => Ok(None),
+    }
 }
 
-pub fn get_segwit_challenge() -> Result<ScriptBuf> {
-    let master_xpriv = xpriv_key_from_abandon()?;
-
-    let secp = Secp256k1::new();
-
-    // purpose (bip 86) / coin type (1=testnet) / account / change (0=external, 1=internal) / addy index
-    let deriv_path = DerivationPath::from_str("m/86h/1h/0h/0/0")?;
-    let xpriv = master_xpriv.derive_priv(&secp, &deriv_path)?;
-
-    let public_key = xpriv.to_priv().public_key(&secp);
-    let segwit_challenge = Script::builder()
-        .push_opcode(OP_PUSHNUM_1)
-        .push_key(&public_key)
-        .push_opcode(OP_PUSHNUM_1)
-        .push_opcode(OP_CHECKMULTISIG)
-        .into_script();
-
-    Ok(segwit_challenge)
+/// The private signet's challenge: a 1-of-1 multisig for the slot's signet key. `None` off a
+/// private signet.
+pub fn signet_challenge(config: &NodeConfig, keys: Option<&SlotKeys>) -> Result<Option<ScriptBuf>> {
+    if !config.private_signet {
+        return Ok(None);
+    }
+    let keys = keys.ok_or_else(|| eyre!("The private signet needs the slot's keys"))?;
+    Ok(Some(BlockSigner::new(&keys.signet)?.challenge()))
 }
 
-pub fn xpriv_key_from_abandon() -> Result<Xpriv> {
-    let mnemonic = Mnemonic::parse(
-            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
-        )
-        .map_err(|_| eyre!("Mnemonic key parsing error."))?;
-    let seed = mnemonic.to_seed("");
-    let xpriv = Xpriv::new_master(Network::Regtest, &seed)?;
-    Ok(xpriv)
+pub fn rpc_client(config: &NodeConfig) -> Result<RpcClient> {
+    let datadir = config.bitcoind_dir()?;
+    let cookie_path = cookie_path(&datadir, config.network)?;
+    Ok(RpcClient::with_cookie(&config.rpc_url(), &cookie_path)?)
 }
 
-pub fn xpriv_to_descriptor(xpriv: Xpriv) -> String {
-    let secp = Secp256k1::new();
-    info!("Xpriv: {}", xpriv);
-    let deriv_path = DerivationPath::from_str("m/86h/1h/0h/0/0").expect("a derivation path");
-
-    // tprv comes from "abandon abandon..." seed
-    // bitcoin-cli -datadir=$HOME/.local/share/bird-view/bitcoind -conf=$HOME/.config/bird-view/bitcoin.conf -rpcwallet=default getdescriptorinfo "tr(tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd/86h/1h/0h/0/*)"
-
-    // bitcoin-cli -datadir=$HOME/.local/share/bird-view/bitcoind -conf=$HOME/.config/bird-view/bitcoin.conf -rpcwallet=default importdescriptors '[{"desc":"tr(tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd/86h/1h/0h/0/*)#vak0p2pv","timestamp":"now", "active": true}]'
-
-    // bitcoin-cli -datadir=$HOME/.local/share/bird-view/bitcoind -conf=$HOME/.config/bird-view/bitcoin.conf -rpcwallet=default getnewaddress "" bech32m
-    // -> bcrt1p8wpt9v4frpf3tkn0srd97pksgsxc5hs52lafxwru9kgeephvs7rqjeprhg
-
-    let cs = desc_checksum("tr(tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd/86h/1h/0h/0/*)").unwrap();
-    info!("CHECKSUM: {cs}");
-
-    let xpriv_desc = format!("{}/{}", xpriv, "86h/1h/0h/0/0");
-    let desc = DescriptorSecretKey::from_str(&xpriv_desc).unwrap();
-    let xkey = match desc {
-        DescriptorSecretKey::Single(_) => panic!("not a single!"),
-        DescriptorSecretKey::XPrv(descriptor_xkey) => descriptor_xkey,
-        DescriptorSecretKey::MultiXPrv(_) => panic!("not a multi!"),
+/// Open a wallet's sqlite db and load the wallet from it, if an earlier run created one.
+///
+/// The sim backend's chain only lives as long as the game, so its wallets are kept in memory.
+pub fn load_wallet(
+    config: &NodeConfig,
+    name: &str,
+    descriptors: &WalletDescriptors,
+) -> Result<(Option<PersistedWallet<Connection>>, Connection)> {
+    let mut db = match config.backend {
+        Backend::Node => Connection::open(config.wallet_db_path(name)?)?,
+        Backend::Sim => Connection::open_in_memory()?,
     };
 
-    let derived_xpriv = xkey.xkey.derive_priv(&secp, &deriv_path).unwrap();
-    let kp = derived_xpriv.to_keypair(&secp);
-    let pk = UntweakedPublicKey::from_keypair(&kp).0;
-    let addy = Address::p2tr(&secp, pk, None, KnownHrp::Regtest);
-
-    assert_eq!(
-        format!("{addy}"),
-        "bcrt1p8wpt9v4frpf3tkn0srd97pksgsxc5hs52lafxwru9kgeephvs7rqjeprhg"
-    );
-
-    // Sanity check (main net)
-    let private_key = PrivateKey::new(kp.secret_key(), Network::Bitcoin);
-    let cpk = CompressedPublicKey::from_private_key(&secp, &private_key).unwrap();
-    let addy2 = Address::p2wpkh(&cpk, KnownHrp::Mainnet);
-    assert_eq!(
-        format!("{addy2}"),
-        "bc1qvjvarw3yz8xmhcrt54qkaafry5r072pg4c5nmy"
-    );
+    let wallet = Wallet::load()
+        .descriptor(KeychainKind::External, Some(descriptors.external.clone()))
+        .descriptor(KeychainKind::Internal, Some(descriptors.internal.clone()))
+        .extract_keys()
+        .check_network(config.network)
+        .load_wallet(&mut db)?;
 
-    format!("{addy}")
+    Ok((wallet, db))
 }
 
-pub fn load_wallet() {
-    let data_dir = get_data_dir(None).unwrap();
-    let conn = bdk_wallet::rusqlite::Connection::open(data_dir).unwrap();
-    //    let wallet = Wallet::load().load_wallet(persister);
-}
-
-pub fn read_cookie_auth(datadir: &Path) -> Result<(String, String)> {
-    let subdir = datadir.join("regtest");
+/// The `.cookie` file of the bitcoind in `datadir`, once bitcoind has written it.
+pub fn cookie_path(datadir: &Path, network: Network) -> Result<PathBuf> {
+    let subdir = match chain_subdir(network) {
+        Some(subdir) => datadir.join(subdir),
+        None => datadir.to_path_buf(),
+    };
     let cookie_path = subdir.join(".cookie");
 
     wait_for_file(&cookie_path, Duration::from_secs(30))?;
-
-    let contents = fs::read_to_string(&cookie_path).map_err(|e| {
-        eyre!(
-            "Failed to read cookie file {}: {}",
-            cookie_path.display(),
-            e
-        )
-    })?;
-
-    let mut parts = contents.trim().splitn(2, ':');
-    let user = parts
-        .next()
-        .ok_or_else(|| eyre!("Malformed cookie file"))?
-        .to_string();
-    let pass = parts
-        .next()
-        .ok_or_else(|| eyre!("Malformed cookie file"))?
-        .to_string();
     info!("Got cookie");
-    Ok((user, pass))
+    Ok(cookie_path)
 }
 
 pub fn get_config_dir() -> Result<PathBuf> {
@@ -182,113 +115,62 @@ pub fn get_data_dir(path: Option<PathBuf>) -> Result<PathBuf> {
     Ok(data_dir.to_path_buf())
 }
 
-pub fn write_bitcoin_conf(path: &Path, challenge: &str) -> std::io::Result<()> {
+/// `challenge` makes the signet a private one, only mined by whoever can satisfy it.
+pub fn write_bitcoin_conf(
+    path: &Path,
+    config: &NodeConfig,
+    challenge: Option<&Script>,
+) -> std::io::Result<()> {
     let mut file = File::create(path)?;
-    //writeln!(file, "signet=1")?;
-    writeln!(file, "regtest=1")?;
+    if config.network != Network::Bitcoin {
+        writeln!(file, "{}=1", config.network)?;
+    }
     writeln!(file, "server=1")?;
     writeln!(file, "txindex=1")?;
     writeln!(file, "fallbackfee=0.0001")?;
-    //writeln!(file, "signetchallenge={}", challenge)?;
+    if let Some(challenge) = challenge {
+        writeln!(file, "signetchallenge={}", challenge.to_hex_string())?;
+    }
     //writeln!(file, "debug=1")?;
+
+    // Ports are only read from the section of the active network
+    if let Some(section) = conf_section(config.network) {
+        writeln!(file, "[{}]", section)?;
+    }
+    writeln!(file, "rpcport={}", config.rpc_port)?;
+    writeln!(file, "port={}", config.p2p_port)?;
+    writeln!(file, "zmqpubrawblock={}", config.zmq_block_addr())?;
+    writeln!(file, "zmqpubrawtx={}", config.zmq_tx_addr())?;
     Ok(())
 }
 
-pub fn wait_for_rpc_ready(user: &str, pass: &str) -> Result<u64> {
-    let client = Client::new();
-    //let url = "http://127.0.0.1:38332"; // Signet
-    let url = "http://127.0.0.1:18443"; // Regtest
-
-    for _ in 0..30 {
-        let res = client
-            .post(url)
-            .basic_auth(user, Some(pass))
-            .json(&serde_json::json!({
-                "jsonrpc": "1.0",
-                "id": "ready",
-                "method": "getblockchaininfo",
-                "params": []
-            }))
-            .send();
-
-        if let Ok(resp) = res {
-            if resp.status().is_success() {
-                let data = resp.text()?;
-                let v: Value = serde_json::from_str(&data)?;
-                info!("wait for rpc: {}", data);
-                info!("Block count: {}", v["result"]["blocks"]);
-                let block_count = v["result"]["blocks"].clone();
-                let block_count = block_count.as_u64().unwrap();
-                return Ok(block_count);
-            }
-        }
-        thread::sleep(Duration::from_secs(1));
+/// The `bitcoin.conf` section name for a network, which isn't always its data subdir.
+fn conf_section(network: Network) -> Option<&'static str> {
+    match network {
+        Network::Bitcoin => None,
+        Network::Testnet => Some("test"),
+        network => chain_subdir(network),
     }
-
-    Err(eyre!("bitcoind did not become ready in time"))
 }
 
-fn load_descriptor_wallet(
-    wallet_name: &str,
-    descriptor: &str,
-    user: &str,
-    pass: &str,
-) -> Result<()> {
-    let client = Client::new();
-    let url = format!("http://127.0.0.1:18443/wallet/{wallet_name}");
-
-    // Try to create the wallet first (ignore if already exists)
-    let _ = client
-        .post("http://127.0.0.1:18443/")
-        .basic_auth(user, Some(pass))
-        .json(&serde_json::json!({
-            "jsonrpc": "1.0",
-            "id": "create",
-            "method": "createwallet",
-            "params": [wallet_name, false, true, "", false, true, true], // wallet_name disable_private_keys blank "passphrase" avoid_reuse descriptors load_on_startup
-        }))
-        .send();
-
-    // Import the descriptor
-    let resp = client
-        .post(&url)
-        .basic_auth(user, Some(pass))
-        .json(&serde_json::json!({
-            "jsonrpc": "1.0",
-            "id": "importdesc",
-            "method": "importdescriptors",
-            "params": [[{
-                "desc": descriptor,
-                "timestamp": "now",
-                "active": true,
-                "range": [0, 1000]
-            }]]
-        }))
-        .send()?;
-
-    if resp.status().is_success() {
-        log::info!("Loaded descriptor into wallet `{wallet_name}`");
-        Ok(())
-    } else {
-        Err(eyre!("Failed to import descriptor: {}", resp.text()?))
-    }
+pub fn wait_for_rpc_ready(rpc: &RpcClient) -> Result<u64> {
+    let info = rpc.wait_until_ready(30, Duration::from_secs(1))?;
+    info!("Block count: {}", info.blocks);
+    Ok(info.blocks)
 }
 
-fn spawn_bitcoind(
-    bitcoind_path: &str,
-    data_dir: &Path,
-    conf_path: &Path,
-) -> Result<(Child, PathBuf, PathBuf)> {
+fn spawn_bitcoind(config: &NodeConfig, data_dir: &Path, conf_path: &Path) -> Result<Child> {
+    let bitcoind_path = config.bitcoind.display();
     let data_dir_arg = format!("-datadir={}", data_dir.display());
     let conf_path_arg = format!("-conf={}", conf_path.display());
     info!("{} {} {}", bitcoind_path, data_dir_arg, conf_path_arg);
-    let mut child = Command::new(bitcoind_path)
+    let mut child = Command::new(&config.bitcoind)
         .arg(data_dir_arg)
         .arg(conf_path_arg)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .expect("Failed to start bitcoind");
+        .map_err(|err| eyre!("Failed to start {bitcoind_path}: {err}"))?;
 
     let stdout = child.stdout.take();
     let stderr = child.stderr.take();
@@ -314,7 +196,7 @@ fn spawn_bitcoind(
     }
 
     info!("{bitcoind_path} process started with PID: {}", child.id());
-    Ok((child, PathBuf::from(data_dir), PathBuf::from(conf_path)))
+    Ok(child)
 }
 
 fn wait_for_file<P: AsRef<Path>>(path: P, timeout: Duration) -> std::io::Result<()> {
@@ -333,57 +215,3 @@ fn wait_for_file<P: AsRef<Path>>(path: P, timeout: Duration) -> std::io::Result<
 
     Ok(())
 }
-
-pub fn mine_blocks(count: u32, address: &str) -> Result<()> {
-    let bitcoin_cli = "bitcoin-cli";
-    let config_path = get_config_dir()?.join("bitcoin.conf");
-    let datadir = get_data_dir(Some(BITCOIN_DIR.into()))?;
-
-    let (user, pass) = read_cookie_auth(&datadir)?;
-    wait_for_rpc_ready(&user, &pass)?;
-
-    let data_dir_arg = format!("-datadir={}", datadir.display());
-    let conf_path_arg = format!("-conf={}", config_path.display());
-    info!("{} {} {}", bitcoin_cli, data_dir_arg, conf_path_arg);
-
-    let mut child = Command::new(bitcoin_cli)
-        .arg("-regtest")
-        .arg(data_dir_arg)
-        .arg(conf_path_arg)
-        .arg("generatetoaddress")
-        .arg(count.to_string())
-        .arg(address)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start bitcoin-cli");
-
-    let stdout = child.stdout.take();
-    let stderr = child.stderr.take();
-
-    // Log stdout
-    if let Some(out) = stdout {
-        thread::spawn(move || {
-            let reader = BufReader::new(out);
-            for line in reader.lines().map_while(Result::ok) {
-                info!("[{bitcoin_cli} stdout] {}", line);
-            }
-        });
-    }
-
-    // Log stderr
-    if let Some(err) = stderr {
-        thread::spawn(move || {
-            let reader = BufReader::new(err);
-            for line in reader.lines().map_while(Result::ok) {
-                warn!("[{bitcoin_cli} stderr] {}", line);
-            }
-        });
-    };
-
-    info!("{bitcoin_cli} process started with PID: {}", child.id());
-    let exit = child.wait()?;
-    info!("{bitcoin_cli} process ended with: {}", exit);
-
-    Ok(())
-}
@@ -1,32 +1,42 @@
 use crate::{
-    bdk_zone::{
-        get_data_dir, launch_bitcoind_process, load_descriptor, mine_blocks, read_cookie_auth,
-        wait_for_rpc_ready, xpriv_key_from_abandon, xpriv_to_descriptor,
-    },
-    constants::BITCOIN_DIR,
+    bdk_zone::{block_signer, launch_bitcoind_process, rpc_client, wait_for_rpc_ready},
+    block_production::{self, NewBlock, StopMining},
+    chain_backend::{Chain, ChainBackend},
     electrum_wallet::{
-        ElectrsProcess, PlayerWallet, TouristWallet, activate_wallet, spawn_electrs,
+        ELECTRS_INDEX_TIMEOUT, ElectrsProcess, PlayerWallet, SharedWallet, StoredWallet,
+        TouristWallet, activate_wallet, spawn_electrs, wait_for_electrum,
     },
+    game_state::{BootEvent, BootStep, GameState},
+    keys::SlotKeys,
+    node_config::{Backend, NodeConfig},
 };
 use bevy::prelude::*;
-use std::{
-    path::PathBuf,
-    process::{Child, Stdio},
-};
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
+use signet_miner::grinder::Cancel;
+use std::process::Child;
 
 pub struct BitcoindHandler;
 
 impl Plugin for BitcoindHandler {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, insert_bitcoind);
+        app.init_state::<GameState>()
+            .add_event::<BootEvent>()
+            .add_systems(Startup, start_boot)
+            .add_systems(
+                Update,
+                (
+                    send_keys.run_if(resource_added::<SlotKeys>),
+                    poll_boot.run_if(not(in_state(GameState::Playing))),
+                ),
+            );
     }
 }
 
 #[derive(Resource)]
-struct BitcoindProcess {
-    child: Child,
-    data_dir: PathBuf,
-    conf_path: PathBuf,
+pub struct BitcoindProcess {
+    pub child: Child,
+    // The supervisor may restart bitcoind, so the RPC client (and its cookie) is built on demand
+    config: NodeConfig,
 }
 
 impl Drop for BitcoindProcess {
@@ -39,19 +49,11 @@ impl Drop for BitcoindProcess {
                 );
             }
             Ok(None) => {
-                info!("Bitcoind still running; trying `bitcoin-cli -conf=... stop`");
-
-                let cli_result = std::process::Command::new("bitcoin-cli")
-                    .arg(format!("-datadir={}", self.data_dir.display()))
-                    .arg(format!("-conf={}", self.conf_path.display()))
-                    .arg("stop")
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .status();
+                info!("Bitcoind still running; sending `stop` over RPC");
 
-                match cli_result {
-                    Ok(status) if status.success() => {
-                        info!("Sent `bitcoin-cli stop`; waiting for process to exit...");
+                match rpc_client(&self.config).and_then(|rpc| Ok(rpc.stop()?)) {
+                    Ok(msg) => {
+                        info!("{msg}; waiting for process to exit...");
 
                         for _ in 0..10 {
                             std::thread::sleep(std::time::Duration::from_secs(1));
@@ -66,11 +68,8 @@ impl Drop for BitcoindProcess {
 
                         warn!("Bitcoind did not exit in time; force killing...");
                     }
-                    Ok(status) => {
-                        error!("`bitcoin-cli stop` exited with status code: {}", status);
-                    }
                     Err(e) => {
-                        error!("Failed to run `bitcoin-cli stop`: {}", e);
+                        error!("Failed to send `stop` to bitcoind: {}", e);
                     }
                 }
 
@@ -102,44 +101,210 @@ pub fn log_or_print(msg: &str, level: log::Level) {
     }
 }
 
-fn insert_bitcoind(mut commands: Commands) {
-    let maybe_child_etal = launch_bitcoind_process();
-    let xpriv = xpriv_key_from_abandon().unwrap();
-    let descriptor = xpriv_to_descriptor(xpriv);
-    info!("LETS TRY insert bitcoind: {}", descriptor);
-    if let Ok((child, data_dir, conf_path)) = maybe_child_etal {
-        load_descriptor("SOME DESCRIPTOR").expect("LOADED!");
-        let bitcoind_process = BitcoindProcess {
+/// Enough blocks for the first coinbase to mature.
+const FUNDING_BLOCKS: u32 = 101;
+
+/// Everything the boot task hands back to the main thread, in the order it happens.
+enum BootMessage {
+    Progress(BootEvent),
+    Bitcoind(BitcoindProcess),
+    Electrs(ElectrsProcess),
+    Blocks(Vec<NewBlock>),
+    Wallets {
+        tourist: Box<StoredWallet>,
+        player: Box<StoredWallet>,
+    },
+}
+
+#[derive(Resource, Deref)]
+struct BootReceiver(Receiver<BootMessage>);
+
+/// Hands the slot's keys to the boot task once the passphrase dialog unlocks them.
+#[derive(Resource, Deref)]
+struct KeysSender(Sender<SlotKeys>);
+
+/// Bring up bitcoind, electrs and the wallets on a background thread so the window stays live.
+fn start_boot(
+    mut commands: Commands,
+    node_config: Res<NodeConfig>,
+    chain: Res<Chain>,
+    stop_mining: Res<StopMining>,
+) {
+    let (boot_tx, boot_rx) = unbounded::<BootMessage>();
+    let (keys_tx, keys_rx) = bounded::<SlotKeys>(1);
+    let config = node_config.clone();
+    let chain = chain.clone();
+    let cancel = stop_mining.token();
+
+    std::thread::spawn(move || run_boot(&config, &keys_rx, chain.as_ref(), &cancel, &boot_tx));
+
+    commands.insert_resource(BootReceiver(boot_rx));
+    commands.insert_resource(KeysSender(keys_tx));
+}
+
+fn send_keys(keys: Res<SlotKeys>, sender: Res<KeysSender>) {
+    // The boot task may already have failed and gone
+    let _ = sender.try_send(keys.clone());
+}
+
+/// Boot, telling the loading screen why if it fails.
+fn run_boot(
+    config: &NodeConfig,
+    keys_rx: &Receiver<SlotKeys>,
+    chain: &dyn ChainBackend,
+    cancel: &Cancel,
+    boot_tx: &Sender<BootMessage>,
+) {
+    if let Err(err) = boot(config, keys_rx, chain, cancel, boot_tx) {
+        error!("Boot failed: {err}");
+        let _ = boot_tx.send(BootMessage::Progress(BootEvent::Failed(err.to_string())));
+    }
+}
+
+fn boot(
+    config: &NodeConfig,
+    keys_rx: &Receiver<SlotKeys>,
+    chain: &dyn ChainBackend,
+    cancel: &Cancel,
+    boot_tx: &Sender<BootMessage>,
+) -> eyre::Result<()> {
+    let send = |message: BootMessage| {
+        boot_tx
+            .send(message)
+            .map_err(|_| eyre::eyre!("The game closed while booting"))
+    };
+    let done = |step: BootStep| send(BootMessage::Progress(BootEvent::Done(step)));
+    let unlocked = || {
+        keys_rx
+            .recv()
+            .map_err(|_| eyre::eyre!("The game closed while booting"))
+    };
+
+    // A private signet's challenge comes from the slot's seed, so its bitcoind has to wait for
+    // the passphrase
+    let early_keys = match config.backend == Backend::Node && config.private_signet {
+        true => Some(unlocked()?),
+        false => None,
+    };
+
+    // The sim backend has no daemons, so their steps are done as soon as they start
+    if config.backend == Backend::Node {
+        let child = launch_bitcoind_process(config, early_keys.as_ref())?;
+        send(BootMessage::Bitcoind(BitcoindProcess {
             child,
-            data_dir,
-            conf_path,
-        };
-        commands.insert_resource(bitcoind_process);
-    } else {
-        warn!("Could not insert the BitcoindProcess Resource")
+            config: config.clone(),
+        }))?;
+    }
+    done(BootStep::BitcoindSpawned)?;
+
+    if config.backend == Backend::Node {
+        let rpc = rpc_client(config)?;
+        wait_for_rpc_ready(&rpc)?;
+    }
+    done(BootStep::RpcReady)?;
+
+    // Otherwise the daemons come up while the player types their passphrase
+    let keys = match early_keys {
+        Some(keys) => keys,
+        None => unlocked()?,
+    };
+    done(BootStep::SeedUnlocked)?;
+
+    if chain.tip()?.height < 50 {
+        // The tourists spend the coinbases
+        let address = keys.tourist.first_address(config.network)?;
+        let signer = block_signer(config, &keys)?;
+        let blocks =
+            block_production::mine(chain, FUNDING_BLOCKS, &address, signer.as_ref(), cancel)?;
+        send(BootMessage::Blocks(blocks))?;
     }
+    done(BootStep::BlocksMined)?;
+
+    if config.backend == Backend::Node {
+        let (child, _, _) = spawn_electrs(config, Some(&keys))
+            .map_err(|err| eyre::eyre!("Need to have electrs installed on your machine: {err}"))?;
+        send(BootMessage::Electrs(ElectrsProcess { child }))?;
+        wait_for_electrum(config, ELECTRS_INDEX_TIMEOUT)?;
+    }
+    done(BootStep::ElectrsIndexed)?;
+
+    let (tourist, player) = activate_wallet(config, &keys, chain)?;
+    send(BootMessage::Wallets {
+        tourist: Box::new(tourist),
+        player: Box::new(player),
+    })?;
+    done(BootStep::WalletsScanned)?;
 
-    let datadir = get_data_dir(Some(BITCOIN_DIR.into())).expect("A datadir");
-    let (user, pass) = read_cookie_auth(&datadir).expect("A user/pass");
-    let block_count = wait_for_rpc_ready(&user, &pass).expect("bitcoind is ready");
-    if block_count < 50 {
-        mine_blocks(
-            101,
-            "bcrt1pkar3gerekw8f9gef9vn9xz0qypytgacp9wa5saelpksdgct33qdqan7c89",
-        )
-        .expect("Blocks");
+    Ok(())
+}
+
+fn poll_boot(
+    mut commands: Commands,
+    receiver: Res<BootReceiver>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut boot_e: EventWriter<BootEvent>,
+    mut new_block_e: EventWriter<NewBlock>,
+) {
+    for message in receiver.try_iter() {
+        match message {
+            BootMessage::Progress(event) => {
+                if let BootEvent::Done(step) = event {
+                    info!("Boot: {}", step.label());
+                    if step.state_after() != *state.get() {
+                        next_state.set(step.state_after());
+                    }
+                }
+                boot_e.write(event);
+            }
+            BootMessage::Bitcoind(process) => commands.insert_resource(process),
+            BootMessage::Electrs(process) => commands.insert_resource(process),
+            BootMessage::Blocks(blocks) => {
+                new_block_e.write_batch(blocks);
+            }
+            BootMessage::Wallets { tourist, player } => {
+                commands.spawn(TouristWallet {
+                    wallet: SharedWallet::new(*tourist),
+                });
+                commands.spawn(PlayerWallet {
+                    wallet: SharedWallet::new(*player),
+                });
+            }
+        }
     }
+}
 
-    let (child, _, _) = spawn_electrs().expect("Need to have electrs installed on your machine");
-    commands.insert_resource(ElectrsProcess { child });
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_config::NodeConfigArgs;
+    use crate::sim_chain::SimChain;
 
-    let (tourist_wallet, player_wallet) = activate_wallet();
+    #[test]
+    fn missing_bitcoind_fails_the_boot() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::resolve(NodeConfigArgs {
+            bitcoind: Some(dir.path().join("no-bitcoind")),
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        })
+        .unwrap();
+        let (boot_tx, boot_rx) = unbounded();
+        let (_keys_tx, keys_rx) = bounded(1);
 
-    commands.spawn(TouristWallet {
-        wallet: tourist_wallet,
-    });
+        run_boot(
+            &config,
+            &keys_rx,
+            &SimChain::new(),
+            &Cancel::default(),
+            &boot_tx,
+        );
 
-    commands.spawn(PlayerWallet {
-        wallet: player_wallet,
-    });
+        let failure = boot_rx.try_iter().find_map(|message| match message {
+            BootMessage::Progress(BootEvent::Failed(err)) => Some(err),
+            _ => None,
+        });
+        let failure = failure.expect("the boot reports its failure");
+        assert!(failure.contains("no-bitcoind"), "{failure}");
+    }
 }
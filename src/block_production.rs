@@ -0,0 +1,344 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bitcoin::{Address, BlockHash, Txid};
+use clap::ValueEnum;
+use crossbeam_channel::{Receiver, Sender, unbounded};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use signet_miner::BlockSigner;
+use signet_miner::grinder::Cancel;
+
+use crate::bdk_zone::block_signer;
+use crate::chain_backend::{Chain, ChainBackend};
+use crate::game_state::GameState;
+use crate::keys::SlotKeys;
+use crate::node_config::NodeConfig;
+use crate::supervisor::{Service, ServiceEvent, ServiceState, ServiceStatus};
+use crate::tourists::RoundStarted;
+
+/// Mines the game's blocks on a background worker, on the schedule the node config picks.
+pub struct BlockProduction;
+
+impl Plugin for BlockProduction {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MineBlocks>()
+            .add_event::<NewBlock>()
+            .init_resource::<StopMining>()
+            .add_systems(Startup, start_worker)
+            .add_systems(Update, stop_mining_without_bitcoind)
+            .add_systems(
+                Update,
+                (schedule_blocks, request_blocks, announce_blocks)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// Blocks mined at each round boundary under [`BlockPolicy::Rounds`].
+pub const BLOCKS_PER_ROUND: u32 = 8;
+/// The shortest wait between scheduled blocks.
+const MIN_DELAY: Duration = Duration::from_secs(1);
+/// Poisson delays are capped at this many intervals, as the signet miner's max interval caps its.
+const MAX_POISSON_INTERVALS: f64 = 3.0;
+
+/// When the game mines blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockPolicy {
+    /// One block every interval
+    Interval,
+    /// One block at random times, an interval apart on average, like real mining
+    Poisson,
+    /// A batch of blocks whenever a round starts
+    #[default]
+    Rounds,
+    /// Only when the player asks for one
+    OnDemand,
+}
+
+/// Ask for blocks to be mined to the tourist wallet, whatever the policy.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct MineBlocks {
+    pub count: u32,
+}
+
+/// A block the game mined made it into the chain.
+#[derive(Event, Clone, Debug)]
+pub struct NewBlock {
+    pub height: u32,
+    pub hash: BlockHash,
+    /// Coinbase first.
+    pub txs: Vec<Txid>,
+}
+
+/// Stops the grinds of the blocks being mined, so none go on after bitcoind has gone.
+#[derive(Resource, Default)]
+pub struct StopMining(Cancel);
+
+impl StopMining {
+    /// Stopped by the next [`StopMining::stop`].
+    pub fn token(&self) -> Cancel {
+        self.0.clone()
+    }
+
+    /// Stop every grind so far; later ones get a fresh token.
+    fn stop(&mut self) {
+        std::mem::take(&mut self.0).cancel();
+    }
+}
+
+struct MineJob {
+    count: u32,
+    address: Address,
+    signer: Option<BlockSigner>,
+    cancel: Cancel,
+}
+
+#[derive(Resource)]
+struct BlockWorker {
+    jobs: Sender<MineJob>,
+    results: Receiver<eyre::Result<Vec<NewBlock>>>,
+    in_flight: u32,
+}
+
+/// The next block the policy schedules, if it schedules any.
+#[derive(Resource)]
+struct BlockSchedule(Option<Timer>);
+
+/// Mine `count` blocks to `address`, signed by `signer` on a private signet, and look up what
+/// went into them. Fewer are mined if `cancel` stops a grind.
+pub fn mine(
+    chain: &dyn ChainBackend,
+    count: u32,
+    address: &Address,
+    signer: Option<&BlockSigner>,
+    cancel: &Cancel,
+) -> eyre::Result<Vec<NewBlock>> {
+    chain
+        .mine_blocks(count, address, signer, cancel)?
+        .iter()
+        .map(|hash| {
+            let block = chain.block(hash)?;
+            Ok(NewBlock {
+                height: block.height,
+                hash: block.hash,
+                txs: block.txids,
+            })
+        })
+        .collect()
+}
+
+/// The wait before the next scheduled block, or `None` if the policy doesn't schedule them.
+fn next_block_delay(policy: BlockPolicy, interval: Duration) -> Option<Duration> {
+    match policy {
+        BlockPolicy::Interval => Some(interval.max(MIN_DELAY)),
+        // Exponentially distributed, the same way `Generate::next_block_delta` spaces them
+        BlockPolicy::Poisson => {
+            let variance = -(-rand::rng().random::<f64>()).ln_1p();
+            let delay = interval.mul_f64(variance.min(MAX_POISSON_INTERVALS));
+            Some(delay.max(MIN_DELAY))
+        }
+        BlockPolicy::Rounds | BlockPolicy::OnDemand => None,
+    }
+}
+
+fn start_worker(mut commands: Commands, chain: Res<Chain>, node_config: Res<NodeConfig>) {
+    let (jobs_tx, jobs_rx) = unbounded::<MineJob>();
+    let (results_tx, results_rx) = unbounded();
+    let chain = chain.clone();
+
+    // Runs until the game drops its end of either channel. Signet blocks take a moment to
+    // grind, so mining never holds up a frame.
+    std::thread::spawn(move || {
+        for MineJob {
+            count,
+            address,
+            signer,
+            cancel,
+        } in jobs_rx.iter()
+        {
+            let result = mine(chain.as_ref(), count, &address, signer.as_ref(), &cancel);
+            if results_tx.send(result).is_err() {
+                return;
+            }
+        }
+    });
+
+    commands.insert_resource(BlockWorker {
+        jobs: jobs_tx,
+        results: results_rx,
+        in_flight: 0,
+    });
+    let delay = next_block_delay(node_config.block_policy, node_config.block_interval);
+    commands.insert_resource(BlockSchedule(
+        delay.map(|delay| Timer::new(delay, TimerMode::Once)),
+    ));
+}
+
+fn schedule_blocks(
+    time: Res<Time>,
+    node_config: Res<NodeConfig>,
+    worker: Res<BlockWorker>,
+    mut schedule: ResMut<BlockSchedule>,
+    mut round_e: EventReader<RoundStarted>,
+    mut mine_e: EventWriter<MineBlocks>,
+) {
+    for _ in round_e.read() {
+        if node_config.block_policy == BlockPolicy::Rounds {
+            mine_e.write(MineBlocks {
+                count: BLOCKS_PER_ROUND,
+            });
+        }
+    }
+
+    let Some(timer) = &mut schedule.0 else {
+        return;
+    };
+    // A scheduled block waits for the last one to finish rather than piling up behind it
+    if !timer.tick(time.delta()).finished() || worker.in_flight > 0 {
+        return;
+    }
+    mine_e.write(MineBlocks { count: 1 });
+    if let Some(delay) = next_block_delay(node_config.block_policy, node_config.block_interval) {
+        *timer = Timer::new(delay, TimerMode::Once);
+    }
+}
+
+fn request_blocks(
+    mut mine_e: EventReader<MineBlocks>,
+    mut worker: ResMut<BlockWorker>,
+    node_config: Res<NodeConfig>,
+    keys: Res<SlotKeys>,
+    service_status: Res<ServiceStatus>,
+    stop_mining: Res<StopMining>,
+) {
+    for &MineBlocks { count } in mine_e.read() {
+        if !service_status.is_running(Service::Bitcoind) {
+            warn!("bitcoind is down; not mining {count} blocks");
+            continue;
+        }
+        // The tourists spend the coinbases
+        let address = match keys.tourist.first_address(node_config.network) {
+            Ok(address) => address,
+            Err(err) => {
+                warn!("No address to mine to: {err}");
+                continue;
+            }
+        };
+        let signer = match block_signer(&node_config, &keys) {
+            Ok(signer) => signer,
+            Err(err) => {
+                warn!("No key to sign blocks with: {err}");
+                continue;
+            }
+        };
+        let job = MineJob {
+            count,
+            address,
+            signer,
+            cancel: stop_mining.token(),
+        };
+        if worker.jobs.send(job).is_err() {
+            warn!("The block producer has stopped");
+            continue;
+        }
+        worker.in_flight += 1;
+    }
+}
+
+/// A grind can take a while, and its block can't be submitted while bitcoind is down.
+fn stop_mining_without_bitcoind(
+    mut service_e: EventReader<ServiceEvent>,
+    mut stop_mining: ResMut<StopMining>,
+) {
+    for event in service_e.read() {
+        let down = matches!(
+            event.state,
+            ServiceState::Unreachable | ServiceState::Restarting { .. } | ServiceState::Failed
+        );
+        if event.service == Service::Bitcoind && down {
+            info!("bitcoind is down; stopping any blocks being mined");
+            stop_mining.stop();
+        }
+    }
+}
+
+fn announce_blocks(mut worker: ResMut<BlockWorker>, mut new_block_e: EventWriter<NewBlock>) {
+    let results: Vec<_> = worker.results.try_iter().collect();
+    for result in results {
+        worker.in_flight = worker.in_flight.saturating_sub(1);
+        match result {
+            Ok(blocks) => {
+                for block in blocks {
+                    info!("New block {} at height {}", block.hash, block.height);
+                    new_block_e.write(block);
+                }
+            }
+            Err(err) => warn!("Failed to mine blocks: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{Network, Script};
+
+    use super::*;
+    use crate::sim_chain::SimChain;
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn interval_blocks_wait_the_interval() {
+        assert_eq!(
+            next_block_delay(BlockPolicy::Interval, INTERVAL),
+            Some(INTERVAL)
+        );
+        assert_eq!(
+            next_block_delay(BlockPolicy::Interval, Duration::ZERO),
+            Some(MIN_DELAY)
+        );
+    }
+
+    #[test]
+    fn poisson_delays_stay_in_bounds() {
+        for _ in 0..1000 {
+            let delay = next_block_delay(BlockPolicy::Poisson, INTERVAL).unwrap();
+            assert!(delay >= MIN_DELAY, "{delay:?}");
+            assert!(
+                delay <= INTERVAL.mul_f64(MAX_POISSON_INTERVALS),
+                "{delay:?}"
+            );
+        }
+        assert_eq!(
+            next_block_delay(BlockPolicy::Poisson, Duration::ZERO),
+            Some(MIN_DELAY)
+        );
+    }
+
+    #[test]
+    fn rounds_and_on_demand_schedule_nothing() {
+        assert_eq!(next_block_delay(BlockPolicy::Rounds, INTERVAL), None);
+        assert_eq!(next_block_delay(BlockPolicy::OnDemand, INTERVAL), None);
+    }
+
+    #[test]
+    fn mined_blocks_are_summarized() {
+        let chain = SimChain::new();
+        let address = Address::p2wsh(Script::new(), Network::Regtest);
+
+        let blocks = mine(&chain, 2, &address, None, &Cancel::default()).unwrap();
+
+        let heights: Vec<_> = blocks.iter().map(|block| block.height).collect();
+        assert_eq!(heights, [1, 2]);
+        assert_eq!(blocks[1].hash, chain.tip().unwrap().hash);
+        for block in &blocks {
+            // Nothing was broadcast, so each block is only its coinbase
+            assert_eq!(block.txs, chain.block(&block.hash).unwrap().txids);
+            assert_eq!(block.txs.len(), 1);
+        }
+        assert_ne!(blocks[0].txs, blocks[1].txs);
+    }
+}
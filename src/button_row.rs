@@ -1,8 +1,9 @@
 #![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
 
 use crate::{
-    bdk_zone::get_segwit_challenge, constants::PopupBase, popup::PopupItem,
-    tilemaptest::GameMapEvent,
+    block_production::MineBlocks, constants::PopupBase, popup::PopupItem,
+    tilemaptest::GameMapEvent, wallet_panel::ToggleWalletPanel,
 };
 use bevy::{color::palettes::basic::*, prelude::*};
 use bevy_ecs_tilemap::tiles::TileColor;
@@ -24,7 +25,8 @@ const PRESSED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
 pub enum ButtonAction {
     Save,
     TogglePopup,
-    ActivateElectrumWallet,
+    ToggleWallet,
+    MineBlock,
 }
 
 fn button_system(
@@ -43,6 +45,8 @@ fn button_system(
     mut popup_q: Query<&mut Node, With<PopupBase>>,
     mut picked_q: Query<(Entity, &PopupItem)>,
     mut tilemap_e: EventWriter<GameMapEvent>,
+    mut wallet_panel_e: EventWriter<ToggleWalletPanel>,
+    mut mine_e: EventWriter<MineBlocks>,
     mut color_q: Query<&mut TileColor>,
 ) {
     for (interaction, mut color, mut border_color, children, button_action) in
@@ -93,8 +97,6 @@ fn button_system(
                             .iter_mut()
                             .for_each(|mut color| color.0 = Color::default());
 
-                        let z = get_segwit_challenge();
-                        println!("z: {z:?}");
                         **text = "Menu".to_string();
                         *color = PRESSED_BUTTON.into();
                         border_color.0 = RED.into();
@@ -129,7 +131,7 @@ fn button_system(
                     }
                 }
             }
-            ButtonAction::ActivateElectrumWallet => {
+            ButtonAction::ToggleWallet => {
                 let mut text = text_query.get_mut(children[0]).unwrap();
                 match *interaction {
                     Interaction::Pressed => {
@@ -142,9 +144,11 @@ fn button_system(
                             .iter_mut()
                             .for_each(|mut color| color.0 = Color::default());
 
-                        **text = "Press".to_string();
+                        **text = "Wallet".to_string();
                         *color = PRESSED_BUTTON.into();
                         border_color.0 = RED.into();
+
+                        wallet_panel_e.write(ToggleWalletPanel);
                     }
                     Interaction::Hovered => {
                         // Despawn any PickedItem
@@ -156,17 +160,33 @@ fn button_system(
                             .iter_mut()
                             .for_each(|mut color| color.0 = Color::default());
 
-                        **text = "Placeholder".to_string();
+                        **text = "Wallet".to_string();
                         *color = HOVERED_BUTTON.into();
                         border_color.0 = Color::WHITE;
                     }
                     Interaction::None => {
-                        **text = "Placeholder".to_string();
+                        **text = "Wallet".to_string();
                         *color = NORMAL_BUTTON.into();
                         border_color.0 = Color::BLACK;
                     }
                 }
             }
+            ButtonAction::MineBlock => match *interaction {
+                Interaction::Pressed => {
+                    *color = PRESSED_BUTTON.into();
+                    border_color.0 = RED.into();
+
+                    mine_e.write(MineBlocks { count: 1 });
+                }
+                Interaction::Hovered => {
+                    *color = HOVERED_BUTTON.into();
+                    border_color.0 = Color::WHITE;
+                }
+                Interaction::None => {
+                    *color = NORMAL_BUTTON.into();
+                    border_color.0 = Color::BLACK;
+                }
+            },
         }
     }
 }
@@ -240,33 +260,61 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                     },
                     TextColor(Color::srgb(0.9, 0.9, 0.9)),
                 ));
-            // parent
-            //     .spawn((
-            //         Button,
-            //         Node {
-            //             width: Val::Px(150.0),
-            //             height: Val::Px(65.0),
-            //             border: UiRect::all(Val::Px(5.0)),
-            //             // horizontally center child text
-            //             justify_content: JustifyContent::Center,
-            //             // vertically center child text
-            //             align_items: AlignItems::Center,
-            //             ..default()
-            //         },
-            //         BorderColor(Color::BLACK),
-            //         BorderRadius::MAX,
-            //         BackgroundColor(NORMAL_BUTTON),
-            //         ZIndex(1),
-            //         ButtonAction::ActivateElectrumWallet,
-            //     ))
-            //     .with_child((
-            //         Text::new("Placeholder"),
-            //         TextFont {
-            //             font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-            //             font_size: 33.0,
-            //             ..default()
-            //         },
-            //         TextColor(Color::srgb(0.9, 0.9, 0.9)),
-            //     ));
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        width: Val::Px(150.0),
+                        height: Val::Px(65.0),
+                        border: UiRect::all(Val::Px(5.0)),
+                        // horizontally center child text
+                        justify_content: JustifyContent::Center,
+                        // vertically center child text
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    BorderColor(Color::BLACK),
+                    BorderRadius::MAX,
+                    BackgroundColor(NORMAL_BUTTON),
+                    ZIndex(1),
+                    ButtonAction::ToggleWallet,
+                ))
+                .with_child((
+                    Text::new("Wallet"),
+                    TextFont {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 33.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                ));
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        width: Val::Px(150.0),
+                        height: Val::Px(65.0),
+                        border: UiRect::all(Val::Px(5.0)),
+                        // horizontally center child text
+                        justify_content: JustifyContent::Center,
+                        // vertically center child text
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    BorderColor(Color::BLACK),
+                    BorderRadius::MAX,
+                    BackgroundColor(NORMAL_BUTTON),
+                    ZIndex(1),
+                    ButtonAction::MineBlock,
+                ))
+                .with_child((
+                    Text::new("Mine"),
+                    TextFont {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 33.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                ));
         });
 }
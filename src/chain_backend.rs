@@ -0,0 +1,211 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use bdk_electrum::{
+    BdkElectrumClient,
+    electrum_client::{self, Client, ElectrumApi},
+};
+use bdk_wallet::KeychainKind;
+use bdk_wallet::chain::BlockId;
+use bdk_wallet::chain::spk_client::{FullScanRequest, FullScanResponse, SyncRequest, SyncResponse};
+use bevy::prelude::*;
+use bitcoin::{Address, BlockHash, FeeRate, Transaction, Txid};
+use eyre::{Result, eyre};
+use signet_miner::grinder::Cancel;
+use signet_miner::rpc_client::EstimateMode;
+use signet_miner::{BlockSigner, RpcNode};
+
+use crate::bdk_zone::{rpc_client, wait_for_rpc_ready};
+use crate::fees::fee_rate_from_btc_per_kvb;
+use crate::node_config::{Backend, NodeConfig};
+use crate::sim_chain::SimChain;
+
+/// A block in the chain, as far as the game cares.
+#[derive(Clone, Debug)]
+pub struct BlockSummary {
+    pub height: u32,
+    pub hash: BlockHash,
+    /// Coinbase first.
+    pub txids: Vec<Txid>,
+}
+
+/// The chain operations the game needs, whatever is behind them.
+pub trait ChainBackend: Send + Sync {
+    fn tip(&self) -> Result<BlockId>;
+
+    /// Mine up to `count` blocks, fewer if `cancel` stops a grind. `signer` signs a private
+    /// signet's blocks; other chains don't need one.
+    fn mine_blocks(
+        &self,
+        count: u32,
+        address: &Address,
+        signer: Option<&BlockSigner>,
+        cancel: &Cancel,
+    ) -> Result<Vec<BlockHash>>;
+
+    fn block(&self, hash: &BlockHash) -> Result<BlockSummary>;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid>;
+
+    /// The fee rate to confirm within `target` blocks, or `None` if there's no estimate yet.
+    fn estimate_fee(&self, target: u16) -> Result<Option<FeeRate>>;
+
+    fn sync(&self, request: SyncRequest<(KeychainKind, u32)>) -> Result<SyncResponse>;
+
+    fn full_scan(
+        &self,
+        request: FullScanRequest<KeychainKind>,
+        stop_gap: usize,
+    ) -> Result<FullScanResponse<KeychainKind>>;
+}
+
+/// The backend picked by the node config, shared with background threads.
+#[derive(Resource, Clone, Deref)]
+pub struct Chain(pub Arc<dyn ChainBackend>);
+
+impl Chain {
+    pub fn from_config(config: &NodeConfig) -> Self {
+        match config.backend {
+            Backend::Node => Self(Arc::new(NodeBackend::new(config))),
+            Backend::Sim => Self(Arc::new(SimChain::new())),
+        }
+    }
+}
+
+const BATCH_SIZE: usize = 5;
+
+/// bitcoind for mining and the tip, electrs for everything wallet related.
+pub struct NodeBackend {
+    config: NodeConfig,
+    /// Kept between calls so its tx and header caches survive; dropped after an error so the next
+    /// call reconnects (electrs may have been restarted).
+    electrum: Mutex<Option<Arc<BdkElectrumClient<Client>>>>,
+}
+
+impl NodeBackend {
+    pub fn new(config: &NodeConfig) -> Self {
+        Self {
+            config: config.clone(),
+            electrum: Mutex::new(None),
+        }
+    }
+
+    fn with_electrum<T>(
+        &self,
+        op: impl FnOnce(&BdkElectrumClient<Client>) -> Result<T, electrum_client::Error>,
+    ) -> Result<T> {
+        let client = {
+            let mut cached = self
+                .electrum
+                .lock()
+                .map_err(|_| eyre!("The electrum client lock is poisoned"))?;
+            match cached.as_ref() {
+                Some(client) => client.clone(),
+                None => {
+                    let client = electrum_client::Client::new(&self.config.electrum_addr())?;
+                    let client = Arc::new(BdkElectrumClient::new(client));
+                    *cached = Some(client.clone());
+                    client
+                }
+            }
+        };
+
+        op(&client).map_err(|err| {
+            if let Ok(mut cached) = self.electrum.lock() {
+                *cached = None;
+            }
+            err.into()
+        })
+    }
+}
+
+impl ChainBackend for NodeBackend {
+    fn tip(&self) -> Result<BlockId> {
+        let info = rpc_client(&self.config)?.get_blockchain_info()?;
+        Ok(BlockId {
+            height: u32::try_from(info.blocks)?,
+            hash: BlockHash::from_str(&info.bestblockhash)?,
+        })
+    }
+
+    fn mine_blocks(
+        &self,
+        count: u32,
+        address: &Address,
+        signer: Option<&BlockSigner>,
+        cancel: &Cancel,
+    ) -> Result<Vec<BlockHash>> {
+        let rpc = rpc_client(&self.config)?;
+        wait_for_rpc_ready(&rpc)?;
+
+        let hashes = if self.config.private_signet {
+            // Signet has no generatetoaddress, so the blocks are built and signed here
+            let signer =
+                signer.ok_or_else(|| eyre!("The private signet's blocks need a signer"))?;
+            let node = RpcNode { rpc, wallet: None };
+            let mut hashes = vec![];
+            for _ in 0..count {
+                let mined =
+                    signet_miner::mine_block(&node, Some(signer), address.script_pubkey(), cancel)?;
+                let Some(hash) = mined else {
+                    info!("Mining cancelled after {} of {count} blocks", hashes.len());
+                    break;
+                };
+                hashes.push(hash);
+            }
+            hashes
+        } else {
+            rpc.generate_to_address(count, &address.to_string())?
+                .iter()
+                .map(|hash| BlockHash::from_str(hash))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        info!("Mined {} blocks, tip: {:?}", hashes.len(), hashes.last());
+
+        Ok(hashes)
+    }
+
+    fn block(&self, hash: &BlockHash) -> Result<BlockSummary> {
+        let info = rpc_client(&self.config)?.get_block_info(&hash.to_string())?;
+        Ok(BlockSummary {
+            height: u32::try_from(info.height)?,
+            hash: BlockHash::from_str(&info.hash)?,
+            txids: info
+                .tx
+                .iter()
+                .map(|txid| Txid::from_str(txid))
+                .collect::<Result<Vec<_>, _>>()?,
+        })
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+        self.with_electrum(|client| client.transaction_broadcast(tx))
+    }
+
+    fn estimate_fee(&self, target: u16) -> Result<Option<FeeRate>> {
+        let from_bitcoind = rpc_client(&self.config)
+            .and_then(|rpc| Ok(rpc.estimate_smart_fee(target, EstimateMode::Economical)?));
+        match from_bitcoind {
+            Ok(estimate) => Ok(estimate.feerate.and_then(fee_rate_from_btc_per_kvb)),
+            // electrs asks the same bitcoind, but may still get through when the game can't
+            Err(err) => {
+                debug!("estimatesmartfee failed, asking electrs: {err}");
+                let btc_per_kvb =
+                    self.with_electrum(|client| client.inner.estimate_fee(usize::from(target)))?;
+                Ok(fee_rate_from_btc_per_kvb(btc_per_kvb))
+            }
+        }
+    }
+
+    fn sync(&self, request: SyncRequest<(KeychainKind, u32)>) -> Result<SyncResponse> {
+        self.with_electrum(|client| client.sync(request, 25, true))
+    }
+
+    fn full_scan(
+        &self,
+        request: FullScanRequest<KeychainKind>,
+        stop_gap: usize,
+    ) -> Result<FullScanResponse<KeychainKind>> {
+        self.with_electrum(|client| client.full_scan(request, stop_gap, BATCH_SIZE, true))
+    }
+}
@@ -0,0 +1,330 @@
+use std::cmp::Reverse;
+
+use bdk_coin_select::metrics::LowestFee;
+use bdk_coin_select::{
+    Candidate, ChangePolicy, CoinSelector, Drain, DrainWeights, TR_KEYSPEND_TXIN_WEIGHT, Target,
+    TargetFee, TargetOutputs,
+};
+use bdk_wallet::LocalOutput;
+use bdk_wallet::chain::ChainPosition;
+use bitcoin::{Amount, FeeRate, ScriptBuf, TxOut};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::payments::PaymentError;
+
+/// Branch and bound gives up after this many rounds and falls back to largest first.
+const BNB_ROUNDS: usize = 100_000;
+
+/// How a batch of payments picks the coins it spends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoinSelection {
+    /// Search for the selection with the lowest fee over the long run
+    #[default]
+    BranchAndBound,
+    /// Spend the biggest coins first
+    LargestFirst,
+    /// Spend the longest confirmed coins first
+    OldestFirst,
+}
+
+impl CoinSelection {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::BranchAndBound => "branch and bound",
+            Self::LargestFirst => "largest first",
+            Self::OldestFirst => "oldest first",
+        }
+    }
+}
+
+/// The coins picked to fund some outputs.
+pub struct Selection<'a> {
+    pub inputs: Vec<&'a LocalOutput>,
+    /// What goes back to the change address, if it's worth an output.
+    pub change: Option<Amount>,
+    pub fee: Amount,
+    /// The selection's waste metric, in sats: what it costs now over what it would cost at the
+    /// long term fee rate, plus whatever's lost to not having change. Lower is better.
+    pub waste: f32,
+}
+
+/// Pick from `coins` enough to pay `outputs` at `fee_rate`, with change to `change`.
+///
+/// The coins are all taproot keyspends, as every game wallet is `tr()`. `long_term` is the rate
+/// the coins could be spent at instead, which the waste is measured against.
+pub fn select<'a>(
+    strategy: CoinSelection,
+    coins: &'a [LocalOutput],
+    outputs: &[TxOut],
+    change: &ScriptBuf,
+    fee_rate: FeeRate,
+    long_term: FeeRate,
+) -> Result<Selection<'a>, PaymentError> {
+    let candidates: Vec<Candidate> = coins
+        .iter()
+        .map(|coin| Candidate {
+            value: coin.txout.value.to_sat(),
+            weight: TR_KEYSPEND_TXIN_WEIGHT,
+            input_count: 1,
+            is_segwit: true,
+        })
+        .collect();
+    let fee_rate = coin_select_rate(fee_rate);
+    let long_term = coin_select_rate(long_term);
+
+    let target = Target {
+        fee: TargetFee::from_feerate(fee_rate),
+        outputs: TargetOutputs::fund_outputs(
+            outputs
+                .iter()
+                .map(|output| (output.weight().to_wu(), output.value.to_sat())),
+        ),
+    };
+    let change_policy = ChangePolicy::min_value_and_waste(
+        DrainWeights::TR_KEYSPEND,
+        change.minimal_non_dust().to_sat(),
+        fee_rate,
+        long_term,
+    );
+
+    let mut selector = CoinSelector::new(&candidates);
+    let selected = match strategy {
+        CoinSelection::BranchAndBound => {
+            let metric = LowestFee {
+                target,
+                long_term_feerate: long_term,
+                change_policy,
+            };
+            selector.run_bnb(metric, BNB_ROUNDS).is_ok() || {
+                selector.sort_candidates_by_descending_value_pwu();
+                selector.select_until_target_met(target).is_ok()
+            }
+        }
+        CoinSelection::LargestFirst => {
+            selector.sort_candidates_by_key(|(_, candidate)| Reverse(candidate.value));
+            selector.select_until_target_met(target).is_ok()
+        }
+        CoinSelection::OldestFirst => {
+            // Unconfirmed coins go last
+            selector.sort_candidates_by_key(|(index, _)| match &coins[index].chain_position {
+                ChainPosition::Confirmed { anchor, .. } => anchor.block_id.height,
+                ChainPosition::Unconfirmed { .. } => u32::MAX,
+            });
+            selector.select_until_target_met(target).is_ok()
+        }
+    };
+    if !selected {
+        selector.select_all();
+        let missing = selector.excess(target, Drain::NONE).unsigned_abs();
+        let available = Amount::from_sat(selector.selected_value());
+        return Err(PaymentError::InsufficientFunds {
+            needed: available + Amount::from_sat(missing),
+            available,
+        });
+    }
+
+    let paying: u64 = outputs.iter().map(|output| output.value.to_sat()).sum();
+    let drain = selector.drain(target, change_policy);
+    let waste = selector.waste(target, long_term, drain, 1.0);
+    let fee = selector.selected_value() - paying - drain.value;
+
+    Ok(Selection {
+        inputs: selector.apply_selection(coins).collect(),
+        change: drain.is_some().then(|| Amount::from_sat(drain.value)),
+        fee: Amount::from_sat(fee),
+        waste,
+    })
+}
+
+fn coin_select_rate(fee_rate: FeeRate) -> bdk_coin_select::FeeRate {
+    // 1 vB is 4 wu
+    bdk_coin_select::FeeRate::from_sat_per_vb(fee_rate.to_sat_per_kwu() as f32 * 4.0 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::KeychainKind;
+    use bdk_wallet::chain::{BlockId, ConfirmationBlockTime};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, OutPoint, Txid};
+
+    use super::*;
+
+    /// A taproot-shaped output script; only its size matters here.
+    fn p2tr() -> ScriptBuf {
+        let mut bytes = vec![0x51, 0x20];
+        bytes.extend([2; 32]);
+        ScriptBuf::from_bytes(bytes)
+    }
+
+    /// Coins of the given values, confirmed at the given heights.
+    fn coins(coins: &[(u64, u32)]) -> Vec<LocalOutput> {
+        coins
+            .iter()
+            .enumerate()
+            .map(|(index, &(value, height))| LocalOutput {
+                outpoint: OutPoint::new(Txid::from_byte_array([index as u8; 32]), 0),
+                txout: TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: p2tr(),
+                },
+                keychain: KeychainKind::External,
+                is_spent: false,
+                derivation_index: 0,
+                chain_position: ChainPosition::Confirmed {
+                    anchor: ConfirmationBlockTime {
+                        block_id: BlockId {
+                            height,
+                            hash: BlockHash::all_zeros(),
+                        },
+                        confirmation_time: 0,
+                    },
+                    transitively: None,
+                },
+            })
+            .collect()
+    }
+
+    fn paying(value: u64) -> Vec<TxOut> {
+        vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: p2tr(),
+        }]
+    }
+
+    fn sat_vb(rate: u64) -> FeeRate {
+        FeeRate::from_sat_per_vb_unchecked(rate)
+    }
+
+    fn values(selection: &Selection) -> Vec<u64> {
+        selection
+            .inputs
+            .iter()
+            .map(|input| input.txout.value.to_sat())
+            .collect()
+    }
+
+    #[test]
+    fn largest_first_spends_the_biggest_coin() {
+        let coins = coins(&[(10_000, 1), (50_000, 2), (20_000, 3)]);
+        let selection = select(
+            CoinSelection::LargestFirst,
+            &coins,
+            &paying(30_000),
+            &p2tr(),
+            sat_vb(1),
+            sat_vb(1),
+        )
+        .unwrap();
+
+        assert_eq!(values(&selection), [50_000]);
+        let change = selection.change.unwrap();
+        assert_eq!(change + selection.fee, Amount::from_sat(20_000));
+    }
+
+    #[test]
+    fn oldest_first_spends_the_longest_confirmed_coins() {
+        let coins = coins(&[(50_000, 30), (20_000, 10), (20_000, 20)]);
+        let selection = select(
+            CoinSelection::OldestFirst,
+            &coins,
+            &paying(30_000),
+            &p2tr(),
+            sat_vb(1),
+            sat_vb(1),
+        )
+        .unwrap();
+
+        let mut outpoints: Vec<_> = selection
+            .inputs
+            .iter()
+            .map(|input| input.outpoint)
+            .collect();
+        outpoints.sort();
+        assert_eq!(outpoints, [coins[1].outpoint, coins[2].outpoint]);
+    }
+
+    #[test]
+    fn branch_and_bound_avoids_change() {
+        // One input and one output come to 111 vB, so the middle coin pays for the payment with
+        // too little left over to be worth change
+        let coins = coins(&[(100_000, 1), (10_150, 2), (20_000, 3)]);
+        let selection = select(
+            CoinSelection::BranchAndBound,
+            &coins,
+            &paying(10_000),
+            &p2tr(),
+            sat_vb(1),
+            sat_vb(1),
+        )
+        .unwrap();
+
+        assert_eq!(values(&selection), [10_150]);
+        assert_eq!(selection.change, None);
+        assert_eq!(selection.fee, Amount::from_sat(150));
+    }
+
+    #[test]
+    fn runs_out_of_coins() {
+        let coins = coins(&[(10_000, 1), (20_000, 2)]);
+        let err = select(
+            CoinSelection::BranchAndBound,
+            &coins,
+            &paying(40_000),
+            &p2tr(),
+            sat_vb(1),
+            sat_vb(1),
+        )
+        .err()
+        .unwrap();
+
+        match err {
+            PaymentError::InsufficientFunds { needed, available } => {
+                assert_eq!(available, Amount::from_sat(30_000));
+                assert!(needed > Amount::from_sat(40_000));
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn waste_weighs_the_fee_rate_against_the_long_term() {
+        let coins = coins(&[(50_000, 1)]);
+        let waste = |fee_rate: u64| {
+            select(
+                CoinSelection::LargestFirst,
+                &coins,
+                &paying(30_000),
+                &p2tr(),
+                sat_vb(fee_rate),
+                sat_vb(5),
+            )
+            .unwrap()
+            .waste
+        };
+
+        // Spending now costs more than spending later at 5 sat/vB, and less below it
+        assert!(waste(20) > waste(5));
+        assert!(waste(1) < waste(5));
+    }
+
+    #[test]
+    fn waste_counts_what_goes_to_the_fee_instead_of_change() {
+        let coins = coins(&[(10_150, 1)]);
+        let selection = select(
+            CoinSelection::LargestFirst,
+            &coins,
+            &paying(10_000),
+            &p2tr(),
+            sat_vb(1),
+            sat_vb(1),
+        )
+        .unwrap();
+
+        // At the long term rate only the excess over the 111 sat fee is wasted
+        assert_eq!(selection.change, None);
+        assert!((selection.waste - 39.0).abs() < 1.0, "{}", selection.waste);
+    }
+}
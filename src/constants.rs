@@ -6,8 +6,15 @@ use strum_macros::EnumIter;
 pub const Z_TILEMAP: i32 = 1;
 
 pub const BITCOIN_DIR: &str = "bitcoind";
+pub const BITCOIN_CONF: &str = "bitcoin.conf";
+pub const ELECTRS_DB_DIR: &str = "electrs_db";
+pub const NODE_CONFIG_TOML: &str = "node.toml";
+pub const INSTANCES_DIR: &str = "instances";
 pub const MAP_DIR: &str = "map";
 pub const MAP_JSON: &str = "map.json";
+pub const WALLETS_DIR: &str = "wallets";
+pub const KEYS_DIR: &str = "keys";
+pub const PSBT_DIR: &str = "psbt";
 
 /// Marks an entity as being a Popup.
 /// Current use: tilemap interactions query to see if the node with this marker is displayed and if it is displayed, the system disables tilemap interaction.
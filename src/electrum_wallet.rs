@@ -1,79 +1,151 @@
+use std::collections::{BTreeSet, HashMap};
 use std::io::BufRead;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::path::PathBuf;
 use std::process::{Child, Stdio};
-use std::str::FromStr;
-
-use bdk_electrum::{
-    BdkElectrumClient,
-    electrum_client::{self, Client},
-};
-use bdk_wallet::KeychainKind;
-use bdk_wallet::Wallet;
-use bdk_wallet::bitcoin::Network;
-use bdk_wallet::chain::spk_client::{SyncRequest, SyncResponse};
-use bdk_wallet::{AddressInfo, SignOptions};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use bdk_wallet::AddressInfo;
+use bdk_wallet::rusqlite::Connection;
+use bdk_wallet::{KeychainKind, PersistedWallet, Wallet};
 use bevy::prelude::*;
-use bitcoin::{Address, Amount, FeeRate};
-use crossbeam_channel::{Receiver, Sender, bounded};
+use bevy_ecs_tilemap::tiles::TileStorage;
+use bitcoin::Amount;
 use num_format::{Locale, ToFormattedString};
+use signet_miner::signet_magic;
 
-use crate::bdk_zone::{get_config_dir, get_data_dir};
+use crate::bdk_zone::{load_wallet, signet_challenge};
 use crate::bitcoind::log_or_print;
-use crate::constants::BITCOIN_DIR;
-use crate::tourists::SatsToSend;
+use crate::chain_backend::ChainBackend;
+use crate::fees::FeePolicy;
+use crate::game_state::GameState;
+use crate::keys::{SlotKeys, WalletDescriptors};
+use crate::node_config::{NodeConfig, PlayerSigner, electrs_network};
+use crate::payments::{Coins, PaymentIntent, PaymentQueue};
+use crate::tourists::{Tourist, TouristTrap, TrapVisited};
+use crate::wallet_sync::WalletSynced;
 
 pub struct ElectrumWallet;
 
 impl Plugin for ElectrumWallet {
     fn build(&self, app: &mut App) {
-        app.add_event::<StreamEvent>()
+        app.init_resource::<TouristPurses>()
             .add_systems(Startup, startup)
-            .add_systems(Update, (send_sats, request_balance))
-            .add_systems(FixedUpdate, read_stream)
-            .insert_resource(Time::<Fixed>::from_seconds(0.5));
+            .add_systems(
+                Update,
+                (
+                    (reclaim_purses, fund_tourists, pay_for_visits).chain(),
+                    assign_trap_addresses,
+                    update_balance_label,
+                )
+                    .run_if(in_state(GameState::Playing)),
+            );
     }
 }
 
 const STOP_GAP: usize = 50;
-const BATCH_SIZE: usize = 5;
 
-const EXTERNAL_DESCRIPTOR: &str = "tr(tprv8ZgxMBicQKsPdrjwWCyXqqJ4YqcyG4DmKtjjsRt29v1PtD3r3PuFJAjWytzcvSTKnZAGAkPSmnrdnuHWxCAwy3i1iPhrtKAfXRH7dVCNGp6/86'/1'/0'/0/*)#g9xn7wf9";
-const INTERNAL_DESCRIPTOR: &str = "tr(tprv8ZgxMBicQKsPdrjwWCyXqqJ4YqcyG4DmKtjjsRt29v1PtD3r3PuFJAjWytzcvSTKnZAGAkPSmnrdnuHWxCAwy3i1iPhrtKAfXRH7dVCNGp6/86'/1'/0'/1/*)#e3rjrmea";
+/// electrs only opens its electrum port once the initial index is built.
+pub const ELECTRS_INDEX_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The tourist wallet's mined coins land at its first address, which tops up the tourists' purses.
+const RESERVE_PURSE: u32 = 0;
+/// What a tourist sets off with.
+const TOURIST_BUDGET: Amount = Amount::from_sat(100_000);
+/// What a tourist pays each trap it visits.
+const TRAP_PRICE: Amount = Amount::from_sat(4_000);
+/// The player's first address takes the payments of traps that don't have an address yet.
+const UNSORTED_REVENUE: u32 = 0;
+
+/// A wallet along with the sqlite db it's persisted to.
+pub struct StoredWallet {
+    wallet: PersistedWallet<Connection>,
+    db: Connection,
+}
 
-const EXTERNAL_ABANDON: &str = "tr(tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd/86h/1h/0h/0/*)#vak0p2pv";
-const INTERNAL_ABANDON: &str = "tr(tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd/86h/1h/0h/1/*)#afnwul35";
+impl StoredWallet {
+    /// Write any staged changes to the db.
+    pub fn persist(&mut self) -> eyre::Result<bool> {
+        Ok(self.wallet.persist(&mut self.db)?)
+    }
+}
 
-#[derive(Resource, Deref)]
-struct StreamReceiver(Receiver<SyncResponse>);
+impl std::ops::Deref for StoredWallet {
+    type Target = Wallet;
 
-#[derive(Resource, Deref)]
-struct StreamSender(Sender<SyncRequest<(KeychainKind, u32)>>);
+    fn deref(&self) -> &Wallet {
+        &self.wallet
+    }
+}
 
-#[derive(Event)]
-struct StreamEvent(Box<SyncResponse>);
+impl std::ops::DerefMut for StoredWallet {
+    fn deref_mut(&mut self) -> &mut Wallet {
+        &mut self.wallet
+    }
+}
 
-#[derive(Component, Deref, DerefMut)]
-pub struct SendSatsTimer(Timer);
+/// A wallet shared between the game and the background workers that sync and spend from it.
+#[derive(Clone)]
+pub struct SharedWallet(Arc<Mutex<StoredWallet>>);
+
+impl SharedWallet {
+    pub fn new(wallet: StoredWallet) -> Self {
+        Self(Arc::new(Mutex::new(wallet)))
+    }
+
+    pub fn lock(&self) -> eyre::Result<MutexGuard<'_, StoredWallet>> {
+        self.0
+            .lock()
+            .map_err(|_| eyre::eyre!("The wallet lock is poisoned"))
+    }
+}
+
+/// Which of the game's wallets something is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WalletId {
+    Tourist,
+    Player,
+}
+
+impl WalletId {
+    pub const ALL: [WalletId; 2] = [WalletId::Tourist, WalletId::Player];
+
+    /// Also the name of the wallet's db.
+    pub fn name(self) -> &'static str {
+        match self {
+            WalletId::Tourist => "tourist",
+            WalletId::Player => "player",
+        }
+    }
+}
 
 #[derive(Component)]
 pub struct TouristWallet {
-    pub wallet: Wallet,
+    pub wallet: SharedWallet,
 }
 
 #[derive(Component)]
 pub struct PlayerWallet {
-    pub wallet: Wallet,
+    pub wallet: SharedWallet,
 }
 
-#[derive(Component)]
-pub struct WalletBalanceLabel;
+/// The handle of one of the game's wallets, once they're spawned.
+pub fn shared_wallet(
+    wallet: WalletId,
+    tourist_q: &Query<&TouristWallet>,
+    player_q: &Query<&PlayerWallet>,
+) -> eyre::Result<SharedWallet> {
+    Ok(match wallet {
+        WalletId::Tourist => tourist_q.single()?.wallet.clone(),
+        WalletId::Player => player_q.single()?.wallet.clone(),
+    })
+}
 
 #[derive(Component)]
-pub struct BalanceTimer(pub Timer);
+pub struct WalletBalanceLabel;
 
 pub fn startup(mut commands: Commands) {
-    commands.spawn(BalanceTimer(Timer::from_seconds(7.0, TimerMode::Once)));
-    commands.spawn(SendSatsTimer(Timer::from_seconds(4.0, TimerMode::Once)));
     commands.spawn((
         Text::new("Sats: 0"),
         TextFont {
@@ -82,179 +154,341 @@ pub fn startup(mut commands: Commands) {
         },
         WalletBalanceLabel,
     ));
+}
 
-    let (sender_tx, sender_rx) = bounded::<SyncRequest<(KeychainKind, u32)>>(1);
-    let (electrs_tx, electrs_rx) = bounded::<SyncResponse>(1);
-    std::thread::spawn(move || {
-        loop {
-            match sender_rx.recv() {
-                Ok(request) => {
-                    let client: BdkElectrumClient<Client> = BdkElectrumClient::new(
-                        electrum_client::Client::new("127.0.0.1:60401").unwrap(),
-                    );
-                    let update = client.sync(request, 25, true).unwrap();
-                    electrs_tx.send(update).unwrap();
-                }
-                Err(err) => warn!("Sender recv did not get anything: {}", err),
-            };
-        }
-    });
+fn update_balance_label(
+    mut synced_e: EventReader<WalletSynced>,
+    player_q: Query<&PlayerWallet>,
+    mut balance_label_q: Query<&mut Text, With<WalletBalanceLabel>>,
+) {
+    let player_synced = synced_e
+        .read()
+        .filter(|synced| synced.wallet == WalletId::Player)
+        .count();
+    if player_synced == 0 {
+        return;
+    }
 
-    commands.insert_resource(StreamReceiver(electrs_rx));
-    commands.insert_resource(StreamSender(sender_tx));
+    let (Ok(player), Ok(mut label)) = (player_q.single(), balance_label_q.single_mut()) else {
+        return;
+    };
+    let Ok(wallet) = player.wallet.lock() else {
+        return;
+    };
+    let sat = wallet.balance().total().to_sat();
+    label.0 = format!("Sats: {}", sat.to_formatted_string(&Locale::en));
 }
 
-pub fn request_balance(
-    player_wallet_q: Query<&PlayerWallet>,
-    mut balance_timer: Query<&mut BalanceTimer>,
-    time: Res<Time>,
-    sender: ResMut<StreamSender>,
-) {
-    for mut timer in &mut balance_timer {
-        if timer.0.tick(time.delta()).just_finished() {
-            for player in player_wallet_q {
-                let request = player.wallet.start_sync_with_revealed_spks().build();
-
-                match sender.0.send(request) {
-                    Ok(val) => (),
-                    Err(err) => warn!("Err sending value: {err}"),
-                };
+/// The tourist wallet receive address each tourist spends from, so every payment a tourist makes
+/// can be traced back to it on-chain. A departed tourist's purse goes to the next tourist to
+/// arrive, along with whatever it didn't spend.
+#[derive(Resource, Default)]
+pub struct TouristPurses {
+    assigned: HashMap<Entity, u32>,
+    free: Vec<u32>,
+}
+
+impl TouristPurses {
+    /// Hand out the purses an earlier game left coins in again, so those coins aren't stranded.
+    fn reclaim(&mut self, wallet: &Wallet) {
+        let funded: BTreeSet<u32> = wallet
+            .list_unspent()
+            .filter(|output| {
+                output.keychain == KeychainKind::External
+                    && output.derivation_index != RESERVE_PURSE
+            })
+            .map(|output| output.derivation_index)
+            .collect();
+        for index in funded {
+            let in_use = self.assigned.values().any(|&assigned| assigned == index);
+            if !in_use && !self.free.contains(&index) {
+                self.free.push(index);
             }
-            timer.0.reset();
         }
     }
 }
 
-fn read_stream(
-    mut player_wallet_q: Query<&mut PlayerWallet>,
-    mut balance_label_q: Query<&mut Text, With<WalletBalanceLabel>>,
-    receiver: Res<StreamReceiver>,
+/// The purses are only kept in memory, so a loaded tourist wallet's are found again.
+fn reclaim_purses(
+    wallet_q: Query<&TouristWallet, Added<TouristWallet>>,
+    mut purses: ResMut<TouristPurses>,
 ) {
-    for response in receiver.try_iter() {
-        let mut player = player_wallet_q.single_mut().unwrap();
-        player.wallet.apply_update(response).unwrap();
-        let balance = player.wallet.balance();
-        let amount = balance.total();
-        let sat = amount.to_sat();
-        balance_label_q.single_mut().unwrap().0 =
-            format!("Sats: {}", sat.to_formatted_string(&Locale::en));
+    let Ok(wallet) = wallet_q.single() else {
+        return;
+    };
+    let Ok(stored) = wallet.wallet.lock() else {
+        return;
+    };
+    purses.reclaim(&stored);
+    if !purses.free.is_empty() {
+        info!(
+            "{} purses still hold coins from earlier games",
+            purses.free.len()
+        );
     }
 }
 
-pub fn send_sats(
-    time: Res<Time>,
-    mut sats_to_send_q: Query<&mut SatsToSend>,
-    mut send_sats_timer_q: Query<&mut SendSatsTimer>,
-    mut wallet_q: Query<&mut TouristWallet>,
+/// Give each new tourist a purse and top it up from the reserve.
+fn fund_tourists(
+    new_tourist_q: Query<Entity, Added<Tourist>>,
+    mut departed_e: RemovedComponents<Tourist>,
+    wallet_q: Query<&TouristWallet>,
+    mut purses: ResMut<TouristPurses>,
+    mut payments: ResMut<PaymentQueue>,
 ) {
-    for mut sats_timer in &mut send_sats_timer_q {
-        if sats_timer.tick(time.delta()).just_finished() {
-            //bcrt1p8wpt9v4frpf3tkn0srd97pksgsxc5hs52lafxwru9kgeephvs7rqjeprhg
-            let mut wallet = wallet_q.single_mut().unwrap();
-
-            // let client: BdkElectrumClient<Client> =
-            //     BdkElectrumClient::new(electrum_client::Client::new("127.0.0.1:60401").unwrap());
-
-            // let request = wallet.wallet.start_sync_with_revealed_spks().build();
-            // let update = client.sync(request, 25, true).unwrap();
-            // wallet.wallet.apply_update(update).unwrap();
-
-            let sats_to_send = sats_to_send_q.single_mut().unwrap().sats;
-            if sats_to_send > 0 {
-                let base_fee = 4;
-                let more_fee = sats_to_send / 4_000;
+    for tourist in departed_e.read() {
+        if let Some(index) = purses.assigned.remove(&tourist) {
+            purses.free.push(index);
+        }
+    }
 
-                let address = Address::from_str(
-                    "bcrt1p8wpt9v4frpf3tkn0srd97pksgsxc5hs52lafxwru9kgeephvs7rqjeprhg",
-                )
-                .unwrap()
-                .require_network(bitcoin::Network::Regtest)
-                .unwrap();
-                let amount = Amount::from_sat(sats_to_send);
-                let fee = FeeRate::from_sat_per_vb(base_fee + more_fee).unwrap();
-                let mut builder = wallet.wallet.build_tx();
-                builder.fee_rate(fee).add_recipient(address, amount);
-                let mut psbt = builder.finish().unwrap();
-
-                let _is_finalized = wallet
-                    .wallet
-                    .sign(&mut psbt, SignOptions::default())
-                    .unwrap();
-
-                let tx = psbt.extract_tx().unwrap();
-                let client: BdkElectrumClient<Client> = BdkElectrumClient::new(
-                    electrum_client::Client::new("127.0.0.1:60401").unwrap(),
-                );
-                match client.transaction_broadcast(&tx) {
-                    Ok(_) => info!("Transaction broadcast! Txid: {}", tx.compute_txid()),
-                    Err(err) => warn!("Broadcast error: {err}"),
+    if new_tourist_q.is_empty() {
+        return;
+    }
+    let Ok(wallet) = wallet_q.single() else {
+        warn!("No tourist wallet to fund the new tourists from");
+        return;
+    };
+    let Ok(mut stored) = wallet.wallet.lock() else {
+        return;
+    };
+
+    for tourist in &new_tourist_q {
+        let index = match purses.free.pop() {
+            Some(index) => index,
+            None => {
+                let mut address = stored.reveal_next_address(KeychainKind::External);
+                if address.index == RESERVE_PURSE {
+                    address = stored.reveal_next_address(KeychainKind::External);
                 }
+                address.index
             }
-            sats_timer.0.reset();
-        }
+        };
+        purses.assigned.insert(tourist, index);
+
+        let purse = Coins::Purse(index);
+        let balance: Amount = stored
+            .list_unspent()
+            .filter(|output| purse.contains(output))
+            .map(|output| output.txout.value)
+            .sum();
+        // Leftovers from an earlier tourist may already cover most of the budget
+        let Some(top_up) = TOURIST_BUDGET
+            .checked_sub(balance)
+            .filter(|top_up| *top_up >= TRAP_PRICE)
+        else {
+            continue;
+        };
+
+        let address = stored.peek_address(KeychainKind::External, index).address;
+        payments.enqueue(
+            &wallet.wallet,
+            PaymentIntent {
+                from: WalletId::Tourist,
+                to: address,
+                amount: top_up,
+                // The tourist can spend the top-up before it confirms, so it needn't hurry
+                fee_policy: FeePolicy::Economy,
+                coins: Coins::Purse(RESERVE_PURSE),
+            },
+        );
+    }
+
+    // Revealing purses has to survive a restart, or they'd be handed out again
+    if let Err(err) = stored.persist() {
+        warn!("Failed to save the tourist wallet: {err}");
     }
 }
 
-pub fn activate_wallet() -> (Wallet, Wallet) {
-    let tourist_wallet = create_wallet(EXTERNAL_DESCRIPTOR, INTERNAL_DESCRIPTOR);
-    let player_wallet = create_wallet(EXTERNAL_ABANDON, INTERNAL_ABANDON);
-    (tourist_wallet, player_wallet)
+/// Each trap a tourist visits gets paid from that tourist's purse.
+fn pay_for_visits(
+    mut visit_e: EventReader<TrapVisited>,
+    wallet_q: Query<&TouristWallet>,
+    storage_q: Query<&TileStorage>,
+    trap_q: Query<&TouristTrap>,
+    purses: Res<TouristPurses>,
+    node_config: Res<NodeConfig>,
+    keys: Res<SlotKeys>,
+    mut payments: ResMut<PaymentQueue>,
+) {
+    for visit in visit_e.read() {
+        let Some(tile) = storage_q
+            .single()
+            .ok()
+            .and_then(|storage| storage.checked_get(&visit.trap.0))
+        else {
+            continue;
+        };
+        // A special sidewalk tile on its own isn't a trap; only a placed trap building is
+        let Ok(trap) = trap_q.get(tile) else {
+            continue;
+        };
+
+        let Some(&index) = purses.assigned.get(&visit.tourist) else {
+            warn!(
+                "Tourist {} has no purse to pay the trap at {:?}",
+                visit.tourist, visit.trap.0
+            );
+            continue;
+        };
+        let Ok(wallet) = wallet_q.single() else {
+            continue;
+        };
+        // Until its address is handed out, a trap pays the player's first address
+        let trap_address = trap
+            .address
+            .clone()
+            .and_then(|address| address.require_network(node_config.network).ok());
+        let address = match trap_address {
+            Some(address) => address,
+            None => match keys.player.first_address(node_config.network) {
+                Ok(address) => address,
+                Err(err) => {
+                    warn!("No address to pay the player at: {err}");
+                    continue;
+                }
+            },
+        };
+        payments.enqueue(
+            &wallet.wallet,
+            PaymentIntent {
+                from: WalletId::Tourist,
+                to: address,
+                amount: TRAP_PRICE,
+                fee_policy: FeePolicy::Normal,
+                coins: Coins::Purse(index),
+            },
+        );
+    }
 }
 
-pub fn create_wallet(external: &str, internal: &str) -> Wallet {
-    let mut wallet: Wallet = Wallet::create(external.to_string(), internal.to_string())
-        .network(Network::Regtest)
-        .create_wallet_no_persist()
-        .unwrap();
+/// Hand each new trap its own receive address, so its revenue can be told apart in the player
+/// wallet's history.
+fn assign_trap_addresses(mut trap_q: Query<&mut TouristTrap>, player_q: Query<&PlayerWallet>) {
+    if trap_q.iter().all(|trap| trap.address.is_some()) {
+        return;
+    }
+    let Ok(player) = player_q.single() else {
+        return;
+    };
+    let Ok(mut wallet) = player.wallet.lock() else {
+        return;
+    };
+
+    for mut trap in &mut trap_q {
+        if trap.address.is_some() {
+            continue;
+        }
+        let mut address = wallet.reveal_next_address(KeychainKind::External);
+        if address.index == UNSORTED_REVENUE {
+            address = wallet.reveal_next_address(KeychainKind::External);
+        }
+        info!("Trap paid at {} (index {})", address.address, address.index);
+        trap.address = Some(address.address.into_unchecked());
+    }
 
-    let address: AddressInfo = wallet.reveal_next_address(KeychainKind::External);
-    info!(
-        "Generated address {} at index {}",
-        address.address, address.index
-    );
+    // The map only saves when the player asks, but the wallet won't hand these out again
+    if let Err(err) = wallet.persist() {
+        warn!("Failed to save the player wallet: {err}");
+    }
+}
 
-    // Create the Electrum client
-    let client: BdkElectrumClient<Client> =
-        BdkElectrumClient::new(electrum_client::Client::new("127.0.0.1:60401").unwrap());
+pub fn activate_wallet(
+    config: &NodeConfig,
+    keys: &SlotKeys,
+    chain: &dyn ChainBackend,
+) -> eyre::Result<(StoredWallet, StoredWallet)> {
+    let tourist_wallet = create_wallet(config, chain, WalletId::Tourist.name(), &keys.tourist)?;
+    let player_descriptors = match config.player_signer {
+        PlayerSigner::Internal => keys.player.clone(),
+        PlayerSigner::External => keys.player.watch_only()?,
+    };
+    let player_wallet = create_wallet(config, chain, WalletId::Player.name(), &player_descriptors)?;
+    Ok((tourist_wallet, player_wallet))
+}
 
-    // Perform the initial full scan on the wallet
-    let full_scan_request = wallet.start_full_scan();
-    let update = client
-        .full_scan(full_scan_request, STOP_GAP, BATCH_SIZE, true)
-        .unwrap();
+/// Load the named wallet from its db, or create it and do a full scan on the first run.
+pub fn create_wallet(
+    config: &NodeConfig,
+    chain: &dyn ChainBackend,
+    name: &str,
+    descriptors: &WalletDescriptors,
+) -> eyre::Result<StoredWallet> {
+    let (loaded, mut db) = load_wallet(config, name, descriptors)?;
+
+    let wallet = match loaded {
+        Some(mut wallet) => {
+            info!("Loaded wallet `{name}`");
+
+            // Catch up on anything that happened while the game was closed
+            let sync_request = wallet.start_sync_with_revealed_spks().build();
+            wallet.apply_update(chain.sync(sync_request)?)?;
+            wallet
+        }
+        None => {
+            let mut wallet =
+                Wallet::create(descriptors.external.clone(), descriptors.internal.clone())
+                    .network(config.network)
+                    .create_wallet(&mut db)?;
+
+            let address: AddressInfo = wallet.reveal_next_address(KeychainKind::External);
+            info!(
+                "Generated address {} at index {}",
+                address.address, address.index
+            );
+
+            // Perform the initial full scan on the wallet
+            let full_scan_request = wallet.start_full_scan().build();
+            wallet.apply_update(chain.full_scan(full_scan_request, STOP_GAP)?)?;
+            wallet
+        }
+    };
 
-    wallet.apply_update(update).unwrap();
     let balance = wallet.balance();
     println!("Wallet balance: {} sat", balance.total().to_sat());
 
-    wallet
+    let mut wallet = StoredWallet { wallet, db };
+    wallet.persist()?;
+    Ok(wallet)
 }
 
-pub fn spawn_electrs() -> Result<(Child, PathBuf, PathBuf)> {
-    let electrs_path = "electrs";
-    let conf_path = get_config_dir()?.join("bitcoin.conf");
-    let data_dir = get_data_dir(Some(BITCOIN_DIR.into()))?;
-    let db_dir = get_data_dir(Some("electrs_db".into()))?;
+pub fn spawn_electrs(
+    config: &NodeConfig,
+    keys: Option<&SlotKeys>,
+) -> Result<(Child, PathBuf, PathBuf)> {
+    let electrs_path = config.electrs.display();
+    let data_dir = config.bitcoind_dir()?;
+    let db_dir = config.electrs_db_dir()?;
 
     let data_dir_arg = format!("{}", data_dir.display());
-    let conf_path_arg = format!("{}", conf_path.display());
     let db_dir_arg = format!("{}", db_dir.display());
 
-    let mut child = std::process::Command::new(electrs_path)
+    let mut command = std::process::Command::new(&config.electrs);
+    if let Some(challenge) = signet_challenge(config, keys)? {
+        // electrs only knows the default signet's magic
+        command.arg("--signet-magic").arg(signet_magic(&challenge));
+    }
+    let mut child = command
         .arg("--network")
-        .arg("regtest")
+        .arg(electrs_network(config.network)?)
         .arg("--db-dir")
         .arg(db_dir_arg)
+        // bitcoin.conf now has network sections electrs can't parse, so it finds the cookie
+        // through the daemon dir instead
         .arg("--daemon-dir")
         .arg(data_dir_arg)
-        .arg("--conf")
-        .arg(conf_path_arg)
+        .arg("--daemon-rpc-addr")
+        .arg(format!("127.0.0.1:{}", config.rpc_port))
+        .arg("--daemon-p2p-addr")
+        .arg(format!("127.0.0.1:{}", config.p2p_port))
         .arg("--electrum-rpc-addr")
-        .arg("127.0.0.1:60401")
+        .arg(config.electrum_addr())
+        .arg("--monitoring-addr")
+        .arg(format!("127.0.0.1:{}", config.monitoring_port))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .expect("Failed to start electrs");
+        .map_err(|err| eyre::eyre!("Failed to start {electrs_path}: {err}"))?;
 
     let stdout = child.stdout.take();
     let stderr = child.stderr.take();
@@ -280,7 +514,27 @@ pub fn spawn_electrs() -> Result<(Child, PathBuf, PathBuf)> {
     }
 
     info!("{electrs_path} process started with PID: {}", child.id());
-    Ok((child, data_dir, conf_path))
+    Ok((child, data_dir, db_dir))
+}
+
+/// Block until electrs accepts connections on its electrum port.
+pub fn wait_for_electrum(config: &NodeConfig, timeout: Duration) -> eyre::Result<()> {
+    let addr = config
+        .electrum_addr()
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| eyre::eyre!("No address for {}", config.electrum_addr()))?;
+    let start = Instant::now();
+
+    while TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_err() {
+        if start.elapsed() > timeout {
+            return Err(eyre::eyre!("Timed out waiting for electrs on {addr}"));
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    info!("electrs is serving on {addr}");
+    Ok(())
 }
 
 #[derive(Resource)]
@@ -319,3 +573,46 @@ impl Drop for ElectrsProcess {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Network;
+    use signet_miner::grinder::Cancel;
+
+    use super::*;
+    use crate::sim_chain::SimChain;
+
+    const XPRIV: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+
+    #[test]
+    fn purses_with_coins_are_handed_out_again() {
+        let chain = SimChain::new();
+        let mut wallet = Wallet::create(
+            format!("tr({XPRIV}/86h/1h/1h/0/*)"),
+            format!("tr({XPRIV}/86h/1h/1h/1/*)"),
+        )
+        .network(Network::Regtest)
+        .create_wallet_no_persist()
+        .unwrap();
+        // Purse 1 never got any coins
+        for index in [RESERVE_PURSE, 2, 3] {
+            let address = wallet.peek_address(KeychainKind::External, index).address;
+            chain
+                .mine_blocks(1, &address, None, &Cancel::default())
+                .unwrap();
+        }
+        let request = wallet.start_full_scan().build();
+        wallet
+            .apply_update(chain.full_scan(request, STOP_GAP).unwrap())
+            .unwrap();
+
+        let mut purses = TouristPurses::default();
+        purses.assigned.insert(Entity::from_raw(1), 3);
+        purses.reclaim(&wallet);
+        assert_eq!(purses.free, [2]);
+
+        // Reclaiming again doesn't hand a purse out twice
+        purses.reclaim(&wallet);
+        assert_eq!(purses.free, [2]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bdk_wallet::SignOptions;
+use bevy::prelude::*;
+use bitcoin::{FeeRate, OutPoint, Psbt, Txid};
+
+use crate::chain_backend::ChainBackend;
+use crate::electrum_wallet::{SharedWallet, WalletId};
+use crate::payments::{self, PaymentError, PaymentIntent, build_error, wallet_error};
+
+/// A PSBT round trip through an external signer, run on the payment worker.
+pub enum PsbtJob {
+    /// Build an unsigned PSBT paying `intent` and write it into `dir`.
+    Export {
+        wallet: SharedWallet,
+        intent: PaymentIntent,
+        dir: PathBuf,
+    },
+    /// Read a signed PSBT back from `path`, finalize it and broadcast it.
+    Import { wallet: SharedWallet, path: PathBuf },
+    /// Give up on the exported PSBT `txid`, freeing its coins.
+    Cancel { txid: Txid },
+}
+
+#[derive(Clone, Debug)]
+pub enum PsbtDone {
+    /// The signer is expected to write the signed PSBT to `signed`.
+    Exported {
+        txid: Txid,
+        unsigned: PathBuf,
+        signed: PathBuf,
+    },
+    Broadcast {
+        txid: Txid,
+    },
+}
+
+/// How a PSBT job went, sent once the worker is done with it.
+#[derive(Event, Clone, Debug)]
+pub struct PsbtResult {
+    pub outcome: Result<PsbtDone, PaymentError>,
+}
+
+/// The coins of exported PSBTs that haven't come back signed, by txid. Nothing else spends them
+/// until their PSBT is broadcast or cancelled.
+#[derive(Default)]
+pub struct LockedCoins(HashMap<Txid, Vec<OutPoint>>);
+
+impl LockedCoins {
+    fn all(&self) -> Vec<OutPoint> {
+        self.0.values().flatten().copied().collect()
+    }
+
+    pub fn unlock(&mut self, txid: Txid) {
+        if self.0.remove(&txid).is_some() {
+            info!("Unlocked the coins of PSBT {txid}");
+        }
+    }
+}
+
+/// Write an unsigned PSBT as base64 to `<txid>.psbt`, locking the coins it spends.
+pub fn export(
+    wallet: &SharedWallet,
+    intent: &PaymentIntent,
+    fee_rate: FeeRate,
+    dir: &Path,
+    locked: &mut LockedCoins,
+) -> Result<PsbtDone, PaymentError> {
+    let psbt = {
+        let mut stored = wallet.lock().map_err(wallet_error)?;
+        let mut builder = stored.build_tx();
+        builder
+            .fee_rate(fee_rate)
+            .add_recipient(intent.to.script_pubkey(), intent.amount)
+            .unspendable(locked.all());
+        let psbt = builder.finish().map_err(build_error)?;
+
+        // Building the tx may have revealed a change address
+        if let Err(err) = stored.persist() {
+            warn!("Failed to save the {} wallet: {err}", intent.from.name());
+        }
+        psbt
+    };
+
+    let txid = psbt.unsigned_tx.compute_txid();
+    let unsigned = dir.join(format!("{txid}.psbt"));
+    let signed = dir.join(format!("{txid}-signed.psbt"));
+    fs::write(&unsigned, psbt.to_string()).map_err(|err| {
+        PaymentError::File(format!("Failed to write {}: {err}", unsigned.display()))
+    })?;
+    info!("Wrote the unsigned PSBT to {}", unsigned.display());
+
+    let inputs = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect();
+    locked.0.insert(txid, inputs);
+    Ok(PsbtDone::Exported {
+        txid,
+        unsigned,
+        signed,
+    })
+}
+
+/// Finalize and broadcast the signed PSBT at `path`, unlocking its coins once it's out.
+pub fn import(
+    chain: &dyn ChainBackend,
+    wallet: &SharedWallet,
+    path: &Path,
+    locked: &mut LockedCoins,
+) -> Result<PsbtDone, PaymentError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| PaymentError::File(format!("Failed to read {}: {err}", path.display())))?;
+    let mut psbt = Psbt::from_str(contents.trim()).map_err(|err| {
+        PaymentError::File(format!("{} isn't a base64 PSBT: {err}", path.display()))
+    })?;
+
+    let tx = {
+        let stored = wallet.lock().map_err(wallet_error)?;
+        let finalized = stored
+            .finalize_psbt(&mut psbt, SignOptions::default())
+            .map_err(|err| PaymentError::Sign(err.to_string()))?;
+        if !finalized {
+            return Err(PaymentError::Sign(
+                "the PSBT isn't fully signed".to_string(),
+            ));
+        }
+        psbt.extract_tx()
+            .map_err(|err| PaymentError::Build(err.to_string()))?
+    };
+
+    let txid = payments::broadcast(chain, wallet, WalletId::Player, tx)?;
+    locked.unlock(txid);
+    Ok(PsbtDone::Broadcast { txid })
+}
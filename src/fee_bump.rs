@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+
+use bdk_wallet::coin_selection::DefaultCoinSelectionAlgorithm;
+use bdk_wallet::{TxBuilder, Wallet};
+use bevy::prelude::*;
+use bitcoin::{Amount, FeeRate, OutPoint, ScriptBuf, Txid};
+
+use crate::chain_backend::ChainBackend;
+use crate::electrum_wallet::{PlayerWallet, SharedWallet, TouristWallet, WalletId};
+use crate::game_state::GameState;
+use crate::node_config::{NodeConfig, PlayerSigner};
+use crate::payments::{
+    self, PaymentError, PaymentId, PaymentIntent, PaymentQueue, PaymentResult, build_error,
+    purse_coins, wallet_error,
+};
+use crate::wallet_sync::WalletSynced;
+
+/// Keeps an eye on the tourists' payments and bumps the ones that don't confirm, so a congested
+/// chain doesn't leave the game's economy stuck.
+pub struct FeeBumps;
+
+impl Plugin for FeeBumps {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UnconfirmedPayments>().add_systems(
+            Update,
+            (track_payments, track_bumps, bump_stuck_payments)
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// How many blocks a payment can go unconfirmed before it's bumped.
+const BUMP_AFTER_BLOCKS: u32 = 3;
+
+/// Each bump at least doubles the fee rate, whatever the estimate says.
+const BUMP_MULTIPLIER: u64 = 2;
+
+/// A tourist payment that's out but not confirmed.
+#[derive(Clone, Debug)]
+pub struct StuckPayment {
+    pub id: PaymentId,
+    pub intent: PaymentIntent,
+    /// The latest replacement, if it's been replaced.
+    pub txid: Txid,
+    pub fee: Amount,
+    /// What it, or it along with its child, pays.
+    pub fee_rate: FeeRate,
+    /// The player wallet's child paying for it, once it's had one.
+    pub child: Option<Txid>,
+    /// Whether other payments share its transaction.
+    pub batched: bool,
+    /// Whether its transaction pays the player wallet anything, for a child to spend.
+    pub pays_player: bool,
+}
+
+/// A stuck payment for the payment worker to bump.
+pub struct BumpJob {
+    pub payment: StuckPayment,
+    pub tourist: SharedWallet,
+    pub player: SharedWallet,
+    /// Whether the game holds the player wallet's keys, so it can sign a child.
+    pub player_signs: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BumpKind {
+    /// The tourist wallet sent a replacement paying a higher fee.
+    Rbf,
+    /// The player wallet spent what it got from the payment, paying for both.
+    Cpfp,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bumped {
+    pub kind: BumpKind,
+    /// The replacement, or the child.
+    pub txid: Txid,
+    pub fee: Amount,
+    pub fee_rate: FeeRate,
+    pub height: u32,
+}
+
+/// How a bump went, sent once the worker is done with it.
+#[derive(Event, Clone, Debug)]
+pub struct BumpResult {
+    pub payment: StuckPayment,
+    pub outcome: Result<Bumped, PaymentError>,
+}
+
+struct Unconfirmed {
+    payment: StuckPayment,
+    /// The height it was sent or last bumped at.
+    since: u32,
+    bumping: bool,
+}
+
+impl Unconfirmed {
+    /// Whether it's waited long enough by `tip` to be bumped.
+    fn due(&self, tip: u32) -> bool {
+        !self.bumping && tip >= self.since + BUMP_AFTER_BLOCKS
+    }
+
+    /// The bump went to the worker at `tip`. If it fails, it waits out another round of blocks
+    /// before it's tried again.
+    fn bumping_at(&mut self, tip: u32) {
+        self.bumping = true;
+        self.since = tip;
+    }
+}
+
+/// The tourist payments that haven't confirmed yet.
+#[derive(Resource, Default)]
+pub struct UnconfirmedPayments(HashMap<PaymentId, Unconfirmed>);
+
+/// The fee rate a bump pays, given what the payment paid and what's estimated now.
+pub fn bump_fee_rate(previous: FeeRate, estimate: FeeRate) -> FeeRate {
+    let doubled = previous
+        .checked_mul(BUMP_MULTIPLIER)
+        .unwrap_or(FeeRate::MAX);
+    estimate.max(doubled)
+}
+
+/// How a stuck payment gets bumped: by replacing it while nothing else would be lost with it,
+/// otherwise by a child from the player wallet. `None` if neither is open to it.
+pub fn bump_kind(payment: &StuckPayment, player_signs: bool) -> Option<BumpKind> {
+    // Replacing it would throw away its child, or the rest of its batch
+    if payment.child.is_none() && !payment.batched {
+        Some(BumpKind::Rbf)
+    } else if player_signs && payment.pays_player {
+        Some(BumpKind::Cpfp)
+    } else {
+        None
+    }
+}
+
+fn track_payments(
+    mut payment_e: EventReader<PaymentResult>,
+    mut unconfirmed: ResMut<UnconfirmedPayments>,
+) {
+    for result in payment_e.read() {
+        let Ok(sent) = &result.outcome else {
+            continue;
+        };
+        if result.intent.from != WalletId::Tourist {
+            continue;
+        }
+        // A batch is bumped as a whole, through whichever of its payments came back first
+        if unconfirmed
+            .0
+            .values()
+            .any(|entry| entry.payment.txid == sent.txid)
+        {
+            continue;
+        }
+
+        let payment = StuckPayment {
+            id: result.id,
+            intent: result.intent.clone(),
+            txid: sent.txid,
+            fee: sent.fee,
+            fee_rate: sent.fee_rate,
+            child: None,
+            batched: sent.batch.is_some_and(|batch| batch.payments > 1),
+            // Found out once it's due a bump, since the player wallet may not have it yet
+            pays_player: false,
+        };
+        unconfirmed.0.insert(
+            result.id,
+            Unconfirmed {
+                payment,
+                since: sent.height,
+                bumping: false,
+            },
+        );
+    }
+}
+
+fn track_bumps(mut bump_e: EventReader<BumpResult>, mut unconfirmed: ResMut<UnconfirmedPayments>) {
+    for result in bump_e.read() {
+        let Some(entry) = unconfirmed.0.get_mut(&result.payment.id) else {
+            continue;
+        };
+        entry.bumping = false;
+
+        match &result.outcome {
+            Ok(bumped) => {
+                info!(
+                    "Bumped payment {} ({}) with {:?} in {} (fee {} at {})",
+                    result.payment.id.0,
+                    result.payment.txid,
+                    bumped.kind,
+                    bumped.txid,
+                    bumped.fee,
+                    bumped.fee_rate
+                );
+                let payment = &mut entry.payment;
+                match bumped.kind {
+                    BumpKind::Rbf => {
+                        payment.txid = bumped.txid;
+                        payment.fee = bumped.fee;
+                    }
+                    BumpKind::Cpfp => payment.child = Some(bumped.txid),
+                }
+                payment.fee_rate = bumped.fee_rate;
+                entry.since = bumped.height;
+            }
+            // It's tried again once it's waited out another round of blocks
+            Err(err) => warn!(
+                "Couldn't bump payment {} ({}): {err}",
+                result.payment.id.0, result.payment.txid
+            ),
+        }
+    }
+}
+
+/// After each sync of the tourist wallet, forget the payments that confirmed and bump the ones
+/// that have waited too long.
+fn bump_stuck_payments(
+    mut synced_e: EventReader<WalletSynced>,
+    tourist_q: Query<&TouristWallet>,
+    player_q: Query<&PlayerWallet>,
+    mut unconfirmed: ResMut<UnconfirmedPayments>,
+    payments: Res<PaymentQueue>,
+    node_config: Res<NodeConfig>,
+) {
+    let tourist_synced = synced_e
+        .read()
+        .filter(|synced| synced.wallet == WalletId::Tourist)
+        .count();
+    if tourist_synced == 0 || unconfirmed.0.is_empty() {
+        return;
+    }
+
+    let (Ok(tourist), Ok(player)) = (tourist_q.single(), player_q.single()) else {
+        return;
+    };
+    let (Ok(stored), Ok(player_wallet)) = (tourist.wallet.lock(), player.wallet.lock()) else {
+        return;
+    };
+    let tip = stored.latest_checkpoint().height();
+    // An outside signer can't be waited on for a child
+    let player_signs = node_config.player_signer == PlayerSigner::Internal;
+
+    unconfirmed.0.retain(|id, entry| {
+        let Some(tx) = stored.get_tx(entry.payment.txid) else {
+            // Replaced by someone else, or dropped from the mempool
+            debug!("Payment {} ({}) is gone", id.0, entry.payment.txid);
+            return false;
+        };
+        if tx.chain_position.is_confirmed() {
+            return false;
+        }
+
+        if !entry.due(tip) {
+            return true;
+        }
+        // Top-ups pay a tourist purse, which leaves a child nothing to spend
+        entry.payment.pays_player = tx
+            .tx_node
+            .tx
+            .output
+            .iter()
+            .any(|output| player_wallet.is_mine(output.script_pubkey.clone()));
+        if bump_kind(&entry.payment, player_signs).is_some()
+            && payments.bump(BumpJob {
+                payment: entry.payment.clone(),
+                tourist: tourist.wallet.clone(),
+                player: player.wallet.clone(),
+                player_signs,
+            })
+        {
+            entry.bumping_at(tip);
+        }
+        true
+    });
+}
+
+/// Replace the payment if the tourist wallet can, otherwise have the player wallet pay for it
+/// with a child. Blocks on the chain backend, so it runs on the payment worker.
+pub fn bump(
+    chain: &dyn ChainBackend,
+    job: &BumpJob,
+    fee_rate: FeeRate,
+) -> Result<Bumped, PaymentError> {
+    match bump_kind(&job.payment, job.player_signs) {
+        Some(BumpKind::Rbf) => match replace(chain, job, fee_rate) {
+            Ok(bumped) => Ok(bumped),
+            Err(err) if job.player_signs && job.payment.pays_player => {
+                debug!(
+                    "Can't replace payment {}, paying for it with a child: {err}",
+                    job.payment.id.0
+                );
+                pay_for_parent(chain, job, fee_rate)
+            }
+            Err(err) => Err(err),
+        },
+        Some(BumpKind::Cpfp) => pay_for_parent(chain, job, fee_rate),
+        None => Err(PaymentError::Build(
+            "it can't be replaced and the player wallet is watch-only".to_string(),
+        )),
+    }
+}
+
+/// RBF: resend the payment from the same coins with a higher fee.
+fn replace(
+    chain: &dyn ChainBackend,
+    job: &BumpJob,
+    fee_rate: FeeRate,
+) -> Result<Bumped, PaymentError> {
+    let payment = &job.payment;
+    let intent = &payment.intent;
+
+    let (tx, fee, height) = {
+        let mut stored = job.tourist.lock().map_err(wallet_error)?;
+
+        // The tourist may have paid other traps from its change since, and those would go too
+        let has_descendants = stored
+            .tx_graph()
+            .walk_descendants(payment.txid, |_, descendant| Some(descendant))
+            .any(|descendant| stored.get_tx(descendant).is_some());
+        if has_descendants {
+            return Err(PaymentError::Build(
+                "later payments spend its change".to_string(),
+            ));
+        }
+
+        // The replacement can't spend what the payment it replaces created
+        let outputs = stored
+            .get_tx(payment.txid)
+            .map_or(0, |tx| tx.tx_node.tx.output.len() as u32);
+        let mut unspendable: Vec<OutPoint> = (0..outputs)
+            .map(|vout| OutPoint::new(payment.txid, vout))
+            .collect();
+        let purse = purse_coins(&stored, intent.coins);
+        if let Some((others, _)) = &purse {
+            unspendable.extend(others);
+        }
+
+        let mut builder = stored
+            .build_fee_bump(payment.txid)
+            .map_err(|err| PaymentError::Build(err.to_string()))?;
+        builder
+            .fee_rate(fee_rate)
+            // Only the wallet's internal change is recognised as change, so a purse's change
+            // would otherwise be kept as a payment
+            .set_recipients(vec![(intent.to.script_pubkey(), intent.amount)])
+            .unspendable(unspendable);
+        if let Some((_, change)) = purse {
+            builder.drain_to(change);
+        }
+        let psbt = builder.finish().map_err(build_error)?;
+
+        let (tx, fee) = payments::sign(&mut stored, psbt, WalletId::Tourist)?;
+        (tx, fee, stored.latest_checkpoint().height())
+    };
+
+    let txid = payments::broadcast(chain, &job.tourist, WalletId::Tourist, tx)?;
+    Ok(Bumped {
+        kind: BumpKind::Rbf,
+        txid,
+        fee,
+        fee_rate,
+        height,
+    })
+}
+
+/// CPFP: spend the player wallet's outputs of the payment back to the same addresses, with a fee
+/// that brings the pair up to `fee_rate`. A payment that already has a child gets it replaced.
+fn pay_for_parent(
+    chain: &dyn ChainBackend,
+    job: &BumpJob,
+    fee_rate: FeeRate,
+) -> Result<Bumped, PaymentError> {
+    let payment = &job.payment;
+
+    let (parent, height) = {
+        let stored = job.tourist.lock().map_err(wallet_error)?;
+        let parent = stored
+            .get_tx(payment.txid)
+            .ok_or_else(|| PaymentError::Build(format!("{} isn't in the wallet", payment.txid)))?
+            .tx_node
+            .tx
+            .clone();
+        (parent, stored.latest_checkpoint().height())
+    };
+
+    let (tx, fee) = {
+        let mut player = job.player.lock().map_err(wallet_error)?;
+
+        // The player wallet may not have synced the payment yet
+        player.apply_unconfirmed_txs([(parent.clone(), payments::unix_now())]);
+
+        let outputs: Vec<OutPoint> = parent
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| player.is_mine(output.script_pubkey.clone()))
+            .map(|(vout, _)| OutPoint::new(payment.txid, vout as u32))
+            .collect();
+        let drain = outputs
+            .first()
+            .map(|outpoint| parent.output[outpoint.vout as usize].script_pubkey.clone())
+            .ok_or_else(|| {
+                PaymentError::Build("it pays nothing to the player wallet".to_string())
+            })?;
+
+        // The child's weight tells what it has to pay on top of the parent's fee
+        let child_weight = match payment.child {
+            Some(child) => player
+                .get_tx(child)
+                .ok_or_else(|| PaymentError::Build(format!("{child} isn't in the wallet")))?
+                .tx_node
+                .tx
+                .weight(),
+            None => {
+                let mut builder = child_builder(&mut player, &outputs, &drain, None)?;
+                builder.fee_rate(fee_rate);
+                let psbt = builder.finish().map_err(build_error)?;
+                let (draft, _) = payments::sign(&mut player, psbt, WalletId::Player)?;
+                draft.weight()
+            }
+        };
+        let package_fee = fee_rate
+            .fee_wu(parent.weight() + child_weight)
+            .ok_or_else(|| PaymentError::Build("the fee overflows".to_string()))?;
+        let child_fee = package_fee
+            .checked_sub(payment.fee)
+            .filter(|fee| *fee > Amount::ZERO)
+            .ok_or_else(|| PaymentError::Build(format!("it already pays {fee_rate} on its own")))?;
+
+        let mut builder = child_builder(&mut player, &outputs, &drain, payment.child)?;
+        builder.fee_absolute(child_fee);
+        let psbt = builder.finish().map_err(build_error)?;
+        payments::sign(&mut player, psbt, WalletId::Player)?
+    };
+
+    let txid = payments::broadcast(chain, &job.player, WalletId::Player, tx)?;
+    Ok(Bumped {
+        kind: BumpKind::Cpfp,
+        txid,
+        fee,
+        fee_rate,
+        height,
+    })
+}
+
+/// A tx spending just `outputs` to `drain`, replacing the earlier child if there's one.
+fn child_builder<'a>(
+    player: &'a mut Wallet,
+    outputs: &[OutPoint],
+    drain: &ScriptBuf,
+    replacing: Option<Txid>,
+) -> Result<TxBuilder<'a, DefaultCoinSelectionAlgorithm>, PaymentError> {
+    let build = |err: &dyn std::fmt::Display| PaymentError::Build(err.to_string());
+
+    let mut builder = match replacing {
+        Some(child) => {
+            let mut builder = player.build_fee_bump(child).map_err(|err| build(&err))?;
+            builder.set_recipients(Vec::new());
+            builder
+        }
+        None => {
+            let mut builder = player.build_tx();
+            builder.add_utxos(outputs).map_err(|err| build(&err))?;
+            builder
+        }
+    };
+    builder.manually_selected_only().drain_to(drain.clone());
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Address, Network, Script};
+
+    use super::*;
+    use crate::fees::FeePolicy;
+    use crate::payments::Coins;
+
+    fn sat_vb(rate: u64) -> FeeRate {
+        FeeRate::from_sat_per_vb_unchecked(rate)
+    }
+
+    fn stuck(batched: bool, child: Option<Txid>) -> StuckPayment {
+        paying(batched, child, true)
+    }
+
+    fn paying(batched: bool, child: Option<Txid>, pays_player: bool) -> StuckPayment {
+        StuckPayment {
+            id: PaymentId(1),
+            intent: PaymentIntent {
+                from: WalletId::Tourist,
+                to: Address::p2wsh(Script::new(), Network::Regtest),
+                amount: Amount::from_sat(10_000),
+                fee_policy: FeePolicy::Normal,
+                coins: Coins::Purse(0),
+            },
+            txid: Txid::from_byte_array([1; 32]),
+            fee: Amount::from_sat(200),
+            fee_rate: sat_vb(2),
+            child,
+            batched,
+            pays_player,
+        }
+    }
+
+    #[test]
+    fn bumps_at_least_double() {
+        assert_eq!(bump_fee_rate(sat_vb(5), sat_vb(3)), sat_vb(10));
+        assert_eq!(bump_fee_rate(sat_vb(5), FeeRate::ZERO), sat_vb(10));
+    }
+
+    #[test]
+    fn bumps_never_below_the_estimate() {
+        assert_eq!(bump_fee_rate(sat_vb(5), sat_vb(40)), sat_vb(40));
+    }
+
+    #[test]
+    fn bumps_saturate() {
+        assert_eq!(bump_fee_rate(FeeRate::MAX, sat_vb(1)), FeeRate::MAX);
+    }
+
+    #[test]
+    fn lone_payments_are_replaced() {
+        assert_eq!(bump_kind(&stuck(false, None), true), Some(BumpKind::Rbf));
+        assert_eq!(bump_kind(&stuck(false, None), false), Some(BumpKind::Rbf));
+    }
+
+    #[test]
+    fn batched_payments_get_a_child() {
+        assert_eq!(bump_kind(&stuck(true, None), true), Some(BumpKind::Cpfp));
+    }
+
+    #[test]
+    fn payments_with_a_child_get_it_replaced() {
+        let child = Some(Txid::from_byte_array([2; 32]));
+        assert_eq!(bump_kind(&stuck(false, child), true), Some(BumpKind::Cpfp));
+    }
+
+    #[test]
+    fn watch_only_players_never_pay_for_parents() {
+        assert_eq!(bump_kind(&stuck(true, None), false), None);
+    }
+
+    #[test]
+    fn top_ups_never_get_a_child() {
+        assert_eq!(bump_kind(&paying(true, None, false), true), None);
+        let child = Some(Txid::from_byte_array([2; 32]));
+        assert_eq!(bump_kind(&paying(false, child, false), true), None);
+        assert_eq!(
+            bump_kind(&paying(false, None, false), true),
+            Some(BumpKind::Rbf)
+        );
+    }
+
+    #[test]
+    fn failed_bumps_wait_another_round() {
+        let mut entry = Unconfirmed {
+            payment: stuck(false, None),
+            since: 10,
+            bumping: false,
+        };
+        assert!(!entry.due(12));
+        assert!(entry.due(13));
+
+        entry.bumping_at(13);
+        assert!(!entry.due(16));
+
+        // What track_bumps does with a failure
+        entry.bumping = false;
+        assert!(!entry.due(14));
+        assert!(!entry.due(15));
+        assert!(entry.due(16));
+    }
+}
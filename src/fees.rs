@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bitcoin::FeeRate;
+
+use crate::chain_backend::ChainBackend;
+
+/// Estimates barely move between blocks, so one is reused for a while.
+const ESTIMATE_TTL: Duration = Duration::from_secs(60);
+
+/// Nothing below this gets relayed.
+const MIN_RELAY_FEE: FeeRate = FeeRate::from_sat_per_vb_unchecked(1);
+
+/// How soon a payment should confirm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FeePolicy {
+    /// Within a day or so.
+    Economy,
+    /// Within the hour.
+    #[default]
+    Normal,
+    /// In the next block or two.
+    Urgent,
+}
+
+impl FeePolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Economy => "economy",
+            Self::Normal => "normal",
+            Self::Urgent => "urgent",
+        }
+    }
+
+    /// The confirmation target, in blocks, the estimate is asked for.
+    fn target_blocks(&self) -> u16 {
+        match self {
+            Self::Economy => 144,
+            Self::Normal => 6,
+            Self::Urgent => 2,
+        }
+    }
+
+    /// What's paid when the backend has no estimate, as on regtest, where there's no fee market
+    /// to learn from.
+    fn fallback(&self) -> FeeRate {
+        match self {
+            Self::Economy => FeeRate::from_sat_per_vb_unchecked(1),
+            Self::Normal => FeeRate::from_sat_per_vb_unchecked(2),
+            Self::Urgent => FeeRate::from_sat_per_vb_unchecked(5),
+        }
+    }
+}
+
+/// Picks the fee rate for each policy, asking the chain backend at most once per
+/// [`ESTIMATE_TTL`]. It blocks on the backend, so it belongs on a worker thread.
+#[derive(Default)]
+pub struct FeeEstimator {
+    cache: HashMap<FeePolicy, (Instant, FeeRate)>,
+}
+
+impl FeeEstimator {
+    pub fn fee_rate(&mut self, chain: &dyn ChainBackend, policy: FeePolicy) -> FeeRate {
+        if let Some((at, fee_rate)) = self.cache.get(&policy)
+            && at.elapsed() < ESTIMATE_TTL
+        {
+            return *fee_rate;
+        }
+
+        let fee_rate = match chain.estimate_fee(policy.target_blocks()) {
+            Ok(Some(estimate)) => estimate.max(MIN_RELAY_FEE),
+            Ok(None) => policy.fallback(),
+            Err(err) => {
+                warn!(
+                    "Couldn't estimate the {} fee rate, using the fallback: {err}",
+                    policy.label()
+                );
+                policy.fallback()
+            }
+        };
+        self.cache.insert(policy, (Instant::now(), fee_rate));
+        fee_rate
+    }
+}
+
+/// bitcoind and electrum estimates are in BTC/kvB, and negative when there's no estimate.
+pub fn fee_rate_from_btc_per_kvb(btc_per_kvb: f64) -> Option<FeeRate> {
+    if !btc_per_kvb.is_finite() || btc_per_kvb <= 0.0 {
+        return None;
+    }
+    // 1 kvB is 4 kwu
+    let sat_per_kwu = btc_per_kvb * 100_000_000.0 / 4.0;
+    Some(FeeRate::from_sat_per_kwu(sat_per_kwu.round() as u64))
+}
@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+
+/// Where the game is in bringing up its node stack.
+#[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GameState {
+    /// bitcoind is starting and the chain is being prepared.
+    #[default]
+    Booting,
+    /// electrs is indexing and the wallets are scanning.
+    Syncing,
+    Playing,
+}
+
+/// The steps of bringing up the node stack, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BootStep {
+    BitcoindSpawned,
+    RpcReady,
+    SeedUnlocked,
+    BlocksMined,
+    ElectrsIndexed,
+    WalletsScanned,
+}
+
+impl BootStep {
+    pub const ALL: [BootStep; 6] = [
+        BootStep::BitcoindSpawned,
+        BootStep::RpcReady,
+        BootStep::SeedUnlocked,
+        BootStep::BlocksMined,
+        BootStep::ElectrsIndexed,
+        BootStep::WalletsScanned,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BootStep::BitcoindSpawned => "bitcoind spawned",
+            BootStep::RpcReady => "RPC ready",
+            BootStep::SeedUnlocked => "Seed unlocked",
+            BootStep::BlocksMined => "Initial blocks mined",
+            BootStep::ElectrsIndexed => "electrs indexed",
+            BootStep::WalletsScanned => "Wallets scanned",
+        }
+    }
+
+    /// The state the game is in once this step is done.
+    pub fn state_after(self) -> GameState {
+        match self {
+            BootStep::BitcoindSpawned | BootStep::RpcReady | BootStep::SeedUnlocked => {
+                GameState::Booting
+            }
+            BootStep::BlocksMined | BootStep::ElectrsIndexed => GameState::Syncing,
+            BootStep::WalletsScanned => GameState::Playing,
+        }
+    }
+}
+
+/// Progress reported by the boot task.
+#[derive(Event, Clone, Debug)]
+pub enum BootEvent {
+    Done(BootStep),
+    Failed(String),
+}
@@ -0,0 +1,408 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bevy::prelude::*;
+use bip39::Mnemonic;
+use bitcoin::bip32::Xpriv;
+use bitcoin::key::Secp256k1;
+use bitcoin::{Address, Network};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use eyre::{Result, eyre};
+use miniscript::Descriptor;
+use miniscript::descriptor::DescriptorPublicKey;
+use miniscript::descriptor::checksum::desc_checksum;
+use serde::{Deserialize, Serialize};
+
+use crate::bdk_zone::get_config_dir;
+use crate::constants::KEYS_DIR;
+use crate::node_config::NodeConfig;
+
+/// BIP86 accounts within a slot's seed.
+const PLAYER_ACCOUNT: u32 = 0;
+const TOURIST_ACCOUNT: u32 = 1;
+/// The private signet's block signing key, kept apart from the wallets' keys.
+const SIGNET_ACCOUNT: u32 = 2;
+
+/// 16 bytes of entropy makes a 12 word mnemonic.
+const ENTROPY_BYTES: usize = 16;
+
+const KEYSTORE_MAGIC: &[u8; 4] = b"TSKS";
+const KEYSTORE_VERSION: u8 = 1;
+const SALT_BYTES: usize = 16;
+const NONCE_BYTES: usize = 12;
+const KEY_BYTES: usize = 32;
+/// magic, version, three argon2 costs, salt, nonce
+const HEADER_BYTES: usize = 4 + 1 + 3 * 4 + SALT_BYTES + NONCE_BYTES;
+/// The most argon2 work a keystore header can ask for, so a damaged one can't hang the game or
+/// exhaust memory. Well above the defaults keystores are written with.
+const MAX_M_COST_KIB: u32 = 1 << 20;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// The receive and change descriptors of one wallet.
+#[derive(Clone)]
+pub struct WalletDescriptors {
+    pub external: String,
+    pub internal: String,
+}
+
+impl WalletDescriptors {
+    /// `tr(xprv/86h/coin'/account'/{0,1}/*)` with checksums.
+    fn bip86(master: &Xpriv, network: Network, account: u32) -> Result<Self> {
+        let coin_type = match network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let descriptor = |change: u32| -> Result<String> {
+            let descriptor = format!("tr({master}/86h/{coin_type}h/{account}h/{change}/*)");
+            let checksum = desc_checksum(&descriptor)?;
+            Ok(format!("{descriptor}#{checksum}"))
+        };
+
+        Ok(Self {
+            external: descriptor(0)?,
+            internal: descriptor(1)?,
+        })
+    }
+
+    /// The same descriptors with only the public keys, for a wallet that can't sign.
+    pub fn watch_only(&self) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let public = |descriptor: &str| -> Result<String> {
+            let (descriptor, _) =
+                Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)?;
+            // Display adds the checksum
+            Ok(descriptor.to_string())
+        };
+
+        Ok(Self {
+            external: public(&self.external)?,
+            internal: public(&self.internal)?,
+        })
+    }
+
+    /// The first receive address, worked out without loading the wallet.
+    pub fn first_address(&self, network: Network) -> Result<Address> {
+        let secp = Secp256k1::new();
+        let (descriptor, _) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &self.external)?;
+        Ok(descriptor.at_derivation_index(0)?.address(network)?)
+    }
+}
+
+/// The keys of a save slot, all derived from the slot's seed.
+///
+/// Each slot gets its own seed the first time it's played, so no two games share keys.
+#[derive(Resource, Clone)]
+pub struct SlotKeys {
+    pub player: WalletDescriptors,
+    pub tourist: WalletDescriptors,
+    /// Signs the private signet's blocks: `multi(1,xprv/86h/1h/2h/0/0)`, whose script is the
+    /// chain's challenge.
+    pub signet: String,
+}
+
+/// A mnemonic along with the BIP39 passphrase (if any) its seed is derived with.
+#[derive(Serialize, Deserialize)]
+struct Seed {
+    mnemonic: Mnemonic,
+    bip39_passphrase: String,
+}
+
+impl Seed {
+    fn keys(&self, network: Network) -> Result<SlotKeys> {
+        let seed = self.mnemonic.to_seed(&self.bip39_passphrase);
+        let master = Xpriv::new_master(network, &seed)?;
+        Ok(SlotKeys {
+            player: WalletDescriptors::bip86(&master, network, PLAYER_ACCOUNT)?,
+            tourist: WalletDescriptors::bip86(&master, network, TOURIST_ACCOUNT)?,
+            signet: format!("multi(1,{master}/86h/1h/{SIGNET_ACCOUNT}h/0/0)"),
+        })
+    }
+}
+
+/// What the player has to do before a slot's keys can be used.
+#[derive(Clone)]
+pub enum SlotLock {
+    /// The slot has a keystore to unlock.
+    Locked,
+    /// The slot is new; its keystore is created from a fresh or imported mnemonic.
+    New { import: Option<Mnemonic> },
+}
+
+impl SlotLock {
+    pub fn for_slot(config: &NodeConfig) -> Result<Self> {
+        if keystore_path(&config.slot)?.exists() {
+            return Ok(Self::Locked);
+        }
+
+        // Older versions left the mnemonic in plaintext; it gets moved into the keystore
+        let legacy = legacy_mnemonic_path(&config.slot)?;
+        let legacy = if legacy.exists() {
+            Some(read_mnemonic(&legacy)?)
+        } else {
+            None
+        };
+        let import = match &config.import_mnemonic {
+            Some(path) => Some(read_mnemonic(path)?),
+            None => None,
+        };
+        // Moving the legacy seed deletes it, so it can't be swapped for another along the way
+        if let (Some(legacy), Some(import)) = (&legacy, &import)
+            && legacy != import
+        {
+            return Err(eyre!(
+                "Slot `{}` already holds a different seed; import into a new slot",
+                config.slot
+            ));
+        }
+        let import = import.or(legacy);
+        Ok(Self::New { import })
+    }
+}
+
+/// Decrypt the slot's keystore.
+pub fn unlock_slot(config: &NodeConfig, passphrase: &str) -> Result<SlotKeys> {
+    let path = keystore_path(&config.slot)?;
+    let contents =
+        fs::read(&path).map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+    let seed: Seed = serde_json::from_slice(&decrypt(&contents, passphrase)?)?;
+
+    // A slot never has its seed swapped out from under its wallets
+    if let Some(import) = &config.import_mnemonic
+        && read_mnemonic(import)? != seed.mnemonic
+    {
+        return Err(eyre!(
+            "Slot `{}` already holds a different seed; import into a new slot",
+            config.slot
+        ));
+    }
+
+    seed.keys(config.network)
+}
+
+/// Write a new keystore for the slot, holding `import` or a freshly generated mnemonic.
+pub fn create_slot(
+    config: &NodeConfig,
+    import: Option<Mnemonic>,
+    passphrase: &str,
+    bip39_passphrase: &str,
+) -> Result<SlotKeys> {
+    if passphrase.is_empty() {
+        return Err(eyre!("Pick a passphrase to protect the seed"));
+    }
+
+    let mnemonic = match import {
+        Some(mnemonic) => mnemonic,
+        None => {
+            let entropy: [u8; ENTROPY_BYTES] = rand::random();
+            Mnemonic::from_entropy(&entropy)?
+        }
+    };
+    let seed = Seed {
+        mnemonic,
+        bip39_passphrase: bip39_passphrase.to_string(),
+    };
+
+    let path = keystore_path(&config.slot)?;
+    write_secret(&path, &encrypt(&serde_json::to_vec(&seed)?, passphrase)?)?;
+    info!("Wrote the keystore for slot `{}`", config.slot);
+
+    // Only drop the plaintext copy once the keystore is known to give the seed back
+    let written = fs::read(&path).map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+    let stored: Seed = serde_json::from_slice(&decrypt(&written, passphrase)?)?;
+    if stored.mnemonic != seed.mnemonic || stored.bip39_passphrase != seed.bip39_passphrase {
+        return Err(eyre!(
+            "The keystore at {} doesn't read back",
+            path.display()
+        ));
+    }
+
+    let legacy = legacy_mnemonic_path(&config.slot)?;
+    if legacy.exists() {
+        fs::remove_file(&legacy)?;
+        info!("Removed the plaintext seed at {}", legacy.display());
+    }
+
+    seed.keys(config.network)
+}
+
+/// Keystore layout: `TSKS`, a version byte, the argon2id memory, time and parallelism costs (u32
+/// little endian), the salt and the nonce, then the ChaCha20-Poly1305 ciphertext. The header is
+/// authenticated along with the ciphertext.
+fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let params = Params::default();
+    let salt: [u8; SALT_BYTES] = rand::random();
+    let nonce: [u8; NONCE_BYTES] = rand::random();
+
+    let mut header = Vec::with_capacity(HEADER_BYTES);
+    header.extend_from_slice(KEYSTORE_MAGIC);
+    header.push(KEYSTORE_VERSION);
+    header.extend_from_slice(&params.m_cost().to_le_bytes());
+    header.extend_from_slice(&params.t_cost().to_le_bytes());
+    header.extend_from_slice(&params.p_cost().to_le_bytes());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+
+    let cipher = cipher(passphrase, &salt, params)?;
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| eyre!("Failed to encrypt the keystore"))?;
+
+    header.extend_from_slice(&ciphertext);
+    Ok(header)
+}
+
+fn decrypt(contents: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if contents.len() < HEADER_BYTES || &contents[..4] != KEYSTORE_MAGIC {
+        return Err(eyre!("Not a keystore"));
+    }
+    if contents[4] != KEYSTORE_VERSION {
+        return Err(eyre!("Unsupported keystore version {}", contents[4]));
+    }
+
+    let (header, ciphertext) = contents.split_at(HEADER_BYTES);
+    let cost = |at: usize| {
+        u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+    };
+    let (m_cost, t_cost, p_cost) = (cost(5), cost(9), cost(13));
+    if m_cost > MAX_M_COST_KIB || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+        return Err(eyre!(
+            "Keystore parameters out of range: m={m_cost} t={t_cost} p={p_cost}"
+        ));
+    }
+    let params = Params::new(m_cost, t_cost, p_cost, Some(KEY_BYTES))
+        .map_err(|e| eyre!("Bad keystore parameters: {e}"))?;
+    let salt = &header[17..17 + SALT_BYTES];
+    let nonce = &header[17 + SALT_BYTES..];
+
+    cipher(passphrase, salt, params)?
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| eyre!("Wrong passphrase, or the keystore is damaged"))
+}
+
+fn cipher(passphrase: &str, salt: &[u8], params: Params) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; KEY_BYTES];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| eyre!("Failed to derive the keystore key: {e}"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Where a slot's keystore lives. It's kept out of the data dir so wiping a chain doesn't lose it.
+fn keystore_path(slot: &str) -> Result<PathBuf> {
+    let dir = get_config_dir()?.join(KEYS_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{slot}.keystore")))
+}
+
+fn legacy_mnemonic_path(slot: &str) -> Result<PathBuf> {
+    Ok(get_config_dir()?
+        .join(KEYS_DIR)
+        .join(format!("{slot}.mnemonic")))
+}
+
+fn read_mnemonic(path: &Path) -> Result<Mnemonic> {
+    let phrase =
+        fs::read_to_string(path).map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+    Mnemonic::parse(phrase.trim())
+        .map_err(|e| eyre!("Invalid mnemonic in {}: {}", path.display(), e))
+}
+
+/// Write a new file only the current user can read.
+fn write_secret(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| eyre!("Failed to create {}: {}", path.display(), e))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    #[test]
+    fn keystore_round_trips() {
+        let sealed = encrypt(b"seed", PASSPHRASE).unwrap();
+        assert_eq!(decrypt(&sealed, PASSPHRASE).unwrap(), b"seed");
+        // Fresh salt and nonce every time
+        assert_ne!(encrypt(b"seed", PASSPHRASE).unwrap(), sealed);
+    }
+
+    #[test]
+    fn wrong_passphrase_fails() {
+        let sealed = encrypt(b"seed", PASSPHRASE).unwrap();
+        let err = decrypt(&sealed, "correct horse battery").unwrap_err();
+        assert!(err.to_string().contains("Wrong passphrase"), "{err}");
+    }
+
+    #[test]
+    fn tampered_header_fails() {
+        let sealed = encrypt(b"seed", PASSPHRASE).unwrap();
+        let (header, ciphertext) = sealed.split_at(HEADER_BYTES);
+        // The key and nonce it was sealed with, so only the authenticated header differs
+        let cipher = cipher(PASSPHRASE, &header[17..17 + SALT_BYTES], Params::default()).unwrap();
+        let nonce = Nonce::from_slice(&header[17 + SALT_BYTES..]);
+        let open = |aad: &[u8]| {
+            cipher.decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+        };
+        assert_eq!(open(header).unwrap(), b"seed");
+
+        // The version doesn't go into the key, but a keystore claiming another can't be opened
+        let mut tampered = header.to_vec();
+        tampered[4] = KEYSTORE_VERSION + 1;
+        assert!(open(&tampered).is_err());
+    }
+
+    #[test]
+    fn unsupported_version_fails() {
+        let mut sealed = encrypt(b"seed", PASSPHRASE).unwrap();
+        sealed[4] = KEYSTORE_VERSION + 1;
+        let err = decrypt(&sealed, PASSPHRASE).unwrap_err();
+        assert!(
+            err.to_string().contains("Unsupported keystore version"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn oversized_costs_are_refused() {
+        let mut sealed = encrypt(b"seed", PASSPHRASE).unwrap();
+        sealed[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = decrypt(&sealed, PASSPHRASE).unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{err}");
+    }
+}
@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+
+use crate::game_state::{BootEvent, BootStep, GameState};
+
+/// Covers the map while the node stack comes up, ticking off each boot step.
+pub struct LoadingScreen;
+
+impl Plugin for LoadingScreen {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                update_steps.run_if(not(in_state(GameState::Playing))),
+            )
+            .add_systems(OnEnter(GameState::Playing), teardown);
+    }
+}
+
+const PENDING_TEXT: Color = Color::srgb(0.5, 0.5, 0.5);
+const DONE_TEXT: Color = Color::srgb(0.35, 0.75, 0.35);
+const FAILED_TEXT: Color = Color::srgb(0.85, 0.25, 0.25);
+
+#[derive(Component)]
+struct LoadingScreenBase;
+
+#[derive(Component)]
+struct StepLabel(BootStep);
+
+#[derive(Component)]
+struct ErrorLabel;
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(10.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+            GlobalZIndex(10),
+            LoadingScreenBase,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("It's Tourist Season"),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+            ));
+            for step in BootStep::ALL {
+                parent.spawn((
+                    Text::new(format!("[ ] {}", step.label())),
+                    TextFont {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                    TextColor(PENDING_TEXT),
+                    StepLabel(step),
+                ));
+            }
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+                TextColor(FAILED_TEXT),
+                ErrorLabel,
+            ));
+        });
+}
+
+fn update_steps(
+    mut boot_e: EventReader<BootEvent>,
+    mut step_q: Query<(&StepLabel, &mut Text, &mut TextColor), Without<ErrorLabel>>,
+    mut error_q: Query<&mut Text, With<ErrorLabel>>,
+) {
+    for event in boot_e.read() {
+        match event {
+            BootEvent::Done(step) => {
+                for (label, mut text, mut color) in &mut step_q {
+                    if label.0 == *step {
+                        **text = format!("[x] {}", label.0.label());
+                        color.0 = DONE_TEXT;
+                    }
+                }
+            }
+            BootEvent::Failed(err) => {
+                for mut text in &mut error_q {
+                    **text = format!("Could not start the node: {err}");
+                }
+            }
+        }
+    }
+}
+
+fn teardown(mut commands: Commands, base_q: Query<Entity, With<LoadingScreenBase>>) {
+    for entity in &base_q {
+        commands.entity(entity).despawn();
+    }
+}
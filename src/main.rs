@@ -1,31 +1,73 @@
 use bevy::prelude::*;
 use bitcoind::BitcoindHandler;
+use block_production::BlockProduction;
 use button_row::ButtonRow;
+use chain_backend::Chain;
 use electrum_wallet::ElectrumWallet;
+use fee_bump::FeeBumps;
+use loading_screen::LoadingScreen;
+use node_config::NodeConfig;
+use passphrase_dialog::PassphraseDialog;
+use payments::Payments;
 use popup::Popup;
+use send_dialog::SendDialog;
+use supervisor::Supervisor;
 use tilemaptest::GameMap;
 use tourists::Tourists;
+use wallet_panel::WalletPanel;
+use wallet_sync::WalletSync;
 
 mod bdk_zone;
 mod bitcoind;
+mod block_production;
 mod borders;
 mod button_row;
 mod camera;
+mod chain_backend;
+mod coin_selection;
 mod constants;
 mod coordinates;
 mod electrum_wallet;
+mod external_signer;
+mod fee_bump;
+mod fees;
+mod game_state;
+mod keys;
+mod loading_screen;
+mod node_config;
+mod passphrase_dialog;
+mod payments;
 mod popup;
+mod send_dialog;
+mod sim_chain;
+mod supervisor;
 mod tiled_thing;
 mod tilemaptest;
 mod tourists;
+mod wallet_panel;
+mod wallet_sync;
 
 fn main() {
+    let node_config = NodeConfig::load().expect("A valid node config");
+    let chain = Chain::from_config(&node_config);
+
     App::new()
+        .insert_resource(node_config)
+        .insert_resource(chain)
         .add_plugins(GameMap)
         .add_plugins(ButtonRow)
         .add_plugins(BitcoindHandler)
+        .add_plugins(LoadingScreen)
+        .add_plugins(PassphraseDialog)
+        .add_plugins(Supervisor)
         .add_plugins(Popup)
         .add_plugins(Tourists)
         .add_plugins(ElectrumWallet)
+        .add_plugins(WalletSync)
+        .add_plugins(BlockProduction)
+        .add_plugins(WalletPanel)
+        .add_plugins(SendDialog)
+        .add_plugins(Payments)
+        .add_plugins(FeeBumps)
         .run();
 }
@@ -0,0 +1,408 @@
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bitcoin::Network;
+use clap::{Parser, ValueEnum};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::bdk_zone::{get_config_dir, get_data_dir};
+use crate::block_production::BlockPolicy;
+use crate::coin_selection::CoinSelection;
+use crate::constants::{
+    BITCOIN_CONF, BITCOIN_DIR, ELECTRS_DB_DIR, INSTANCES_DIR, MAP_DIR, NODE_CONFIG_TOML, PSBT_DIR,
+    WALLETS_DIR,
+};
+
+/// Settings for the bitcoind + electrs stack the game runs against.
+///
+/// Values are resolved in order: command line, environment, `node.toml` in the config dir,
+/// and finally the defaults for the chosen network.
+///
+/// When an `instance` is named, everything lives under its own data dir and any port that wasn't
+/// set explicitly is picked from the free ports on the machine, so several stacks can run at once.
+#[derive(Resource, Clone, Debug)]
+pub struct NodeConfig {
+    pub backend: Backend,
+    pub network: Network,
+    /// Run the game's own signet, whose blocks only the game's key can sign.
+    pub private_signet: bool,
+    pub instance: Option<String>,
+    /// The save slot whose seed the wallets are derived from.
+    pub slot: String,
+    /// A file holding a mnemonic to import into the slot.
+    pub import_mnemonic: Option<PathBuf>,
+    pub rpc_port: u16,
+    pub p2p_port: u16,
+    pub zmq_block_port: u16,
+    pub zmq_tx_port: u16,
+    pub electrum_port: u16,
+    pub monitoring_port: u16,
+    pub bitcoind: PathBuf,
+    pub electrs: PathBuf,
+    pub data_dir: PathBuf,
+    /// Tourist payments queued within this long of each other share a transaction. Off when
+    /// `None`.
+    pub batch_window: Option<Duration>,
+    /// How a batch picks the coins it spends.
+    pub coin_selection: CoinSelection,
+    pub player_signer: PlayerSigner,
+    /// When the game mines blocks.
+    pub block_policy: BlockPolicy,
+    /// The time between blocks, or the average for the Poisson policy.
+    pub block_interval: Duration,
+}
+
+/// Which chain the game plays against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A bitcoind + electrs stack launched by the game
+    #[default]
+    Node,
+    /// An in-memory regtest chain; no daemons needed
+    Sim,
+}
+
+/// Who signs the player wallet's spends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerSigner {
+    /// The game, with the slot's keys
+    #[default]
+    Internal,
+    /// An outside signer; the player wallet is watch-only and spends go out as PSBT files
+    External,
+}
+
+/// The optional settings shared by the command line and `node.toml`.
+#[derive(Parser, Clone, Debug, Default, Deserialize, Serialize)]
+#[command(version, about = "It's Tourist Season")]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfigArgs {
+    /// Path to a node config file (defaults to `node.toml` in the config dir)
+    #[arg(long, env = "TOURIST_CONFIG")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "TOURIST_BACKEND", value_enum)]
+    pub backend: Option<Backend>,
+    /// bitcoin, testnet, signet or regtest
+    #[arg(long, env = "TOURIST_NETWORK")]
+    pub network: Option<Network>,
+    /// Run a signet of the game's own, with blocks signed by the game's key
+    #[arg(long, env = "TOURIST_PRIVATE_SIGNET", num_args = 0..=1, default_missing_value = "true")]
+    pub private_signet: Option<bool>,
+    /// Run an isolated stack with its own data dir and free ports
+    #[arg(long, env = "TOURIST_INSTANCE")]
+    pub instance: Option<String>,
+    /// Save slot to play; each slot has its own seed (defaults to `default`)
+    #[arg(long, env = "TOURIST_SLOT")]
+    pub slot: Option<String>,
+    /// Import the mnemonic in this file into the save slot
+    #[arg(long, env = "TOURIST_IMPORT_MNEMONIC")]
+    #[serde(skip)]
+    pub import_mnemonic: Option<PathBuf>,
+    #[arg(long, env = "TOURIST_RPC_PORT")]
+    pub rpc_port: Option<u16>,
+    #[arg(long, env = "TOURIST_P2P_PORT")]
+    pub p2p_port: Option<u16>,
+    #[arg(long, env = "TOURIST_ZMQ_BLOCK_PORT")]
+    pub zmq_block_port: Option<u16>,
+    #[arg(long, env = "TOURIST_ZMQ_TX_PORT")]
+    pub zmq_tx_port: Option<u16>,
+    #[arg(long, env = "TOURIST_ELECTRUM_PORT")]
+    pub electrum_port: Option<u16>,
+    /// Port for the electrs Prometheus metrics
+    #[arg(long, env = "TOURIST_MONITORING_PORT")]
+    pub monitoring_port: Option<u16>,
+    /// Path to the bitcoind binary
+    #[arg(long, env = "TOURIST_BITCOIND")]
+    pub bitcoind: Option<PathBuf>,
+    /// Path to the electrs binary
+    #[arg(long, env = "TOURIST_ELECTRS")]
+    pub electrs: Option<PathBuf>,
+    /// Directory holding the bitcoind data dir and the electrs db
+    #[arg(long, env = "TOURIST_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Batch tourist payments made within this many milliseconds into one transaction (0 is off)
+    #[arg(long, env = "TOURIST_BATCH_WINDOW_MS")]
+    pub batch_window_ms: Option<u64>,
+    /// Coin selection for batched payments
+    #[arg(long, env = "TOURIST_COIN_SELECTION", value_enum)]
+    pub coin_selection: Option<CoinSelection>,
+    /// Who signs the player wallet's spends (defaults to internal)
+    #[arg(long, env = "TOURIST_PLAYER_SIGNER", value_enum)]
+    pub player_signer: Option<PlayerSigner>,
+    /// When to mine blocks (defaults to rounds)
+    #[arg(long, env = "TOURIST_BLOCK_POLICY", value_enum)]
+    pub block_policy: Option<BlockPolicy>,
+    /// Seconds between blocks for the interval and poisson policies (defaults to 10)
+    #[arg(long, env = "TOURIST_BLOCK_INTERVAL_SECS")]
+    pub block_interval_secs: Option<u64>,
+}
+
+impl NodeConfigArgs {
+    /// Fill in anything not set on `self` from `other`.
+    pub fn or(self, other: NodeConfigArgs) -> Self {
+        Self {
+            config: self.config.or(other.config),
+            backend: self.backend.or(other.backend),
+            network: self.network.or(other.network),
+            private_signet: self.private_signet.or(other.private_signet),
+            instance: self.instance.or(other.instance),
+            slot: self.slot.or(other.slot),
+            import_mnemonic: self.import_mnemonic.or(other.import_mnemonic),
+            rpc_port: self.rpc_port.or(other.rpc_port),
+            p2p_port: self.p2p_port.or(other.p2p_port),
+            zmq_block_port: self.zmq_block_port.or(other.zmq_block_port),
+            zmq_tx_port: self.zmq_tx_port.or(other.zmq_tx_port),
+            electrum_port: self.electrum_port.or(other.electrum_port),
+            monitoring_port: self.monitoring_port.or(other.monitoring_port),
+            bitcoind: self.bitcoind.or(other.bitcoind),
+            electrs: self.electrs.or(other.electrs),
+            data_dir: self.data_dir.or(other.data_dir),
+            batch_window_ms: self.batch_window_ms.or(other.batch_window_ms),
+            coin_selection: self.coin_selection.or(other.coin_selection),
+            player_signer: self.player_signer.or(other.player_signer),
+            block_policy: self.block_policy.or(other.block_policy),
+            block_interval_secs: self.block_interval_secs.or(other.block_interval_secs),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| eyre!("Failed to parse {}: {}", path.display(), e))
+    }
+}
+
+impl NodeConfig {
+    /// Parse the command line and environment, then merge in the config file.
+    pub fn load() -> Result<Self> {
+        let cli = NodeConfigArgs::parse();
+
+        let file_args = match &cli.config {
+            Some(path) => NodeConfigArgs::from_file(path)?,
+            None => {
+                let path = get_config_dir()?.join(NODE_CONFIG_TOML);
+                if path.exists() {
+                    NodeConfigArgs::from_file(&path)?
+                } else {
+                    NodeConfigArgs::default()
+                }
+            }
+        };
+
+        let config = Self::resolve(cli.or(file_args))?;
+        info!("Node config: {:?}", config);
+        Ok(config)
+    }
+
+    pub fn resolve(args: NodeConfigArgs) -> Result<Self> {
+        let backend = args.backend.unwrap_or_default();
+        let private_signet = args.private_signet.unwrap_or(false);
+        let network = match args.network {
+            Some(network) => network,
+            None if private_signet => Network::Signet,
+            None => Network::Regtest,
+        };
+        if private_signet && network != Network::Signet {
+            return Err(eyre!("A private signet runs on signet, not {network}"));
+        }
+        if backend == Backend::Sim && network != Network::Regtest {
+            return Err(eyre!("The sim backend only runs regtest, not {network}"));
+        }
+        if backend == Backend::Node {
+            electrs_network(network)?;
+        }
+
+        if let Some(instance) = &args.instance {
+            check_name("instance", instance)?;
+        }
+        let slot = args.slot.unwrap_or_else(|| DEFAULT_SLOT.to_string());
+        check_name("slot", &slot)?;
+
+        let data_dir = match (args.data_dir, &args.instance) {
+            (Some(data_dir), _) => data_dir,
+            (None, Some(instance)) => get_data_dir(Some(Path::new(INSTANCES_DIR).join(instance)))?,
+            (None, None) => get_data_dir(None)?,
+        };
+
+        // Instances take free ports instead of the defaults. Every listener is held until all
+        // ports are picked so no two of them collide.
+        let mut listeners = Vec::new();
+        let mut port = |port: Option<u16>, default: u16| -> Result<u16> {
+            match port {
+                Some(port) => Ok(port),
+                None if args.instance.is_some() => {
+                    let listener = TcpListener::bind("127.0.0.1:0")?;
+                    let port = listener.local_addr()?.port();
+                    listeners.push(listener);
+                    Ok(port)
+                }
+                None => Ok(default),
+            }
+        };
+
+        Ok(Self {
+            backend,
+            network,
+            private_signet,
+            rpc_port: port(args.rpc_port, default_rpc_port(network))?,
+            p2p_port: port(args.p2p_port, default_p2p_port(network))?,
+            zmq_block_port: port(args.zmq_block_port, DEFAULT_ZMQ_BLOCK_PORT)?,
+            zmq_tx_port: port(args.zmq_tx_port, DEFAULT_ZMQ_TX_PORT)?,
+            electrum_port: port(args.electrum_port, default_electrum_port(network))?,
+            monitoring_port: port(args.monitoring_port, default_monitoring_port(network))?,
+            instance: args.instance,
+            slot,
+            import_mnemonic: args.import_mnemonic,
+            bitcoind: args.bitcoind.unwrap_or_else(|| "bitcoind".into()),
+            electrs: args.electrs.unwrap_or_else(|| "electrs".into()),
+            data_dir,
+            batch_window: args
+                .batch_window_ms
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+            coin_selection: args.coin_selection.unwrap_or_default(),
+            player_signer: args.player_signer.unwrap_or_default(),
+            block_policy: args.block_policy.unwrap_or_default(),
+            block_interval: Duration::from_secs(
+                args.block_interval_secs
+                    .filter(|secs| *secs > 0)
+                    .unwrap_or(DEFAULT_BLOCK_INTERVAL_SECS),
+            ),
+        })
+    }
+
+    pub fn rpc_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.rpc_port)
+    }
+
+    pub fn electrum_addr(&self) -> String {
+        format!("127.0.0.1:{}", self.electrum_port)
+    }
+
+    pub fn zmq_block_addr(&self) -> String {
+        format!("tcp://127.0.0.1:{}", self.zmq_block_port)
+    }
+
+    pub fn zmq_tx_addr(&self) -> String {
+        format!("tcp://127.0.0.1:{}", self.zmq_tx_port)
+    }
+
+    /// The `bitcoin.conf` written for this stack on every launch.
+    pub fn bitcoin_conf_path(&self) -> Result<PathBuf> {
+        fs::create_dir_all(&self.data_dir)?;
+        Ok(self.data_dir.join(BITCOIN_CONF))
+    }
+
+    pub fn bitcoind_dir(&self) -> Result<PathBuf> {
+        let dir = self.data_dir.join(BITCOIN_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    pub fn electrs_db_dir(&self) -> Result<PathBuf> {
+        let dir = self.data_dir.join(ELECTRS_DB_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    pub fn map_dir(&self) -> Result<PathBuf> {
+        let dir = self.data_dir.join(MAP_DIR);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Where PSBTs for an external signer are written and read back from.
+    pub fn psbt_dir(&self) -> Result<PathBuf> {
+        let dir = self.data_dir.join(PSBT_DIR).join(&self.slot);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// The sqlite db for one of the game's wallets. Each slot and network gets its own.
+    pub fn wallet_db_path(&self, name: &str) -> Result<PathBuf> {
+        let dir = self.data_dir.join(WALLETS_DIR).join(&self.slot);
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join(format!("{name}-{}.sqlite3", self.network)))
+    }
+}
+
+/// Instance and slot names become directory and file names.
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(eyre!("Invalid {kind} name: {name:?}"));
+    }
+    Ok(())
+}
+
+/// The subdirectory bitcoind keeps its chain data (and `.cookie`) in.
+pub fn chain_subdir(network: Network) -> Option<&'static str> {
+    match network {
+        Network::Bitcoin => None,
+        Network::Testnet => Some("testnet3"),
+        Network::Testnet4 => Some("testnet4"),
+        Network::Signet => Some("signet"),
+        _ => Some("regtest"),
+    }
+}
+
+/// The name electrs's `--network` knows `network` by.
+pub fn electrs_network(network: Network) -> Result<&'static str> {
+    match network {
+        Network::Bitcoin => Ok("bitcoin"),
+        // electrs calls testnet3 plain testnet and has no testnet4
+        Network::Testnet => Ok("testnet"),
+        Network::Signet => Ok("signet"),
+        Network::Regtest => Ok("regtest"),
+        _ => Err(eyre!("electrs can't follow {network}")),
+    }
+}
+
+const DEFAULT_SLOT: &str = "default";
+const DEFAULT_BLOCK_INTERVAL_SECS: u64 = 10;
+const DEFAULT_ZMQ_BLOCK_PORT: u16 = 28332;
+const DEFAULT_ZMQ_TX_PORT: u16 = 28333;
+
+fn default_rpc_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8332,
+        Network::Testnet => 18332,
+        Network::Testnet4 => 48332,
+        Network::Signet => 38332,
+        _ => 18443,
+    }
+}
+
+fn default_p2p_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 8333,
+        Network::Testnet => 18333,
+        Network::Testnet4 => 48333,
+        Network::Signet => 38333,
+        _ => 18444,
+    }
+}
+
+fn default_electrum_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 50001,
+        Network::Testnet | Network::Testnet4 => 60001,
+        Network::Signet => 60601,
+        _ => 60401,
+    }
+}
+
+fn default_monitoring_port(network: Network) -> u16 {
+    match network {
+        Network::Bitcoin => 4224,
+        Network::Testnet | Network::Testnet4 => 14224,
+        Network::Signet => 34224,
+        _ => 24224,
+    }
+}
@@ -0,0 +1,283 @@
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, TryRecvError, bounded};
+use eyre::eyre;
+
+use crate::keys::{SlotKeys, SlotLock, create_slot, unlock_slot};
+use crate::node_config::NodeConfig;
+
+/// Asks for the save slot's passphrase on startup, then unlocks (or creates) its keystore and
+/// inserts the slot's keys.
+pub struct PassphraseDialog;
+
+impl Plugin for PassphraseDialog {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (type_passphrase, open_slot)
+                .chain()
+                .run_if(resource_exists::<Dialog>),
+        );
+    }
+}
+
+const FOCUSED_TEXT: Color = Color::srgb(0.9, 0.9, 0.9);
+const UNFOCUSED_TEXT: Color = Color::srgb(0.5, 0.5, 0.5);
+const FAILED_TEXT: Color = Color::srgb(0.85, 0.25, 0.25);
+
+#[derive(Resource)]
+struct Dialog {
+    lock: SlotLock,
+    fields: Vec<String>,
+    focus: usize,
+    /// The keystore being opened on another thread, since argon2 takes a moment.
+    opening: Option<Receiver<eyre::Result<SlotKeys>>>,
+}
+
+impl Dialog {
+    fn new(lock: SlotLock) -> Self {
+        let fields = vec![String::new(); field_names(&lock).len()];
+        Self {
+            lock,
+            fields,
+            focus: 0,
+            opening: None,
+        }
+    }
+
+    fn field_names(&self) -> &'static [&'static str] {
+        field_names(&self.lock)
+    }
+
+    /// A field's label, with its contents masked.
+    fn field_text(&self, index: usize) -> String {
+        let cursor = if index == self.focus { "_" } else { "" };
+        format!(
+            "{}: {}{}",
+            self.field_names()[index],
+            "*".repeat(self.fields[index].chars().count()),
+            cursor
+        )
+    }
+}
+
+fn field_names(lock: &SlotLock) -> &'static [&'static str] {
+    match lock {
+        SlotLock::Locked => &["Passphrase"],
+        SlotLock::New { .. } => &[
+            "New passphrase",
+            "Repeat passphrase",
+            "BIP39 passphrase (optional)",
+        ],
+    }
+}
+
+#[derive(Component)]
+struct DialogBase;
+
+#[derive(Component)]
+struct FieldLabel(usize);
+
+#[derive(Component)]
+struct DialogError;
+
+fn setup(mut commands: Commands, node_config: Res<NodeConfig>) {
+    let (title, dialog, error) = match SlotLock::for_slot(&node_config) {
+        Ok(lock) => {
+            let title = match &lock {
+                SlotLock::Locked => format!("Unlock save slot `{}`", node_config.slot),
+                SlotLock::New { import: Some(_) } => {
+                    format!("Protect the imported seed for slot `{}`", node_config.slot)
+                }
+                SlotLock::New { import: None } => {
+                    format!("Create a seed for save slot `{}`", node_config.slot)
+                }
+            };
+            (title, Some(Dialog::new(lock)), String::new())
+        }
+        Err(err) => {
+            error!("Couldn't open the save slot: {err}");
+            ("Save slot".to_string(), None, err.to_string())
+        }
+    };
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(10.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+            GlobalZIndex(20),
+            DialogBase,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(title),
+                TextFont {
+                    font_size: 30.0,
+                    ..default()
+                },
+            ));
+            if let Some(dialog) = &dialog {
+                for index in 0..dialog.field_names().len() {
+                    parent.spawn((
+                        Text::new(dialog.field_text(index)),
+                        TextFont {
+                            font_size: 20.0,
+                            ..default()
+                        },
+                        TextColor(if index == dialog.focus {
+                            FOCUSED_TEXT
+                        } else {
+                            UNFOCUSED_TEXT
+                        }),
+                        FieldLabel(index),
+                    ));
+                }
+                parent.spawn((
+                    Text::new("Tab switches fields, Enter continues"),
+                    TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    },
+                    TextColor(UNFOCUSED_TEXT),
+                ));
+            }
+            parent.spawn((
+                Text::new(error),
+                TextFont {
+                    font_size: 20.0,
+                    ..default()
+                },
+                TextColor(FAILED_TEXT),
+                DialogError,
+            ));
+        });
+
+    if let Some(dialog) = dialog {
+        commands.insert_resource(dialog);
+    }
+}
+
+fn type_passphrase(
+    mut keyboard_e: EventReader<KeyboardInput>,
+    mut dialog: ResMut<Dialog>,
+    node_config: Res<NodeConfig>,
+    mut field_q: Query<(&FieldLabel, &mut Text, &mut TextColor), Without<DialogError>>,
+    mut error_q: Query<&mut Text, With<DialogError>>,
+) {
+    if dialog.opening.is_some() {
+        keyboard_e.clear();
+        return;
+    }
+
+    let field_count = dialog.field_names().len();
+    let mut changed = false;
+    let mut submit = false;
+
+    for event in keyboard_e.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        let Dialog { fields, focus, .. } = &mut *dialog;
+        match &event.logical_key {
+            Key::Enter => submit = true,
+            Key::Tab => *focus = (*focus + 1) % field_count,
+            Key::Backspace => {
+                fields[*focus].pop();
+            }
+            Key::Space => fields[*focus].push(' '),
+            Key::Character(chars) => {
+                fields[*focus].extend(chars.chars().filter(|c| !c.is_control()))
+            }
+            _ => continue,
+        }
+        changed = true;
+    }
+
+    if submit {
+        for mut text in &mut error_q {
+            text.clear();
+        }
+        let lock = dialog.lock.clone();
+        let fields = dialog.fields.clone();
+        let config = node_config.clone();
+        let (result_tx, result_rx) = bounded(1);
+        std::thread::spawn(move || {
+            let result = match lock {
+                SlotLock::Locked => unlock_slot(&config, &fields[0]),
+                SlotLock::New { .. } if fields[0] != fields[1] => {
+                    Err(eyre!("The passphrases don't match"))
+                }
+                SlotLock::New { import } => create_slot(&config, import, &fields[0], &fields[2]),
+            };
+            let _ = result_tx.send(result);
+        });
+        dialog.opening = Some(result_rx);
+    }
+
+    if changed {
+        show_fields(&dialog, &mut field_q);
+    }
+}
+
+/// Insert the slot's keys once its keystore is open, or ask again if it wouldn't open.
+fn open_slot(
+    mut commands: Commands,
+    mut dialog: ResMut<Dialog>,
+    node_config: Res<NodeConfig>,
+    mut field_q: Query<(&FieldLabel, &mut Text, &mut TextColor), Without<DialogError>>,
+    mut error_q: Query<&mut Text, With<DialogError>>,
+    base_q: Query<Entity, With<DialogBase>>,
+) {
+    let Some(opening) = &dialog.opening else {
+        return;
+    };
+    let result = match opening.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err(eyre!("The keystore couldn't be opened")),
+    };
+    dialog.opening = None;
+
+    match result {
+        Ok(keys) => {
+            info!("Unlocked save slot `{}`", node_config.slot);
+            commands.insert_resource(keys);
+            commands.remove_resource::<Dialog>();
+            for entity in &base_q {
+                commands.entity(entity).despawn();
+            }
+        }
+        Err(err) => {
+            warn!("Couldn't unlock save slot `{}`: {err}", node_config.slot);
+            for mut text in &mut error_q {
+                **text = err.to_string();
+            }
+            dialog.fields.iter_mut().for_each(String::clear);
+            dialog.focus = 0;
+            show_fields(&dialog, &mut field_q);
+        }
+    }
+}
+
+fn show_fields(
+    dialog: &Dialog,
+    field_q: &mut Query<(&FieldLabel, &mut Text, &mut TextColor), Without<DialogError>>,
+) {
+    for (label, mut text, mut color) in field_q {
+        **text = dialog.field_text(label.0);
+        color.0 = if label.0 == dialog.focus {
+            FOCUSED_TEXT
+        } else {
+            UNFOCUSED_TEXT
+        };
+    }
+}
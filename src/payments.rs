@@ -0,0 +1,844 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bdk_wallet::chain::ChainPosition;
+use bdk_wallet::coin_selection::InsufficientFunds;
+use bdk_wallet::error::CreateTxError;
+use bdk_wallet::{KeychainKind, LocalOutput, SignOptions, Wallet};
+use bevy::prelude::*;
+use bitcoin::{
+    Address, Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Transaction, TxIn, TxOut, Txid, Weight,
+    Witness, absolute, constants, transaction,
+};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use thiserror::Error;
+
+use crate::chain_backend::{Chain, ChainBackend};
+use crate::coin_selection::{self, CoinSelection};
+use crate::electrum_wallet::{SharedWallet, StoredWallet, WalletId};
+use crate::external_signer::{self, LockedCoins, PsbtJob, PsbtResult};
+use crate::fee_bump::{self, BumpJob, BumpResult};
+use crate::fees::{FeeEstimator, FeePolicy};
+use crate::node_config::NodeConfig;
+
+/// Builds, signs and broadcasts payments on a background worker so the game never waits on them.
+pub struct Payments;
+
+impl Plugin for Payments {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PaymentResult>()
+            .add_event::<BumpResult>()
+            .add_event::<PsbtResult>()
+            .add_systems(Startup, start_worker)
+            .add_systems(Update, report_payments);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PaymentId(pub u64);
+
+/// A payment for the worker to make.
+#[derive(Clone, Debug)]
+pub struct PaymentIntent {
+    pub from: WalletId,
+    pub to: Address,
+    pub amount: Amount,
+    pub fee_policy: FeePolicy,
+    pub coins: Coins,
+}
+
+/// Which of a wallet's coins a payment can spend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Coins {
+    /// Whatever the wallet's coin selection picks.
+    Any,
+    /// Only the coins received at this receive address index. The change goes back to it.
+    Purse(u32),
+}
+
+impl Coins {
+    pub fn contains(&self, output: &LocalOutput) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Purse(index) => {
+                output.keychain == KeychainKind::External && output.derivation_index == *index
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PaymentSent {
+    pub txid: Txid,
+    /// The rate the fee policy came up with.
+    pub fee_rate: FeeRate,
+    pub fee: Amount,
+    /// The tip the wallet had synced to when the payment went out.
+    pub height: u32,
+    /// Set when the payment went out in a batch, whose whole fee is `fee`.
+    pub batch: Option<BatchSent>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BatchSent {
+    /// How many payments share the transaction.
+    pub payments: usize,
+    pub coin_selection: CoinSelection,
+    /// The summed waste metric of each purse's coin selection, in sats.
+    pub waste: f32,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum PaymentError {
+    #[error("not enough sats: {needed} needed, {available} available")]
+    InsufficientFunds { needed: Amount, available: Amount },
+    #[error("could not build the transaction: {0}")]
+    Build(String),
+    #[error("could not sign the transaction: {0}")]
+    Sign(String),
+    #[error("broadcast failed: {0}")]
+    Broadcast(String),
+    #[error("wallet error: {0}")]
+    Wallet(String),
+    #[error("{0}")]
+    File(String),
+}
+
+/// How a payment went, sent once the worker is done with it.
+#[derive(Event, Clone, Debug)]
+pub struct PaymentResult {
+    pub id: PaymentId,
+    pub intent: PaymentIntent,
+    pub outcome: Result<PaymentSent, PaymentError>,
+}
+
+struct PaymentJob {
+    id: PaymentId,
+    intent: PaymentIntent,
+    wallet: SharedWallet,
+}
+
+/// The worker makes payments and bumps them in the order they're queued, so a bump never races
+/// a payment for the same coins.
+enum Job {
+    Pay(PaymentJob),
+    Bump(BumpJob),
+    Psbt(PsbtJob),
+}
+
+enum JobResult {
+    Pay(PaymentResult),
+    Bump(BumpResult),
+    Psbt(PsbtResult),
+}
+
+#[derive(Resource)]
+pub struct PaymentQueue {
+    jobs: Sender<Job>,
+    results: Receiver<JobResult>,
+    next_id: u64,
+}
+
+impl PaymentQueue {
+    /// Hand a payment from `wallet` to the worker. Its outcome comes back as a [`PaymentResult`].
+    pub fn enqueue(&mut self, wallet: &SharedWallet, intent: PaymentIntent) -> PaymentId {
+        let id = PaymentId(self.next_id);
+        self.next_id += 1;
+
+        let job = PaymentJob {
+            id,
+            intent,
+            wallet: wallet.clone(),
+        };
+        if self.jobs.send(Job::Pay(job)).is_err() {
+            warn!("The payment worker has stopped; dropping payment {}", id.0);
+        }
+        id
+    }
+
+    /// Hand a PSBT export, import or cancel to the worker. The outcome of an export or import
+    /// comes back as a [`PsbtResult`].
+    pub fn psbt(&self, job: PsbtJob) {
+        if self.jobs.send(Job::Psbt(job)).is_err() {
+            warn!("The payment worker has stopped; dropping the PSBT job");
+        }
+    }
+
+    /// Hand a stuck payment to the worker to bump. Its outcome comes back as a [`BumpResult`].
+    pub fn bump(&self, job: BumpJob) -> bool {
+        let id = job.payment.id;
+        if self.jobs.send(Job::Bump(job)).is_err() {
+            warn!(
+                "The payment worker has stopped; not bumping payment {}",
+                id.0
+            );
+            return false;
+        }
+        true
+    }
+}
+
+fn start_worker(mut commands: Commands, chain: Res<Chain>, node_config: Res<NodeConfig>) {
+    let (jobs_tx, jobs_rx) = unbounded::<Job>();
+    let (results_tx, results_rx) = unbounded::<JobResult>();
+
+    let worker = Worker {
+        chain: chain.clone(),
+        fees: FeeEstimator::default(),
+        batch_window: node_config.batch_window,
+        coin_selection: node_config.coin_selection,
+        locked: LockedCoins::default(),
+        results: results_tx,
+    };
+    std::thread::spawn(move || worker.run(jobs_rx));
+
+    commands.insert_resource(PaymentQueue {
+        jobs: jobs_tx,
+        results: results_rx,
+        next_id: 0,
+    });
+}
+
+/// Tourist payments waiting out the batch window.
+struct Batch {
+    jobs: Vec<PaymentJob>,
+    deadline: Instant,
+}
+
+struct Worker {
+    chain: Chain,
+    fees: FeeEstimator,
+    batch_window: Option<Duration>,
+    coin_selection: CoinSelection,
+    /// The coins of PSBTs out with the external signer.
+    locked: LockedCoins,
+    results: Sender<JobResult>,
+}
+
+impl Worker {
+    /// Runs until the game drops its end of either channel.
+    fn run(mut self, jobs: Receiver<Job>) {
+        let mut batch: Option<Batch> = None;
+        loop {
+            let job = match &batch {
+                Some(batch) => match jobs.recv_deadline(batch.deadline) {
+                    Ok(job) => Some(job),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                },
+                None => match jobs.recv() {
+                    Ok(job) => Some(job),
+                    Err(_) => return,
+                },
+            };
+
+            let reported = match job {
+                // The window's closed
+                None => batch.take().is_none_or(|batch| self.pay_batch(batch.jobs)),
+                Some(Job::Pay(job)) => match self.batch_window {
+                    Some(window) if job.intent.from == WalletId::Tourist => {
+                        batch
+                            .get_or_insert_with(|| Batch {
+                                jobs: Vec::new(),
+                                deadline: Instant::now() + window,
+                            })
+                            .jobs
+                            .push(job);
+                        true
+                    }
+                    _ => self.pay(job),
+                },
+                Some(Job::Bump(job)) => self.bump(job),
+                Some(Job::Psbt(job)) => self.psbt(job),
+            };
+            if !reported {
+                return;
+            }
+        }
+    }
+
+    fn pay(&mut self, PaymentJob { id, intent, wallet }: PaymentJob) -> bool {
+        let chain = self.chain.as_ref();
+        let fee_rate = self.fees.fee_rate(chain, intent.fee_policy);
+        let outcome = pay(chain, &wallet, &intent, fee_rate);
+        self.results
+            .send(JobResult::Pay(PaymentResult {
+                id,
+                intent,
+                outcome,
+            }))
+            .is_ok()
+    }
+
+    fn pay_batch(&mut self, jobs: Vec<PaymentJob>) -> bool {
+        let Some((from, wallet)) = jobs
+            .first()
+            .map(|job| (job.intent.from, job.wallet.clone()))
+        else {
+            return true;
+        };
+        let chain = self.chain.as_ref();
+        // The most urgent payment sets the rate for the batch
+        let fee_rate = jobs
+            .iter()
+            .map(|job| self.fees.fee_rate(chain, job.intent.fee_policy))
+            .max()
+            .unwrap_or(FeeRate::BROADCAST_MIN);
+        let long_term = self.fees.fee_rate(chain, FeePolicy::Economy);
+
+        let intents: Vec<PaymentIntent> = jobs.iter().map(|job| job.intent.clone()).collect();
+        let outcomes = pay_batch(
+            chain,
+            &wallet,
+            from,
+            &intents,
+            self.coin_selection,
+            fee_rate,
+            long_term,
+        );
+        jobs.into_iter().zip(outcomes).all(|(job, outcome)| {
+            self.results
+                .send(JobResult::Pay(PaymentResult {
+                    id: job.id,
+                    intent: job.intent,
+                    outcome,
+                }))
+                .is_ok()
+        })
+    }
+
+    fn bump(&mut self, job: BumpJob) -> bool {
+        let chain = self.chain.as_ref();
+        let estimate = self.fees.fee_rate(chain, job.payment.intent.fee_policy);
+        let fee_rate = fee_bump::bump_fee_rate(job.payment.fee_rate, estimate);
+        let outcome = fee_bump::bump(chain, &job, fee_rate);
+        self.results
+            .send(JobResult::Bump(BumpResult {
+                payment: job.payment,
+                outcome,
+            }))
+            .is_ok()
+    }
+
+    fn psbt(&mut self, job: PsbtJob) -> bool {
+        let chain = self.chain.as_ref();
+        let outcome = match &job {
+            PsbtJob::Export {
+                wallet,
+                intent,
+                dir,
+            } => {
+                let fee_rate = self.fees.fee_rate(chain, intent.fee_policy);
+                external_signer::export(wallet, intent, fee_rate, dir, &mut self.locked)
+            }
+            PsbtJob::Import { wallet, path } => {
+                external_signer::import(chain, wallet, path, &mut self.locked)
+            }
+            PsbtJob::Cancel { txid } => {
+                self.locked.unlock(*txid);
+                return true;
+            }
+        };
+        self.results
+            .send(JobResult::Psbt(PsbtResult { outcome }))
+            .is_ok()
+    }
+}
+
+fn pay(
+    chain: &dyn ChainBackend,
+    wallet: &SharedWallet,
+    intent: &PaymentIntent,
+    fee_rate: FeeRate,
+) -> Result<PaymentSent, PaymentError> {
+    // The wallet is only locked while building and signing, not while broadcasting
+    let (tx, fee, height) = {
+        let mut stored = wallet.lock().map_err(wallet_error)?;
+        let purse = purse_coins(&stored, intent.coins);
+
+        let mut builder = stored.build_tx();
+        builder
+            .fee_rate(fee_rate)
+            .add_recipient(intent.to.script_pubkey(), intent.amount);
+        if let Some((others, change)) = purse {
+            builder.unspendable(others).drain_to(change);
+        }
+        let psbt = builder.finish().map_err(build_error)?;
+
+        let (tx, fee) = sign(&mut stored, psbt, intent.from)?;
+        (tx, fee, stored.latest_checkpoint().height())
+    };
+
+    let txid = broadcast(chain, wallet, intent.from, tx)?;
+    Ok(PaymentSent {
+        txid,
+        fee_rate,
+        fee,
+        height,
+        batch: None,
+    })
+}
+
+/// Make several payments from one wallet in a single transaction. The outcomes line up with
+/// `intents`.
+fn pay_batch(
+    chain: &dyn ChainBackend,
+    wallet: &SharedWallet,
+    from: WalletId,
+    intents: &[PaymentIntent],
+    strategy: CoinSelection,
+    fee_rate: FeeRate,
+    long_term: FeeRate,
+) -> Vec<Result<PaymentSent, PaymentError>> {
+    let mut funded = vec![Ok(()); intents.len()];
+    let built = build_batch(
+        wallet,
+        from,
+        intents,
+        strategy,
+        fee_rate,
+        long_term,
+        &mut funded,
+    );
+
+    let payments = funded.iter().filter(|funded| funded.is_ok()).count();
+    let sent = built.and_then(|(tx, fee, height, waste)| {
+        let txid = broadcast(chain, wallet, from, tx)?;
+        Ok(PaymentSent {
+            txid,
+            fee_rate,
+            fee,
+            height,
+            batch: Some(BatchSent {
+                payments,
+                coin_selection: strategy,
+                waste,
+            }),
+        })
+    });
+
+    funded
+        .into_iter()
+        .map(|funded| funded.and_then(|()| sent.clone()))
+        .collect()
+}
+
+/// One purse's inputs and outputs within a batch, and the fee its selection priced them at.
+struct PurseSpend {
+    inputs: Vec<OutPoint>,
+    outputs: Vec<TxOut>,
+    change: Option<TxOut>,
+    fee: Amount,
+}
+
+/// Build and sign the transaction for a batch. Each purse funds its own payments and gets its
+/// own change, so batching never mixes the tourists' coins. A purse that can't cover its
+/// payments has them marked in `funded` and is left out.
+fn build_batch(
+    wallet: &SharedWallet,
+    from: WalletId,
+    intents: &[PaymentIntent],
+    strategy: CoinSelection,
+    fee_rate: FeeRate,
+    long_term: FeeRate,
+    funded: &mut [Result<(), PaymentError>],
+) -> Result<(Transaction, Amount, u32, f32), PaymentError> {
+    let mut stored = wallet.lock().map_err(wallet_error)?;
+
+    let mut groups: Vec<(Coins, Vec<usize>)> = Vec::new();
+    for (index, intent) in intents.iter().enumerate() {
+        match groups.iter_mut().find(|(coins, _)| *coins == intent.coins) {
+            Some((_, members)) => members.push(index),
+            None => groups.push((intent.coins, vec![index])),
+        }
+    }
+    let purses: Vec<Coins> = groups
+        .iter()
+        .map(|(coins, _)| *coins)
+        .filter(|coins| *coins != Coins::Any)
+        .collect();
+    let unspent = spendable_coins(&stored);
+
+    let mut spends: Vec<PurseSpend> = Vec::new();
+    let mut waste = 0.0;
+    for (coins, members) in &groups {
+        let candidates: Vec<LocalOutput> = unspent
+            .iter()
+            .filter(|output| match coins {
+                Coins::Any => !purses.iter().any(|purse| purse.contains(output)),
+                purse => purse.contains(output),
+            })
+            .cloned()
+            .collect();
+
+        // Payments to the same trap share an output
+        let mut paying: Vec<TxOut> = Vec::new();
+        for intent in members.iter().map(|&index| &intents[index]) {
+            let script_pubkey = intent.to.script_pubkey();
+            match paying
+                .iter_mut()
+                .find(|output| output.script_pubkey == script_pubkey)
+            {
+                Some(output) => output.value += intent.amount,
+                None => paying.push(TxOut {
+                    value: intent.amount,
+                    script_pubkey,
+                }),
+            }
+        }
+
+        let change = match coins {
+            Coins::Any => stored
+                .next_unused_address(KeychainKind::Internal)
+                .script_pubkey(),
+            Coins::Purse(index) => stored
+                .peek_address(KeychainKind::External, *index)
+                .script_pubkey(),
+        };
+
+        match coin_selection::select(strategy, &candidates, &paying, &change, fee_rate, long_term) {
+            Ok(selection) => {
+                spends.push(PurseSpend {
+                    inputs: selection
+                        .inputs
+                        .iter()
+                        .map(|input| input.outpoint)
+                        .collect(),
+                    outputs: paying,
+                    change: selection.change.map(|value| TxOut {
+                        value,
+                        script_pubkey: change,
+                    }),
+                    fee: selection.fee,
+                });
+                waste += selection.waste;
+            }
+            Err(err) => {
+                for &index in members {
+                    funded[index] = Err(err.clone());
+                }
+            }
+        }
+    }
+    if spends.is_empty() {
+        return Err(PaymentError::Build(
+            "no purse in the batch could pay".to_string(),
+        ));
+    }
+    let fee = settle_batch_fee(&mut spends, fee_rate)?;
+    let inputs: Vec<OutPoint> = spends
+        .iter()
+        .flat_map(|spend| spend.inputs.clone())
+        .collect();
+    let outputs: Vec<TxOut> = spends
+        .into_iter()
+        .flat_map(|spend| spend.outputs.into_iter().chain(spend.change))
+        .collect();
+
+    // The coins are already picked, so the wallet only has to put the transaction together
+    let mut builder = stored.build_tx();
+    builder
+        .add_utxos(&inputs)
+        .map_err(|err| PaymentError::Build(err.to_string()))?
+        .manually_selected_only()
+        .set_recipients(
+            outputs
+                .into_iter()
+                .map(|output| (output.script_pubkey, output.value))
+                .collect(),
+        )
+        .fee_absolute(fee);
+    let psbt = builder.finish().map_err(build_error)?;
+
+    let (tx, fee) = sign(&mut stored, psbt, from)?;
+    Ok((tx, fee, stored.latest_checkpoint().height(), waste))
+}
+
+/// Each purse's selection priced a transaction of its own, but the batch is one transaction, so
+/// its version, locktime and counts are only paid for once. Each purse pays for its own inputs
+/// and outputs plus an even share of that, and gets back what it overpaid in its change. Returns
+/// the batch's fee.
+fn settle_batch_fee(spends: &mut [PurseSpend], fee_rate: FeeRate) -> Result<Amount, PaymentError> {
+    // Every game wallet is tr(), so every input is a keyspend with a 64 byte signature
+    let draft = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: spends
+            .iter()
+            .flat_map(|spend| &spend.inputs)
+            .map(|&previous_output| TxIn {
+                previous_output,
+                witness: Witness::from_slice(&[[0u8; 64]]),
+                ..Default::default()
+            })
+            .collect(),
+        output: spends
+            .iter()
+            .flat_map(|spend| spend.outputs.iter().chain(&spend.change))
+            .cloned()
+            .collect(),
+    };
+    let input_weight = draft
+        .input
+        .first()
+        .map_or(Weight::ZERO, TxIn::segwit_weight);
+    let own_weight = |spend: &PurseSpend| {
+        input_weight * spend.inputs.len() as u64
+            + spend
+                .outputs
+                .iter()
+                .chain(&spend.change)
+                .map(TxOut::weight)
+                .sum::<Weight>()
+    };
+    let shared = draft.weight() - spends.iter().map(own_weight).sum::<Weight>();
+    let share = Weight::from_wu(shared.to_wu().div_ceil(spends.len() as u64));
+
+    let mut fee = Amount::ZERO;
+    for spend in spends.iter_mut() {
+        let owed = fee_rate
+            .fee_wu(own_weight(spend) + share)
+            .ok_or_else(|| PaymentError::Build("the fee overflows".to_string()))?;
+        // Without change, what it overpaid was too little to be worth an output anyway
+        if let Some(change) = &mut spend.change
+            && spend.fee > owed
+        {
+            change.value += spend.fee - owed;
+            spend.fee = owed;
+        }
+        fee += spend.fee;
+    }
+    Ok(fee)
+}
+
+pub fn wallet_error(err: eyre::Report) -> PaymentError {
+    PaymentError::Wallet(err.to_string())
+}
+
+pub fn build_error(err: CreateTxError) -> PaymentError {
+    match err {
+        CreateTxError::CoinSelection(InsufficientFunds { needed, available }) => {
+            PaymentError::InsufficientFunds { needed, available }
+        }
+        err => PaymentError::Build(err.to_string()),
+    }
+}
+
+/// For a purse payment, the wallet's coins outside the purse, which it mustn't spend, and the
+/// purse's address the change goes back to.
+pub fn purse_coins(wallet: &Wallet, coins: Coins) -> Option<(Vec<OutPoint>, ScriptBuf)> {
+    match coins {
+        Coins::Any => None,
+        Coins::Purse(index) => {
+            let others = wallet
+                .list_unspent()
+                .filter(|output| !coins.contains(output))
+                .map(|output| output.outpoint)
+                .collect();
+            let change = wallet
+                .peek_address(KeychainKind::External, index)
+                .script_pubkey();
+            Some((others, change))
+        }
+    }
+}
+
+/// The wallet's coins that a hand-picked selection can spend. The wallet only leaves immature
+/// coinbases out of the coins it picks itself, and every block the game mines pays the reserve
+/// purse.
+fn spendable_coins(wallet: &Wallet) -> Vec<LocalOutput> {
+    let next_height = wallet.latest_checkpoint().height() + 1;
+    wallet
+        .list_unspent()
+        .filter(|output| {
+            let coinbase = wallet
+                .get_tx(output.outpoint.txid)
+                .is_some_and(|tx| tx.tx_node.tx.is_coinbase());
+            let confirmations = match &output.chain_position {
+                ChainPosition::Confirmed { anchor, .. } => {
+                    next_height.saturating_sub(anchor.block_id.height)
+                }
+                ChainPosition::Unconfirmed { .. } => 0,
+            };
+            !coinbase || confirmations >= constants::COINBASE_MATURITY
+        })
+        .collect()
+}
+
+/// Sign a transaction built by `stored`, returning it along with its fee.
+pub fn sign(
+    stored: &mut StoredWallet,
+    mut psbt: Psbt,
+    wallet: WalletId,
+) -> Result<(Transaction, Amount), PaymentError> {
+    let finalized = stored
+        .sign(&mut psbt, SignOptions::default())
+        .map_err(|err| PaymentError::Sign(err.to_string()))?;
+    if !finalized {
+        return Err(PaymentError::Sign(
+            "the inputs aren't all signed".to_string(),
+        ));
+    }
+
+    // Building the tx may have revealed a change address
+    if let Err(err) = stored.persist() {
+        warn!("Failed to save the {} wallet: {err}", wallet.name());
+    }
+
+    let fee = psbt
+        .fee()
+        .map_err(|err| PaymentError::Build(err.to_string()))?;
+    let tx = psbt
+        .extract_tx()
+        .map_err(|err| PaymentError::Build(err.to_string()))?;
+    Ok((tx, fee))
+}
+
+/// Broadcast a transaction `shared` signed and hand it back to the wallet as unconfirmed.
+pub fn broadcast(
+    chain: &dyn ChainBackend,
+    shared: &SharedWallet,
+    wallet: WalletId,
+    tx: Transaction,
+) -> Result<Txid, PaymentError> {
+    let txid = chain
+        .broadcast(&tx)
+        .map_err(|err| PaymentError::Broadcast(err.to_string()))?;
+
+    // Mark the coins as spent now, so the next payment doesn't pick them again before a sync
+    let mut stored = shared.lock().map_err(wallet_error)?;
+    stored.apply_unconfirmed_txs([(tx, unix_now())]);
+    if let Err(err) = stored.persist() {
+        warn!("Failed to save the {} wallet: {err}", wallet.name());
+    }
+    Ok(txid)
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn report_payments(
+    queue: Res<PaymentQueue>,
+    mut payment_e: EventWriter<PaymentResult>,
+    mut bump_e: EventWriter<BumpResult>,
+    mut psbt_e: EventWriter<PsbtResult>,
+) {
+    for result in queue.results.try_iter() {
+        let result = match result {
+            JobResult::Pay(result) => result,
+            JobResult::Bump(result) => {
+                bump_e.write(result);
+                continue;
+            }
+            JobResult::Psbt(result) => {
+                if let Err(err) = &result.outcome {
+                    warn!("PSBT job failed: {err}");
+                }
+                psbt_e.write(result);
+                continue;
+            }
+        };
+        match &result.outcome {
+            Ok(sent) => match sent.batch {
+                Some(batch) if batch.payments > 1 => info!(
+                    "Payment {} of {} from the {} wallet sent in {} with {} others (fee {} at {}, \
+                     {} waste {:.0} sat)",
+                    result.id.0,
+                    result.intent.amount,
+                    result.intent.from.name(),
+                    sent.txid,
+                    batch.payments - 1,
+                    sent.fee,
+                    sent.fee_rate,
+                    batch.coin_selection.label(),
+                    batch.waste
+                ),
+                _ => info!(
+                    "Payment {} of {} from the {} wallet sent in {} (fee {} at {}, {})",
+                    result.id.0,
+                    result.intent.amount,
+                    result.intent.from.name(),
+                    sent.txid,
+                    sent.fee,
+                    sent.fee_rate,
+                    result.intent.fee_policy.label()
+                ),
+            },
+            Err(err) => warn!(
+                "Payment {} of {} from the {} wallet failed: {err}",
+                result.id.0,
+                result.intent.amount,
+                result.intent.from.name()
+            ),
+        }
+        payment_e.write(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{Network, Script};
+    use signet_miner::grinder::Cancel;
+
+    use super::*;
+    use crate::sim_chain::SimChain;
+
+    const XPRIV: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    const STOP_GAP: usize = 10;
+
+    #[test]
+    fn fresh_coinbases_are_never_picked() {
+        let chain = SimChain::new();
+        let mut wallet = Wallet::create(
+            format!("tr({XPRIV}/86h/1h/0h/0/*)"),
+            format!("tr({XPRIV}/86h/1h/0h/1/*)"),
+        )
+        .network(Network::Regtest)
+        .create_wallet_no_persist()
+        .unwrap();
+        // The reserve purse gets two coinbases that mature, then three that don't
+        let reserve = wallet.peek_address(KeychainKind::External, 0).address;
+        let elsewhere = Address::p2wsh(Script::new(), Network::Regtest);
+        let cancel = Cancel::default();
+        chain.mine_blocks(2, &reserve, None, &cancel).unwrap();
+        chain.mine_blocks(100, &elsewhere, None, &cancel).unwrap();
+        chain.mine_blocks(3, &reserve, None, &cancel).unwrap();
+        let request = wallet.start_full_scan().build();
+        wallet
+            .apply_update(chain.full_scan(request, STOP_GAP).unwrap())
+            .unwrap();
+        assert_eq!(wallet.list_unspent().count(), 5);
+
+        let coins = spendable_coins(&wallet);
+        assert_eq!(coins.len(), 2);
+        let change = reserve.script_pubkey();
+        let paying = |btc: u64| {
+            vec![TxOut {
+                value: Amount::from_int_btc(btc),
+                script_pubkey: elsewhere.script_pubkey(),
+            }]
+        };
+        let rate = FeeRate::from_sat_per_vb_unchecked(1);
+
+        for strategy in [
+            CoinSelection::BranchAndBound,
+            CoinSelection::LargestFirst,
+            CoinSelection::OldestFirst,
+        ] {
+            let selection =
+                coin_selection::select(strategy, &coins, &paying(60), &change, rate, rate).unwrap();
+            assert!(selection.inputs.iter().all(|input| matches!(
+                &input.chain_position,
+                ChainPosition::Confirmed { anchor, .. } if anchor.block_id.height <= 2
+            )));
+
+            // The two mature coinbases can't cover it, and the fresh ones aren't reached for
+            assert!(
+                coin_selection::select(strategy, &coins, &paying(120), &change, rate, rate)
+                    .is_err()
+            );
+        }
+    }
+}
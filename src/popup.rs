@@ -4,7 +4,7 @@
 use crate::{
     constants::{ImgAsset, PopupBase, WALKABLES},
     tilemaptest::{AlphaPos, CurTilePos, CursorPos, LastTilePos, TileBuddies, TileValues},
-    tourists::{RedrawGrid, TouristDespawnPoint, TouristSpawnPoint},
+    tourists::{RedrawGrid, TouristDespawnPoint, TouristSpawnPoint, TouristTrap},
 };
 use bevy::{color::palettes::basic::*, prelude::*};
 use bevy_ecs_tilemap::tiles::{TileColor, TilePos, TileStorage, TileTextureIndex};
@@ -245,6 +245,7 @@ pub struct PopupItem {
     pub relative_pos_and_idx: Vec<(TilePos, TileTextureIndex)>,
     pub spawnpoint: Option<TouristSpawnPoint>,
     pub despawnpoint: Option<TouristDespawnPoint>,
+    pub trap: Option<TouristTrap>,
 }
 
 fn button_system(
@@ -281,6 +282,7 @@ fn button_system(
                             ],
                             spawnpoint: None,
                             despawnpoint: None,
+                            trap: None,
                         };
 
                         commands.spawn((
@@ -344,6 +346,7 @@ fn button_system(
 
                             spawnpoint: None,
                             despawnpoint: None,
+                            trap: Some(TouristTrap::default()),
                         };
 
                         commands.spawn((
@@ -359,6 +362,7 @@ fn button_system(
                             relative_pos_and_idx: vec![],
                             spawnpoint: None,
                             despawnpoint: None,
+                            trap: None,
                         };
 
                         commands.spawn((
@@ -409,6 +413,7 @@ fn button_system(
                             ],
                             spawnpoint: Some(TouristSpawnPoint {}),
                             despawnpoint: None,
+                            trap: None,
                         };
 
                         commands.spawn((
@@ -459,6 +464,7 @@ fn button_system(
                             ],
                             spawnpoint: None,
                             despawnpoint: Some(TouristDespawnPoint {}),
+                            trap: None,
                         };
 
                         commands.spawn((
@@ -474,6 +480,7 @@ fn button_system(
                             relative_pos_and_idx: vec![],
                             spawnpoint: None,
                             despawnpoint: None,
+                            trap: None,
                         };
 
                         commands.spawn((
@@ -489,6 +496,7 @@ fn button_system(
                             relative_pos_and_idx: vec![],
                             spawnpoint: None,
                             despawnpoint: None,
+                            trap: None,
                         };
 
                         commands.spawn((
@@ -686,6 +694,7 @@ fn pick_and_place(
                                                 buddies: tile_buddies.clone(),
                                                 spawnpoint: popup_item.spawnpoint.clone(),
                                                 despawnpoint: popup_item.despawnpoint.clone(),
+                                                trap: popup_item.trap.clone(),
                                             },
                                         }
                                     } else {
@@ -699,6 +708,7 @@ fn pick_and_place(
                                                 buddies: TileBuddies::default(),
                                                 spawnpoint: None,
                                                 despawnpoint: None,
+                                                trap: None,
                                             },
                                         }
                                     }
@@ -761,6 +771,18 @@ fn place_tiles(
                     .remove::<TouristDespawnPoint>();
             }
 
+            // A new trap is paid at a fresh address, handed out by the player wallet
+            if event.tile_values.trap.is_some() {
+                commands
+                    .entity(event.clicked_entity)
+                    .insert(TouristTrap::default());
+                info!("Placed trap");
+            } else {
+                commands
+                    .entity(event.clicked_entity)
+                    .remove::<TouristTrap>();
+            }
+
             if !WALKABLES.iter().any(|idx| idx == &texture_idx.0) {
                 redraw_ew.write(RedrawGrid::MarkUnWalkable(*tile_pos));
             } else {
@@ -791,6 +813,7 @@ fn erase_tiles(
 
                 commands.entity(*entity).remove::<TouristSpawnPoint>();
                 commands.entity(*entity).remove::<TouristDespawnPoint>();
+                commands.entity(*entity).remove::<TouristTrap>();
             }
         }
     }
@@ -0,0 +1,377 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use bitcoin::{Address, Amount, Txid};
+use eyre::eyre;
+
+use crate::electrum_wallet::{PlayerWallet, WalletId};
+use crate::external_signer::{PsbtDone, PsbtJob, PsbtResult};
+use crate::fees::FeePolicy;
+use crate::game_state::GameState;
+use crate::node_config::{NodeConfig, PlayerSigner};
+use crate::payments::{Coins, PaymentId, PaymentIntent, PaymentQueue, PaymentResult};
+
+/// Lets the player spend their earnings to any address. The player wallet signs the payment
+/// itself, or with an external signer the payment goes out as a PSBT file and comes back signed.
+pub struct SendDialog;
+
+impl Plugin for SendDialog {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                open_dialog.run_if(send_dialog_closed),
+                (type_in_dialog, show_results)
+                    .chain()
+                    .run_if(resource_exists::<SendForm>),
+            )
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+const FOCUSED_TEXT: Color = Color::srgb(0.9, 0.9, 0.9);
+const UNFOCUSED_TEXT: Color = Color::srgb(0.5, 0.5, 0.5);
+const STATUS_TEXT: Color = Color::srgb(0.75, 0.75, 0.35);
+
+const FIELD_NAMES: [&str; 2] = ["To address", "Amount (sat)"];
+
+/// Opens the send dialog when pressed.
+#[derive(Component)]
+pub struct SendButton;
+
+/// Whether the player isn't typing into the send dialog, so keys can go to the rest of the game.
+pub fn send_dialog_closed(form: Option<Res<SendForm>>) -> bool {
+    form.is_none()
+}
+
+#[derive(Resource)]
+pub struct SendForm {
+    fields: [String; 2],
+    focus: usize,
+    stage: Stage,
+}
+
+enum Stage {
+    Compose,
+    Sending(PaymentId),
+    Exporting,
+    /// Waiting on the external signer to write `signed`, the signed copy of PSBT `txid`.
+    AwaitingSignature {
+        txid: Txid,
+        signed: PathBuf,
+    },
+    Broadcasting {
+        txid: Txid,
+        signed: PathBuf,
+    },
+}
+
+impl SendForm {
+    fn field_text(&self, index: usize) -> String {
+        let cursor = if index == self.focus { "_" } else { "" };
+        format!("{}: {}{}", FIELD_NAMES[index], self.fields[index], cursor)
+    }
+
+    fn intent(&self, config: &NodeConfig) -> eyre::Result<PaymentIntent> {
+        let to = Address::from_str(self.fields[0].trim())?.require_network(config.network)?;
+        let amount = self.fields[1]
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| eyre!("The amount has to be a whole number of sats"))?;
+        Ok(PaymentIntent {
+            from: WalletId::Player,
+            to,
+            amount: Amount::from_sat(amount),
+            fee_policy: FeePolicy::Normal,
+            coins: Coins::Any,
+        })
+    }
+}
+
+#[derive(Component)]
+struct DialogBase;
+
+#[derive(Component)]
+struct FieldLabel(usize);
+
+#[derive(Component)]
+struct StatusLabel;
+
+fn open_dialog(
+    mut commands: Commands,
+    button_q: Query<&Interaction, (Changed<Interaction>, With<SendButton>)>,
+    node_config: Res<NodeConfig>,
+) {
+    if !button_q
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        return;
+    }
+
+    let form = SendForm {
+        fields: Default::default(),
+        focus: 0,
+        stage: Stage::Compose,
+    };
+    let hint = match node_config.player_signer {
+        PlayerSigner::Internal => "Tab switches fields, Enter sends, Esc closes",
+        PlayerSigner::External => "Tab switches fields, Enter exports a PSBT to sign, Esc closes",
+    };
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(10.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.1, 0.1, 0.1, 0.85)),
+            GlobalZIndex(6),
+            DialogBase,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Send from the player wallet"),
+                TextFont {
+                    font_size: 30.0,
+                    ..default()
+                },
+            ));
+            for index in 0..FIELD_NAMES.len() {
+                parent.spawn((
+                    Text::new(form.field_text(index)),
+                    TextFont {
+                        font_size: 20.0,
+                        ..default()
+                    },
+                    TextColor(if index == form.focus {
+                        FOCUSED_TEXT
+                    } else {
+                        UNFOCUSED_TEXT
+                    }),
+                    FieldLabel(index),
+                ));
+            }
+            parent.spawn((
+                Text::new(hint),
+                TextFont {
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(UNFOCUSED_TEXT),
+            ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 18.0,
+                    ..default()
+                },
+                TextColor(STATUS_TEXT),
+                StatusLabel,
+            ));
+        });
+    commands.insert_resource(form);
+}
+
+fn type_in_dialog(
+    mut commands: Commands,
+    mut keyboard_e: EventReader<KeyboardInput>,
+    mut form: ResMut<SendForm>,
+    node_config: Res<NodeConfig>,
+    player_q: Query<&PlayerWallet>,
+    mut payments: ResMut<PaymentQueue>,
+    mut field_q: Query<(&FieldLabel, &mut Text, &mut TextColor), Without<StatusLabel>>,
+    mut status_q: Query<&mut Text, With<StatusLabel>>,
+    base_q: Query<Entity, With<DialogBase>>,
+) {
+    let mut changed = false;
+    let mut submit = false;
+
+    for event in keyboard_e.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        let SendForm {
+            fields,
+            focus,
+            stage,
+        } = &mut *form;
+        match &event.logical_key {
+            // An export on its way has to come back before its coins can be unlocked
+            Key::Escape if matches!(stage, Stage::Exporting) => continue,
+            Key::Escape => {
+                // Closing gives up on the PSBT, so its coins can be spent again
+                if let Stage::AwaitingSignature { txid, .. } | Stage::Broadcasting { txid, .. } =
+                    stage
+                {
+                    payments.psbt(PsbtJob::Cancel { txid: *txid });
+                }
+                commands.remove_resource::<SendForm>();
+                for entity in &base_q {
+                    commands.entity(entity).despawn();
+                }
+                return;
+            }
+            Key::Enter => submit = true,
+            Key::Tab => *focus = (*focus + 1) % FIELD_NAMES.len(),
+            Key::Backspace => {
+                fields[*focus].pop();
+            }
+            Key::Character(chars) => {
+                fields[*focus].extend(chars.chars().filter(|c| !c.is_control()))
+            }
+            _ => continue,
+        }
+        changed = true;
+    }
+
+    if submit {
+        let status = submit_form(&mut form, &node_config, &player_q, &mut payments);
+        for mut text in &mut status_q {
+            **text = status.clone();
+        }
+    }
+
+    if changed {
+        for (label, mut text, mut color) in &mut field_q {
+            **text = form.field_text(label.0);
+            color.0 = if label.0 == form.focus {
+                FOCUSED_TEXT
+            } else {
+                UNFOCUSED_TEXT
+            };
+        }
+    }
+}
+
+/// Move the form on to its next stage, returning what to tell the player.
+fn submit_form(
+    form: &mut SendForm,
+    node_config: &NodeConfig,
+    player_q: &Query<&PlayerWallet>,
+    payments: &mut PaymentQueue,
+) -> String {
+    let Ok(player) = player_q.single() else {
+        return "The player wallet isn't loaded yet".to_string();
+    };
+
+    match &form.stage {
+        Stage::Compose => {
+            let intent = match form.intent(node_config) {
+                Ok(intent) => intent,
+                Err(err) => return err.to_string(),
+            };
+            match node_config.player_signer {
+                PlayerSigner::Internal => {
+                    let id = payments.enqueue(&player.wallet, intent);
+                    form.stage = Stage::Sending(id);
+                    "Sending...".to_string()
+                }
+                PlayerSigner::External => {
+                    let dir = match node_config.psbt_dir() {
+                        Ok(dir) => dir,
+                        Err(err) => return err.to_string(),
+                    };
+                    payments.psbt(PsbtJob::Export {
+                        wallet: player.wallet.clone(),
+                        intent,
+                        dir,
+                    });
+                    form.stage = Stage::Exporting;
+                    "Building the PSBT...".to_string()
+                }
+            }
+        }
+        Stage::AwaitingSignature { txid, signed } => {
+            let (txid, signed) = (*txid, signed.clone());
+            payments.psbt(PsbtJob::Import {
+                wallet: player.wallet.clone(),
+                path: signed.clone(),
+            });
+            form.stage = Stage::Broadcasting { txid, signed };
+            "Broadcasting the signed PSBT...".to_string()
+        }
+        Stage::Sending(_) | Stage::Exporting | Stage::Broadcasting { .. } => {
+            "Still working on the last one".to_string()
+        }
+    }
+}
+
+fn show_results(
+    mut payment_e: EventReader<PaymentResult>,
+    mut psbt_e: EventReader<PsbtResult>,
+    mut form: ResMut<SendForm>,
+    mut status_q: Query<&mut Text, With<StatusLabel>>,
+    mut field_q: Query<(&FieldLabel, &mut Text), Without<StatusLabel>>,
+) {
+    let mut status = None;
+
+    for result in payment_e.read() {
+        if !matches!(form.stage, Stage::Sending(id) if id == result.id) {
+            continue;
+        }
+        form.stage = Stage::Compose;
+        status = Some(match &result.outcome {
+            Ok(sent) => {
+                form.fields = Default::default();
+                format!("Sent in {}", sent.txid)
+            }
+            Err(err) => err.to_string(),
+        });
+    }
+
+    for result in psbt_e.read() {
+        let stage = std::mem::replace(&mut form.stage, Stage::Compose);
+        status = Some(match (&result.outcome, stage) {
+            (
+                Ok(PsbtDone::Exported {
+                    txid,
+                    unsigned,
+                    signed,
+                }),
+                _,
+            ) => {
+                let text = format!(
+                    "Wrote {}. Sign it, save it as {}, then press Enter to broadcast",
+                    unsigned.display(),
+                    signed.display()
+                );
+                form.stage = Stage::AwaitingSignature {
+                    txid: *txid,
+                    signed: signed.clone(),
+                };
+                text
+            }
+            (Ok(PsbtDone::Broadcast { txid }), _) => {
+                form.fields = Default::default();
+                format!("Sent in {txid}")
+            }
+            // The signer can have another go
+            (Err(err), Stage::Broadcasting { txid, signed }) => {
+                form.stage = Stage::AwaitingSignature { txid, signed };
+                err.to_string()
+            }
+            (Err(err), _) => err.to_string(),
+        });
+    }
+
+    let Some(status) = status else {
+        return;
+    };
+    for mut text in &mut status_q {
+        **text = status.clone();
+    }
+    for (label, mut text) in &mut field_q {
+        **text = form.field_text(label.0);
+    }
+}
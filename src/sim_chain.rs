@@ -0,0 +1,641 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bdk_wallet::KeychainKind;
+use bdk_wallet::chain::local_chain::LocalChain;
+use bdk_wallet::chain::spk_client::{FullScanRequest, FullScanResponse, SyncRequest, SyncResponse};
+use bdk_wallet::chain::{BlockId, ConfirmationBlockTime, TxGraph, TxUpdate};
+use bevy::prelude::*;
+use bitcoin::block::{Header, Version};
+use bitcoin::constants::genesis_block;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Address, Amount, Block, BlockHash, CompactTarget, FeeRate, Network, OutPoint, ScriptBuf,
+    Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness, absolute, transaction,
+};
+use eyre::{Result, eyre};
+use signet_miner::BlockSigner;
+use signet_miner::grinder::Cancel;
+
+use crate::chain_backend::{BlockSummary, ChainBackend};
+
+const COINBASE_MATURITY: u32 = 100;
+const REGTEST_HALVING_INTERVAL: u32 = 150;
+const BLOCK_INTERVAL_SECS: u32 = 600;
+
+/// An in-memory regtest chain for playing without bitcoind or electrs.
+///
+/// Blocks are "mined" instantly and broadcasts go straight into a mempool. A broadcast has to
+/// spend outputs the chain knows about, that aren't spent yet and (for coinbases) are mature, and
+/// can't create more value than it spends. Scripts and signatures are not checked.
+///
+/// A broadcast that spends the same outputs as mempool transactions replaces them, along with
+/// their descendants, if it pays a higher feerate and more fee than they do.
+pub struct SimChain {
+    state: Mutex<SimState>,
+}
+
+struct SimState {
+    chain: LocalChain,
+    graph: TxGraph<ConfirmationBlockTime>,
+    /// Block times by height, since `LocalChain` only keeps hashes.
+    times: BTreeMap<u32, u32>,
+    /// The txids in each block by height, coinbase first.
+    txids: BTreeMap<u32, Vec<Txid>>,
+    mempool: Vec<Arc<Transaction>>,
+    /// When the last broadcast was seen, so every later one is seen strictly after it.
+    last_seen: u64,
+}
+
+impl SimChain {
+    pub fn new() -> Self {
+        let genesis = genesis_block(Network::Regtest);
+        let (chain, _) = LocalChain::from_genesis_hash(genesis.block_hash());
+
+        Self {
+            state: Mutex::new(SimState {
+                chain,
+                graph: TxGraph::default(),
+                times: BTreeMap::from([(0, genesis.header.time)]),
+                txids: BTreeMap::from([(0, vec![genesis.txdata[0].compute_txid()])]),
+                mempool: Vec::new(),
+                last_seen: 0,
+            }),
+        }
+    }
+
+    fn state(&self) -> Result<std::sync::MutexGuard<'_, SimState>> {
+        self.state
+            .lock()
+            .map_err(|_| eyre!("The sim chain lock is poisoned"))
+    }
+}
+
+impl Default for SimChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimState {
+    fn mine_block(&mut self, script_pubkey: ScriptBuf) -> Result<BlockHash> {
+        let tip = self.chain.tip();
+        let height = tip.height() + 1;
+        let time = self.times[&tip.height()] + BLOCK_INTERVAL_SECS;
+
+        let mempool = std::mem::take(&mut self.mempool);
+        let mut fees = Amount::ZERO;
+        for tx in &mempool {
+            fees += self.graph.calculate_fee(tx)?;
+        }
+
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                // BIP34 height push keeps every coinbase txid unique
+                script_sig: ScriptBuf::builder().push_int(height as i64).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: block_subsidy(height) + fees,
+                script_pubkey,
+            }],
+        };
+
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: tip.hash(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase)
+                .chain(mempool.iter().map(|tx| tx.as_ref().clone()))
+                .collect(),
+        };
+        block.header.merkle_root = block
+            .compute_merkle_root()
+            .ok_or_else(|| eyre!("A block needs at least a coinbase"))?;
+
+        let block_id = BlockId {
+            height,
+            hash: block.block_hash(),
+        };
+        self.chain.insert_block(block_id)?;
+        self.times.insert(height, time);
+        self.txids.insert(
+            height,
+            block.txdata.iter().map(Transaction::compute_txid).collect(),
+        );
+
+        let anchor = ConfirmationBlockTime {
+            block_id,
+            confirmation_time: time as u64,
+        };
+        for tx in block.txdata {
+            let txid = tx.compute_txid();
+            let _ = self.graph.insert_tx(tx);
+            let _ = self.graph.insert_anchor(txid, anchor);
+        }
+
+        Ok(block_id.hash)
+    }
+
+    /// The height a transaction confirmed at, if it's in the chain.
+    fn confirmed_height(&self, txid: Txid) -> Option<u32> {
+        self.graph
+            .all_anchors()
+            .get(&txid)?
+            .iter()
+            .map(|anchor| anchor.block_id.height)
+            .min()
+    }
+
+    fn in_mempool(&self, txid: Txid) -> Option<&Arc<Transaction>> {
+        self.mempool.iter().find(|tx| tx.compute_txid() == txid)
+    }
+
+    /// Whether a transaction is confirmed or in the mempool, rather than replaced.
+    fn is_live(&self, txid: Txid) -> bool {
+        self.confirmed_height(txid).is_some() || self.in_mempool(txid).is_some()
+    }
+
+    /// Checks a broadcast, returning the mempool transactions it replaces.
+    fn validate(&self, tx: &Transaction) -> Result<HashSet<Txid>> {
+        if tx.is_coinbase() {
+            return Err(eyre!("Coinbase transactions can't be broadcast"));
+        }
+        if tx.input.is_empty() || tx.output.is_empty() {
+            return Err(eyre!("Transactions need inputs and outputs"));
+        }
+
+        let next_height = self.chain.tip().height() + 1;
+        let mut spent = HashSet::new();
+        let mut value_in = Amount::ZERO;
+        let mut conflicts = HashSet::new();
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            if !spent.insert(outpoint) {
+                return Err(eyre!("{outpoint} is spent twice"));
+            }
+
+            let prev_tx = self
+                .graph
+                .get_tx(outpoint.txid)
+                .filter(|_| self.is_live(outpoint.txid))
+                .ok_or_else(|| eyre!("Missing inputs: {outpoint}"))?;
+            let prevout = prev_tx
+                .output
+                .get(outpoint.vout as usize)
+                .ok_or_else(|| eyre!("Missing inputs: {outpoint}"))?;
+            for &spender in self.graph.outspends(outpoint) {
+                if self.confirmed_height(spender).is_some() {
+                    return Err(eyre!("{outpoint} is already spent"));
+                }
+                if self.in_mempool(spender).is_some() {
+                    conflicts.insert(spender);
+                }
+            }
+            if prev_tx.is_coinbase() {
+                let confirmations = self
+                    .confirmed_height(outpoint.txid)
+                    .map_or(0, |height| next_height - height);
+                if confirmations < COINBASE_MATURITY {
+                    return Err(eyre!("{outpoint} is an immature coinbase"));
+                }
+            }
+            value_in += prevout.value;
+        }
+
+        let value_out: Amount = tx.output.iter().map(|output| output.value).sum();
+        if value_out > value_in {
+            return Err(eyre!("Outputs ({value_out}) exceed inputs ({value_in})"));
+        }
+
+        let fee = value_in - value_out;
+        let mut replaced_fees = Amount::ZERO;
+        for &txid in &conflicts {
+            let replaced = self
+                .in_mempool(txid)
+                .ok_or_else(|| eyre!("{txid} left the mempool"))?;
+            let replaced_fee = self.graph.calculate_fee(replaced)?;
+            // Cross-multiplied so neither feerate gets rounded
+            let pays_more = u128::from(fee.to_sat()) * u128::from(replaced.weight().to_wu())
+                > u128::from(replaced_fee.to_sat()) * u128::from(tx.weight().to_wu());
+            if !pays_more {
+                return Err(eyre!("Replacing {txid} needs a higher feerate"));
+            }
+            replaced_fees += replaced_fee;
+        }
+        if !conflicts.is_empty() && fee <= replaced_fees {
+            return Err(eyre!(
+                "Replacements must pay more than the {replaced_fees} they replace"
+            ));
+        }
+        Ok(conflicts)
+    }
+
+    /// Drops `txids` from the mempool along with everything that spends from them.
+    fn evict(&mut self, mut txids: HashSet<Txid>) {
+        loop {
+            let before = txids.len();
+            for tx in &self.mempool {
+                if tx
+                    .input
+                    .iter()
+                    .any(|input| txids.contains(&input.previous_output.txid))
+                {
+                    txids.insert(tx.compute_txid());
+                }
+            }
+            if txids.len() == before {
+                break;
+            }
+        }
+        for txid in &txids {
+            info!("Sim evicted {txid}");
+        }
+        self.mempool
+            .retain(|tx| !txids.contains(&tx.compute_txid()));
+    }
+
+    /// Every transaction touching the given scripts, txids or outpoints.
+    fn tx_update(
+        &self,
+        spks: &HashSet<ScriptBuf>,
+        txids: &HashSet<Txid>,
+        outpoints: &HashSet<OutPoint>,
+    ) -> TxUpdate<ConfirmationBlockTime> {
+        let mut update = TxUpdate::default();
+
+        for node in self.graph.full_txs() {
+            let pays_to = node
+                .output
+                .iter()
+                .any(|output| spks.contains(&output.script_pubkey));
+            let spends_from = node.input.iter().any(|input| {
+                outpoints.contains(&input.previous_output)
+                    || self
+                        .graph
+                        .get_txout(input.previous_output)
+                        .is_some_and(|prevout| spks.contains(&prevout.script_pubkey))
+            });
+            let creates = (0..node.output.len() as u32)
+                .any(|vout| outpoints.contains(&OutPoint::new(node.txid, vout)));
+            if !(pays_to || spends_from || creates || txids.contains(&node.txid)) {
+                continue;
+            }
+            if !self.is_live(node.txid) {
+                continue;
+            }
+
+            // Prevouts let the wallet work out fees
+            for input in &node.input {
+                if let Some(prevout) = self.graph.get_txout(input.previous_output) {
+                    update.txouts.insert(input.previous_output, prevout.clone());
+                }
+            }
+            for anchor in node.anchors {
+                update.anchors.insert((*anchor, node.txid));
+            }
+            if let Some(seen_at) = node.last_seen_unconfirmed {
+                update.seen_ats.insert(node.txid, seen_at);
+            }
+            update.txs.push(node.tx.clone());
+        }
+
+        update
+    }
+
+    fn is_used(&self, spk: &ScriptBuf) -> bool {
+        self.graph.full_txs().any(|node| {
+            node.output
+                .iter()
+                .any(|output| output.script_pubkey == *spk)
+        })
+    }
+}
+
+impl ChainBackend for SimChain {
+    fn tip(&self) -> Result<BlockId> {
+        Ok(self.state()?.chain.tip().block_id())
+    }
+
+    fn mine_blocks(
+        &self,
+        count: u32,
+        address: &Address,
+        _signer: Option<&BlockSigner>,
+        _cancel: &Cancel,
+    ) -> Result<Vec<BlockHash>> {
+        let mut state = self.state()?;
+        let hashes = (0..count)
+            .map(|_| state.mine_block(address.script_pubkey()))
+            .collect::<Result<Vec<_>>>()?;
+        info!(
+            "Sim mined {} blocks, tip: {:?}",
+            hashes.len(),
+            hashes.last()
+        );
+        Ok(hashes)
+    }
+
+    fn block(&self, hash: &BlockHash) -> Result<BlockSummary> {
+        let state = self.state()?;
+        let height = state
+            .chain
+            .iter_checkpoints()
+            .find(|checkpoint| checkpoint.hash() == *hash)
+            .map(|checkpoint| checkpoint.height())
+            .ok_or_else(|| eyre!("Block {hash} isn't in the chain"))?;
+        Ok(BlockSummary {
+            height,
+            hash: *hash,
+            txids: state.txids.get(&height).cloned().unwrap_or_default(),
+        })
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+        let mut state = self.state()?;
+        let txid = tx.compute_txid();
+        if state.is_live(txid) {
+            return Ok(txid);
+        }
+
+        let replaced = state.validate(tx)?;
+        state.evict(replaced);
+
+        // Wallets keep whichever of two conflicts they saw last, so a replacement has to be seen
+        // after what it replaces even within the same second
+        let seen_at = unix_now().max(state.last_seen + 1);
+        state.last_seen = seen_at;
+
+        let tx = Arc::new(tx.clone());
+        let _ = state.graph.insert_tx(tx.clone());
+        let _ = state.graph.insert_seen_at(txid, seen_at);
+        state.mempool.push(tx);
+        Ok(txid)
+    }
+
+    /// Fees don't matter to the sim's mempool, so there's nothing to estimate from.
+    fn estimate_fee(&self, _target: u16) -> Result<Option<FeeRate>> {
+        Ok(None)
+    }
+
+    fn sync(&self, mut request: SyncRequest<(KeychainKind, u32)>) -> Result<SyncResponse> {
+        let spks = request.iter_spks().collect();
+        let txids = request.iter_txids().collect();
+        let outpoints = request.iter_outpoints().collect();
+
+        let state = self.state()?;
+        Ok(SyncResponse {
+            tx_update: state.tx_update(&spks, &txids, &outpoints),
+            chain_update: Some(state.chain.tip()),
+        })
+    }
+
+    fn full_scan(
+        &self,
+        mut request: FullScanRequest<KeychainKind>,
+        stop_gap: usize,
+    ) -> Result<FullScanResponse<KeychainKind>> {
+        let state = self.state()?;
+        let mut last_active_indices = BTreeMap::new();
+        let mut spks = HashSet::new();
+
+        for keychain in request.keychains() {
+            let mut unused = 0;
+            for (index, spk) in request.iter_spks(keychain) {
+                if state.is_used(&spk) {
+                    last_active_indices.insert(keychain, index);
+                    unused = 0;
+                } else {
+                    unused += 1;
+                }
+                spks.insert(spk);
+                if unused >= stop_gap {
+                    break;
+                }
+            }
+        }
+
+        Ok(FullScanResponse {
+            tx_update: state.tx_update(&spks, &HashSet::new(), &HashSet::new()),
+            last_active_indices,
+            chain_update: Some(state.chain.tip()),
+        })
+    }
+}
+
+fn block_subsidy(height: u32) -> Amount {
+    let halvings = height / REGTEST_HALVING_INTERVAL;
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_sat((50 * 100_000_000) >> halvings)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::Wallet;
+    use bitcoin::Script;
+
+    use super::*;
+
+    const XPRIV: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+    const SUBSIDY: Amount = Amount::from_sat(50 * 100_000_000);
+
+    fn address() -> Address {
+        Address::p2wsh(Script::new(), Network::Regtest)
+    }
+
+    /// The sim mines without a signer and never stops early.
+    fn mine_to(chain: &SimChain, count: u32, address: &Address) -> Vec<BlockHash> {
+        chain
+            .mine_blocks(count, address, None, &Cancel::default())
+            .unwrap()
+    }
+
+    fn spend(outpoints: &[OutPoint], value: Amount) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: outpoints
+                .iter()
+                .map(|&previous_output| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value,
+                script_pubkey: address().script_pubkey(),
+            }],
+        }
+    }
+
+    /// The coinbase output of the block at `height`.
+    fn coinbase(chain: &SimChain, height: u32) -> OutPoint {
+        OutPoint::new(chain.state().unwrap().txids[&height][0], 0)
+    }
+
+    fn wallet() -> Wallet {
+        Wallet::create(
+            format!("tr({XPRIV}/86h/1h/0h/0/*)"),
+            format!("tr({XPRIV}/86h/1h/0h/1/*)"),
+        )
+        .network(Network::Regtest)
+        .create_wallet_no_persist()
+        .unwrap()
+    }
+
+    #[test]
+    fn mines_blocks_onto_the_tip() {
+        let chain = SimChain::new();
+        let hashes = mine_to(&chain, 3, &address());
+
+        let tip = chain.tip().unwrap();
+        assert_eq!(tip.height, 3);
+        assert_eq!(tip.hash, hashes[2]);
+        let block = chain.block(&hashes[0]).unwrap();
+        assert_eq!(block.height, 1);
+        assert_eq!(block.txids.len(), 1);
+        let state = chain.state().unwrap();
+        assert_eq!(
+            state.graph.get_tx(block.txids[0]).unwrap().output[0].value,
+            SUBSIDY
+        );
+    }
+
+    #[test]
+    fn broadcasts_confirm_in_the_next_block() {
+        let chain = SimChain::new();
+        mine_to(&chain, 101, &address());
+        let fee = Amount::from_sat(1_000);
+        let tx = spend(&[coinbase(&chain, 1)], SUBSIDY - fee);
+
+        let txid = chain.broadcast(&tx).unwrap();
+        // Broadcasting again is a no-op
+        assert_eq!(chain.broadcast(&tx).unwrap(), txid);
+        let hash = mine_to(&chain, 1, &address())[0];
+
+        let block = chain.block(&hash).unwrap();
+        assert_eq!(block.txids[1..], [txid]);
+        let state = chain.state().unwrap();
+        assert!(state.mempool.is_empty());
+        assert_eq!(state.confirmed_height(txid), Some(102));
+        // The miner collects the fee
+        assert_eq!(
+            state.graph.get_tx(block.txids[0]).unwrap().output[0].value,
+            SUBSIDY + fee
+        );
+    }
+
+    #[test]
+    fn coinbases_mature_after_100_blocks() {
+        let chain = SimChain::new();
+        mine_to(&chain, 99, &address());
+        let tx = spend(&[coinbase(&chain, 1)], SUBSIDY);
+
+        let err = chain.broadcast(&tx).unwrap_err();
+        assert!(err.to_string().contains("immature"), "{err}");
+        mine_to(&chain, 1, &address());
+        chain.broadcast(&tx).unwrap();
+    }
+
+    #[test]
+    fn rejects_double_spends() {
+        let chain = SimChain::new();
+        mine_to(&chain, 102, &address());
+        let outpoint = coinbase(&chain, 1);
+
+        let err = chain
+            .broadcast(&spend(&[outpoint, outpoint], SUBSIDY))
+            .unwrap_err();
+        assert!(err.to_string().contains("spent twice"), "{err}");
+
+        chain.broadcast(&spend(&[outpoint], SUBSIDY)).unwrap();
+        mine_to(&chain, 1, &address());
+        let err = chain
+            .broadcast(&spend(&[outpoint, coinbase(&chain, 2)], SUBSIDY))
+            .unwrap_err();
+        assert!(err.to_string().contains("already spent"), "{err}");
+    }
+
+    #[test]
+    fn rejects_overspends_and_unknown_inputs() {
+        let chain = SimChain::new();
+        mine_to(&chain, 101, &address());
+
+        let err = chain
+            .broadcast(&spend(&[coinbase(&chain, 1)], SUBSIDY + Amount::ONE_SAT))
+            .unwrap_err();
+        assert!(err.to_string().contains("exceed"), "{err}");
+
+        let unknown = OutPoint::new(Txid::all_zeros(), 0);
+        let err = chain.broadcast(&spend(&[unknown], SUBSIDY)).unwrap_err();
+        assert!(err.to_string().contains("Missing inputs"), "{err}");
+    }
+
+    #[test]
+    fn higher_feerate_conflicts_replace_mempool_spends() {
+        let chain = SimChain::new();
+        mine_to(&chain, 101, &address());
+        let outpoint = coinbase(&chain, 1);
+
+        let original = spend(&[outpoint], SUBSIDY - Amount::from_sat(1_000));
+        let original_txid = chain.broadcast(&original).unwrap();
+        let child = spend(
+            &[OutPoint::new(original_txid, 0)],
+            SUBSIDY - Amount::from_sat(2_000),
+        );
+        chain.broadcast(&child).unwrap();
+
+        let err = chain
+            .broadcast(&spend(&[outpoint], SUBSIDY - Amount::from_sat(500)))
+            .unwrap_err();
+        assert!(err.to_string().contains("higher feerate"), "{err}");
+
+        let replacement = spend(&[outpoint], SUBSIDY - Amount::from_sat(5_000));
+        let replacement_txid = chain.broadcast(&replacement).unwrap();
+
+        // The child went with its parent
+        let err = chain.broadcast(&child).unwrap_err();
+        assert!(err.to_string().contains("Missing inputs"), "{err}");
+        let hash = mine_to(&chain, 1, &address())[0];
+        assert_eq!(chain.block(&hash).unwrap().txids[1..], [replacement_txid]);
+    }
+
+    #[test]
+    fn wallets_find_their_coins() {
+        let chain = SimChain::new();
+        let mut wallet = wallet();
+        let address = wallet.peek_address(KeychainKind::External, 0).address;
+        mine_to(&chain, 3, &address);
+
+        let request = wallet.start_full_scan().build();
+        wallet
+            .apply_update(chain.full_scan(request, 20).unwrap())
+            .unwrap();
+        assert_eq!(wallet.balance().total(), SUBSIDY * 3);
+        assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+
+        mine_to(&chain, 1, &address);
+        let request = wallet.start_sync_with_revealed_spks().build();
+        wallet.apply_update(chain.sync(request).unwrap()).unwrap();
+        assert_eq!(wallet.balance().total(), SUBSIDY * 4);
+        assert_eq!(wallet.latest_checkpoint().height(), 4);
+    }
+}
@@ -0,0 +1,408 @@
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, bounded};
+use signet_miner::rpc_client::RpcClient;
+
+use crate::bdk_zone::{launch_bitcoind_process, rpc_client};
+use crate::bitcoind::BitcoindProcess;
+use crate::electrum_wallet::{ElectrsProcess, spawn_electrs};
+use crate::keys::SlotKeys;
+use crate::node_config::{Backend, NodeConfig};
+
+/// Watches bitcoind and electrs, restarting them with backoff when they die or stop answering.
+pub struct Supervisor;
+
+impl Plugin for Supervisor {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ServiceEvent>()
+            .init_resource::<ServiceStatus>()
+            .insert_resource(Supervised::default())
+            .add_systems(Startup, start_probes)
+            .add_systems(Update, supervise);
+    }
+}
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a running service may go without answering before it gets killed and restarted.
+const UNREACHABLE_GRACE: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_RESTARTS: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Service {
+    Bitcoind,
+    Electrs,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ServiceState {
+    /// Launched but not answering yet.
+    #[default]
+    Starting,
+    Running,
+    /// The process is alive but stopped answering.
+    Unreachable,
+    /// The process died; a restart is scheduled.
+    Restarting {
+        attempt: u32,
+    },
+    /// Gave up after too many restarts.
+    Failed,
+}
+
+/// The last known state of each backing service.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct ServiceStatus {
+    pub bitcoind: ServiceState,
+    pub electrs: ServiceState,
+}
+
+impl ServiceStatus {
+    pub fn get(&self, service: Service) -> ServiceState {
+        match service {
+            Service::Bitcoind => self.bitcoind,
+            Service::Electrs => self.electrs,
+        }
+    }
+
+    pub fn is_running(&self, service: Service) -> bool {
+        self.get(service) == ServiceState::Running
+    }
+}
+
+/// Sent whenever a service changes state.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ServiceEvent {
+    pub service: Service,
+    pub state: ServiceState,
+}
+
+/// Run condition for systems that talk to electrs.
+pub fn electrs_running(status: Res<ServiceStatus>) -> bool {
+    status.is_running(Service::Electrs)
+}
+
+/// Run condition for systems that talk to bitcoind.
+pub fn bitcoind_running(status: Res<ServiceStatus>) -> bool {
+    status.is_running(Service::Bitcoind)
+}
+
+/// Reachability as seen by the probe thread.
+#[derive(Clone, Copy, Debug, Default)]
+struct HealthReport {
+    bitcoind: bool,
+    electrs: bool,
+}
+
+#[derive(Default)]
+struct Supervision {
+    restarts: u32,
+    retry_at: Option<Duration>,
+    last_seen: Duration,
+}
+
+#[derive(Resource, Default)]
+struct Supervised {
+    receiver: Option<Receiver<HealthReport>>,
+    report: HealthReport,
+    bitcoind: Supervision,
+    electrs: Supervision,
+}
+
+/// Probe RPC and electrum on a background thread so a hung service can't stall a frame.
+fn start_probes(
+    mut supervised: ResMut<Supervised>,
+    mut status: ResMut<ServiceStatus>,
+    node_config: Res<NodeConfig>,
+) {
+    // The sim backend is always up
+    if node_config.backend == Backend::Sim {
+        status.bitcoind = ServiceState::Running;
+        status.electrs = ServiceState::Running;
+        return;
+    }
+
+    let (report_tx, report_rx) = bounded::<HealthReport>(1);
+    let config = node_config.clone();
+
+    std::thread::spawn(move || {
+        let mut rpc: Option<RpcClient> = None;
+        loop {
+            std::thread::sleep(PROBE_INTERVAL);
+
+            // The cookie changes whenever bitcoind restarts, so drop the client on any error
+            if rpc.is_none() {
+                rpc = rpc_client(&config).ok();
+            }
+            let bitcoind = match &rpc {
+                Some(client) => client.get_blockchain_info().is_ok(),
+                None => false,
+            };
+            if !bitcoind {
+                rpc = None;
+            }
+
+            let electrs = config
+                .electrum_addr()
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .is_some_and(|addr| TcpStream::connect_timeout(&addr, PROBE_TIMEOUT).is_ok());
+
+            if report_tx.send(HealthReport { bitcoind, electrs }).is_err() {
+                // The app is gone
+                return;
+            }
+        }
+    });
+
+    supervised.receiver = Some(report_rx);
+}
+
+fn supervise(
+    time: Res<Time>,
+    node_config: Res<NodeConfig>,
+    keys: Option<Res<SlotKeys>>,
+    mut supervised: ResMut<Supervised>,
+    mut status: ResMut<ServiceStatus>,
+    mut bitcoind: Option<ResMut<BitcoindProcess>>,
+    mut electrs: Option<ResMut<ElectrsProcess>>,
+    mut events: EventWriter<ServiceEvent>,
+) {
+    let now = time.elapsed();
+    let latest = supervised
+        .receiver
+        .as_ref()
+        .and_then(|receiver| receiver.try_iter().last());
+    if let Some(report) = latest {
+        supervised.report = report;
+    }
+    let report = supervised.report;
+
+    if let Some(process) = bitcoind.as_deref_mut() {
+        let state = supervise_one(
+            Service::Bitcoind,
+            status.bitcoind,
+            &mut supervised.bitcoind,
+            &mut process.child,
+            report.bitcoind,
+            now,
+            || launch_bitcoind_process(&node_config, keys.as_deref()),
+        );
+        if state != status.bitcoind {
+            status.bitcoind = state;
+            events.write(ServiceEvent {
+                service: Service::Bitcoind,
+                state,
+            });
+        }
+    }
+
+    if let Some(process) = electrs.as_deref_mut() {
+        let state = supervise_one(
+            Service::Electrs,
+            status.electrs,
+            &mut supervised.electrs,
+            &mut process.child,
+            report.electrs,
+            now,
+            || spawn_electrs(&node_config, keys.as_deref()).map(|(child, _, _)| child),
+        );
+        if state != status.electrs {
+            status.electrs = state;
+            events.write(ServiceEvent {
+                service: Service::Electrs,
+                state,
+            });
+        }
+    }
+}
+
+fn supervise_one<E: std::fmt::Display>(
+    service: Service,
+    state: ServiceState,
+    supervision: &mut Supervision,
+    child: &mut std::process::Child,
+    reachable: bool,
+    now: Duration,
+    respawn: impl FnOnce() -> Result<std::process::Child, E>,
+) -> ServiceState {
+    match state {
+        ServiceState::Failed => ServiceState::Failed,
+        ServiceState::Restarting { attempt } => {
+            if supervision.retry_at.is_some_and(|retry_at| now < retry_at) {
+                return state;
+            }
+            match respawn() {
+                Ok(new_child) => {
+                    info!(
+                        "Restarted {service:?} (attempt {attempt}), PID {}",
+                        new_child.id()
+                    );
+                    *child = new_child;
+                    supervision.retry_at = None;
+                    supervision.last_seen = now;
+                    ServiceState::Starting
+                }
+                Err(err) => {
+                    warn!("Failed to restart {service:?}: {err}");
+                    schedule_restart(service, supervision, now)
+                }
+            }
+        }
+        _ => match child.try_wait() {
+            Ok(Some(exit)) => {
+                warn!("{service:?} exited with {exit}");
+                schedule_restart(service, supervision, now)
+            }
+            Ok(None) if reachable => {
+                supervision.restarts = 0;
+                supervision.last_seen = now;
+                ServiceState::Running
+            }
+            Ok(None) => {
+                // Only kill a service that was up before; a fresh electrs can index for minutes
+                let was_up = matches!(state, ServiceState::Running | ServiceState::Unreachable);
+                if was_up && now.saturating_sub(supervision.last_seen) > UNREACHABLE_GRACE {
+                    warn!("{service:?} hasn't answered for {UNREACHABLE_GRACE:?}; killing it");
+                    if let Err(err) = child.kill() {
+                        error!("Failed to kill {service:?}: {err}");
+                    }
+                    // Reap it so the next pass sees the exit
+                    let _ = child.wait();
+                    supervision.last_seen = now;
+                }
+                if was_up {
+                    ServiceState::Unreachable
+                } else {
+                    ServiceState::Starting
+                }
+            }
+            Err(err) => {
+                error!("Failed to check {service:?} status: {err}");
+                state
+            }
+        },
+    }
+}
+
+fn schedule_restart(
+    service: Service,
+    supervision: &mut Supervision,
+    now: Duration,
+) -> ServiceState {
+    if supervision.restarts >= MAX_RESTARTS {
+        error!("{service:?} keeps dying; giving up after {MAX_RESTARTS} restarts");
+        return ServiceState::Failed;
+    }
+    supervision.restarts += 1;
+    let backoff = Duration::from_secs(1 << supervision.restarts.min(6)).min(MAX_BACKOFF);
+    info!("Restarting {service:?} in {backoff:?}");
+    supervision.retry_at = Some(now + backoff);
+    ServiceState::Restarting {
+        attempt: supervision.restarts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Child, Command};
+
+    use super::*;
+
+    fn exited() -> Child {
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        child
+    }
+
+    fn long_running() -> Child {
+        Command::new("sleep").arg("60").spawn().unwrap()
+    }
+
+    #[test]
+    fn crashed_service_is_restarted() {
+        let mut supervision = Supervision::default();
+        let mut child = exited();
+        let start = Duration::from_secs(100);
+        let never = || -> Result<Child, String> { panic!("restarted before the backoff") };
+
+        let state = supervise_one(
+            Service::Bitcoind,
+            ServiceState::Running,
+            &mut supervision,
+            &mut child,
+            false,
+            start,
+            never,
+        );
+        assert_eq!(state, ServiceState::Restarting { attempt: 1 });
+
+        let state = supervise_one(
+            Service::Bitcoind,
+            state,
+            &mut supervision,
+            &mut child,
+            false,
+            start + Duration::from_secs(1),
+            never,
+        );
+        assert_eq!(state, ServiceState::Restarting { attempt: 1 });
+
+        let state = supervise_one(
+            Service::Bitcoind,
+            state,
+            &mut supervision,
+            &mut child,
+            false,
+            start + Duration::from_secs(2),
+            || Ok::<_, String>(long_running()),
+        );
+        assert_eq!(state, ServiceState::Starting);
+
+        let state = supervise_one(
+            Service::Bitcoind,
+            state,
+            &mut supervision,
+            &mut child,
+            true,
+            start + Duration::from_secs(4),
+            never,
+        );
+        assert_eq!(state, ServiceState::Running);
+        assert_eq!(supervision.restarts, 0);
+        child.kill().unwrap();
+    }
+
+    #[test]
+    fn backoff_grows_until_it_gives_up() {
+        let mut supervision = Supervision::default();
+        let mut child = exited();
+        let mut now = Duration::ZERO;
+        let mut state = ServiceState::Running;
+        let mut backoffs = Vec::new();
+
+        // Every restart fails, so each attempt schedules the next
+        while state != ServiceState::Failed {
+            state = supervise_one(
+                Service::Electrs,
+                state,
+                &mut supervision,
+                &mut child,
+                false,
+                now,
+                || Err("no electrs"),
+            );
+            if let Some(retry_at) = supervision.retry_at.filter(|&retry_at| retry_at > now) {
+                backoffs.push((retry_at - now).as_secs());
+                now = retry_at;
+            }
+        }
+
+        assert_eq!(backoffs, [2, 4, 8, 16, 32, 60, 60, 60]);
+        assert_eq!(supervision.restarts, MAX_RESTARTS);
+    }
+}
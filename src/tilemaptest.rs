@@ -2,9 +2,9 @@
 #![allow(clippy::too_many_arguments)]
 
 use crate::{
-    bdk_zone::get_data_dir,
-    constants::{ImgAsset, MAP_DIR, MAP_JSON, PopupBase, Z_TILEMAP},
-    tourists::{TouristDespawnPoint, TouristSpawnPoint},
+    constants::{ImgAsset, MAP_JSON, PopupBase, Z_TILEMAP},
+    node_config::NodeConfig,
+    tourists::{TouristDespawnPoint, TouristSpawnPoint, TouristTrap},
 };
 use bevy::platform::collections::HashSet;
 use bevy::prelude::*;
@@ -39,7 +39,7 @@ impl Plugin for GameMap {
             .add_systems(
                 First,
                 (
-                    crate::camera::movement,
+                    crate::camera::movement.run_if(crate::send_dialog::send_dialog_closed),
                     update_cursor_pos,
                     update_cur_tile_pos,
                 )
@@ -85,9 +85,14 @@ pub struct TileValues {
     pub buddies: TileBuddies,
     pub spawnpoint: Option<TouristSpawnPoint>,
     pub despawnpoint: Option<TouristDespawnPoint>,
+    pub trap: Option<TouristTrap>,
 }
 
-fn startup_original_tiles(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn startup_original_tiles(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    node_config: Res<NodeConfig>,
+) {
     commands.spawn(Camera2d);
 
     let image_handles: Vec<Handle<Image>> = ImgAsset::iter()
@@ -114,7 +119,7 @@ fn startup_original_tiles(mut commands: Commands, asset_server: Res<AssetServer>
     // it is associated with. This is done with the TilemapId component on each tile.
     let tilemap_entity = commands.spawn_empty().id();
 
-    let map_json_file = get_data_dir(Some(MAP_DIR.into())).unwrap().join(MAP_JSON);
+    let map_json_file = node_config.map_dir().unwrap().join(MAP_JSON);
 
     let map: Vec<TileValues> = if let Ok(map) = fs::read_to_string(&map_json_file) {
         serde_json::from_str(&map).unwrap()
@@ -132,6 +137,7 @@ fn startup_original_tiles(mut commands: Commands, asset_server: Res<AssetServer>
                     buddies: TileBuddies::default(),
                     spawnpoint: None,
                     despawnpoint: None,
+                    trap: None,
                 };
                 map.push(value);
             }
@@ -159,6 +165,9 @@ fn startup_original_tiles(mut commands: Commands, asset_server: Res<AssetServer>
         if let Some(despawnpoint) = tile_values.despawnpoint {
             commands.entity(tile_entity).insert(despawnpoint);
         }
+        if let Some(trap) = tile_values.trap {
+            commands.entity(tile_entity).insert(trap);
+        }
         tile_storage.set(&tile_values.pos, tile_entity);
     }
 
@@ -201,7 +210,9 @@ fn save_tilemap(
         &TileBuddies,
         Option<&TouristSpawnPoint>,
         Option<&TouristDespawnPoint>,
+        Option<&TouristTrap>,
     )>,
+    node_config: Res<NodeConfig>,
 ) {
     let test = TouristSpawnPoint {};
 
@@ -211,9 +222,18 @@ fn save_tilemap(
                 let items: Vec<TileValues> = tilemap_q
                     .iter()
                     .map(
-                        |(pos, alpha_pos, idx, buddies, maybe_spawn_point, maybe_despawn_point)| {
+                        |(
+                            pos,
+                            alpha_pos,
+                            idx,
+                            buddies,
+                            maybe_spawn_point,
+                            maybe_despawn_point,
+                            maybe_trap,
+                        )| {
                             let spawnpoint = maybe_spawn_point.cloned();
                             let despawnpoint = maybe_despawn_point.cloned();
+                            let trap = maybe_trap.cloned();
                             TileValues {
                                 pos: *pos,
                                 alpha_pos: *alpha_pos,
@@ -221,12 +241,13 @@ fn save_tilemap(
                                 buddies: buddies.clone(),
                                 spawnpoint,
                                 despawnpoint,
+                                trap,
                             }
                         },
                     )
                     .collect();
                 let json_items = serde_json::to_string(&items).unwrap();
-                let map_json_file = get_data_dir(Some(MAP_DIR.into())).unwrap().join(MAP_JSON);
+                let map_json_file = node_config.map_dir().unwrap().join(MAP_JSON);
                 fs::write(map_json_file, json_items).unwrap();
             }
         }
@@ -1,13 +1,16 @@
+use bevy::platform::collections::HashSet;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::tiles::{TilePos, TileStorage, TileTextureIndex};
+use bitcoin::Address;
+use bitcoin::address::NetworkUnchecked;
 use pathfinding::{grid::Grid, prelude::astar};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    bdk_zone::mine_blocks,
     constants::{ImgAsset, WALKABLES},
-    tilemaptest::{tilepos_to_transform, translation_to_tilepos, usizes_to_transform},
+    game_state::GameState,
+    tilemaptest::{AlphaPos, tilepos_to_transform, translation_to_tilepos, usizes_to_transform},
 };
 
 pub struct Tourists;
@@ -17,12 +20,13 @@ impl Plugin for Tourists {
         app.init_resource::<CurrentRound>()
             .add_event::<RedrawGrid>()
             .add_event::<RecalcTouristPath>()
+            .add_event::<TrapVisited>()
+            .add_event::<RoundStarted>()
             .add_systems(PostStartup, post_startup)
             .add_systems(
                 Update,
                 (
-                    tourist_spawner,
-                    move_tourist,
+                    (tourist_spawner, move_tourist).run_if(in_state(GameState::Playing)),
                     redraw_grid,
                     path_recalculator,
                 ),
@@ -70,6 +74,20 @@ pub enum RecalcTouristPath {
     NewGoal((Entity, TilePos)),
 }
 
+/// A tourist stepped onto a trap it hasn't paid yet. `trap` is the alpha tile of the trap's
+/// building.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct TrapVisited {
+    pub tourist: Entity,
+    pub trap: AlphaPos,
+}
+
+/// The next round of tourists is on its way.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct RoundStarted {
+    pub round: u32,
+}
+
 #[derive(Component, Deref, DerefMut)]
 struct SpawnTouristTimer(Timer);
 
@@ -83,6 +101,7 @@ struct CurrentRound(pub u32);
 pub struct Tourist {
     status: TouristStatus,
     path: Vec<(usize, usize)>,
+    visited_traps: HashSet<AlphaPos>,
 }
 
 pub enum TouristStatus {
@@ -91,12 +110,6 @@ pub enum TouristStatus {
     Walking(TilePos),
 }
 
-#[derive(Component)]
-pub struct SatsToSend {
-    pub sats: u64,
-    pub iterations: u32,
-}
-
 #[derive(Component, Deref, DerefMut)]
 pub struct TouristGrid(Grid);
 
@@ -106,16 +119,19 @@ pub struct TouristSpawnPoint {}
 #[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TouristDespawnPoint {}
 
+/// The alpha tile of a building tourists pay to visit. `address` is the player wallet address it's
+/// paid at, handed out once the building is placed.
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TouristTrap {
+    pub address: Option<Address<NetworkUnchecked>>,
+}
+
 fn post_startup(
     mut commands: Commands,
     tilemap_q: Query<&TileStorage>,
     position_q: Query<(&TilePos, &TileTextureIndex)>,
     asset_server: Res<AssetServer>,
 ) {
-    commands.spawn(SatsToSend {
-        sats: 0,
-        iterations: 0,
-    });
     commands.spawn(SpawnTouristTimer(Timer::from_seconds(2.0, TimerMode::Once)));
     commands.spawn(NextRound(Timer::from_seconds(10.0, TimerMode::Once)));
 
@@ -281,6 +297,7 @@ fn tourist_spawner(
     despawn_pos_q: Query<&TilePos, With<TouristDespawnPoint>>,
     mut next_round_timer_q: Query<&mut NextRound>,
     mut current_round_q: ResMut<CurrentRound>,
+    mut round_e: EventWriter<RoundStarted>,
 ) {
     for mut timer in &mut spawn_tourist_timer {
         if timer.tick(time.delta()).just_finished() {
@@ -344,6 +361,7 @@ fn tourist_spawner(
                                 Tourist {
                                     status: TouristStatus::Standing,
                                     path: path[1..].into(), // skip current tile
+                                    visited_traps: HashSet::default(),
                                 },
                                 tourist_initial_transform,
                                 GlobalZIndex(6),
@@ -364,13 +382,11 @@ fn tourist_spawner(
 
     for mut timer in &mut next_round_timer_q {
         if timer.0.tick(time.delta()).just_finished() {
-            mine_blocks(
-                8,
-                "bcrt1pkar3gerekw8f9gef9vn9xz0qypytgacp9wa5saelpksdgct33qdqan7c89",
-            )
-            .unwrap();
             current_round_q.0 += 1;
             info!("current round: {}", current_round_q.0);
+            round_e.write(RoundStarted {
+                round: current_round_q.0,
+            });
             timer.reset();
         }
     }
@@ -386,31 +402,28 @@ fn move_tourist(
         &mut WalkCycleTimer,
     )>,
     mut recalc_ew: EventWriter<RecalcTouristPath>,
+    mut visit_ew: EventWriter<TrapVisited>,
     texture_q: Query<&TileTextureIndex>,
+    alpha_q: Query<&AlphaPos>,
     storage_q: Query<&TileStorage>,
     time: Res<Time>,
-    mut sats_to_send_q: Query<&mut SatsToSend>,
     tourist_sprites: Res<TouristAnimations>,
 ) {
     for (entity, mut tourist, mut transform, mut sprite, mut walk_timer) in tourist_q.iter_mut() {
         let tile_pos = translation_to_tilepos(&transform.translation, Vec2::default());
         let storage = storage_q.single().expect("One tile storage");
-        let is_walkable = if let Some(tile_entity) = storage.checked_get(&tile_pos) {
-            if let Ok(texture_idx) = texture_q.get(tile_entity) {
-                if texture_idx.0 == ImgAsset::SidewalkSpecial.index() {
-                    let mut sats_to_send = sats_to_send_q.single_mut().unwrap();
-                    sats_to_send.sats += 4_000;
-                    sats_to_send.iterations += 1;
-                    true
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        } else {
-            false
-        };
+        // A tourist pays each trap once, however long it lingers there
+        if let Some(tile_entity) = storage.checked_get(&tile_pos)
+            && let Ok(texture_idx) = texture_q.get(tile_entity)
+            && texture_idx.0 == ImgAsset::SidewalkSpecial.index()
+            && let Ok(trap) = alpha_q.get(tile_entity)
+            && tourist.visited_traps.insert(*trap)
+        {
+            visit_ew.write(TrapVisited {
+                tourist: entity,
+                trap: *trap,
+            });
+        }
 
         match &tourist.status {
             TouristStatus::Standing => tourist.status = TouristStatus::Navigating,
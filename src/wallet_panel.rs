@@ -0,0 +1,343 @@
+use bdk_wallet::chain::{ChainPosition, ConfirmationBlockTime};
+use bdk_wallet::{KeychainKind, Wallet};
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use num_format::{Locale, ToFormattedString};
+
+use crate::electrum_wallet::{PlayerWallet, WalletId};
+use crate::game_state::GameState;
+use crate::send_dialog::SendButton;
+use crate::wallet_sync::WalletSynced;
+
+/// A panel with the player wallet's balance, transactions and coins, opened from the button row.
+pub struct WalletPanel;
+
+impl Plugin for WalletPanel {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ToggleWalletPanel>()
+            .init_resource::<PanelState>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (toggle_panel, refresh_panel, scroll_lists)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+const HEADING_TEXT: Color = Color::srgb(0.9, 0.9, 0.9);
+const ROW_TEXT: Color = Color::srgb(0.75, 0.75, 0.75);
+const LINE_HEIGHT: f32 = 18.0;
+/// Only the most recent transactions are listed, so a long history doesn't stall the UI.
+const MAX_ROWS: usize = 200;
+
+/// Open or close the wallet panel.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ToggleWalletPanel;
+
+#[derive(Resource, Default)]
+struct PanelState {
+    open: bool,
+    /// Redraw on the next frame, even without a sync.
+    stale: bool,
+}
+
+#[derive(Component)]
+struct WalletPanelBase;
+
+#[derive(Component, Clone, Copy)]
+enum BalanceLine {
+    Confirmed,
+    TrustedPending,
+    UntrustedPending,
+    Immature,
+}
+
+impl BalanceLine {
+    const ALL: [BalanceLine; 4] = [
+        BalanceLine::Confirmed,
+        BalanceLine::TrustedPending,
+        BalanceLine::UntrustedPending,
+        BalanceLine::Immature,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            BalanceLine::Confirmed => "Confirmed",
+            BalanceLine::TrustedPending => "Pending (ours)",
+            BalanceLine::UntrustedPending => "Pending (incoming)",
+            BalanceLine::Immature => "Immature",
+        }
+    }
+}
+
+/// A list that scrolls with the mouse wheel while hovered.
+#[derive(Component)]
+struct ScrollList;
+
+#[derive(Component)]
+struct TxList;
+
+#[derive(Component)]
+struct UtxoList;
+
+fn setup(mut commands: Commands) {
+    let heading = |text: &str| {
+        (
+            Text::new(text),
+            TextFont {
+                font_size: 20.0,
+                ..default()
+            },
+            TextColor(HEADING_TEXT),
+        )
+    };
+    let list = || {
+        (
+            Node {
+                flex_direction: FlexDirection::Column,
+                flex_grow: 1.0,
+                flex_basis: Val::Px(0.0),
+                overflow: Overflow::scroll_y(),
+                ..default()
+            },
+            ScrollPosition::default(),
+            Interaction::default(),
+            ScrollList,
+        )
+    };
+
+    commands
+        .spawn((
+            Node {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                right: Val::Px(10.0),
+                width: Val::Px(520.0),
+                height: Val::Percent(75.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(6.0),
+                padding: UiRect::all(Val::Px(12.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.1, 0.1, 0.1, 0.9)),
+            GlobalZIndex(5),
+            WalletPanelBase,
+        ))
+        .with_children(|parent| {
+            parent.spawn(heading("Player wallet"));
+            for line in BalanceLine::ALL {
+                parent.spawn((
+                    Text::new(format!("{}: -", line.label())),
+                    TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    },
+                    TextColor(ROW_TEXT),
+                    line,
+                ));
+            }
+            parent.spawn(heading("Transactions"));
+            parent.spawn((list(), TxList));
+            parent.spawn(heading("Coins"));
+            parent.spawn((list(), UtxoList));
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        align_self: AlignSelf::FlexEnd,
+                        padding: UiRect::axes(Val::Px(16.0), Val::Px(6.0)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgb(0.25, 0.25, 0.25)),
+                    SendButton,
+                ))
+                .with_child((
+                    Text::new("Send"),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    TextColor(HEADING_TEXT),
+                ));
+        });
+}
+
+fn toggle_panel(
+    mut toggle_e: EventReader<ToggleWalletPanel>,
+    mut state: ResMut<PanelState>,
+    mut base_q: Query<&mut Node, With<WalletPanelBase>>,
+) {
+    if toggle_e.read().count() % 2 == 0 {
+        return;
+    }
+    state.open = !state.open;
+    state.stale = state.open;
+
+    for mut node in &mut base_q {
+        node.display = if state.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
+fn refresh_panel(
+    mut commands: Commands,
+    mut synced_e: EventReader<WalletSynced>,
+    mut state: ResMut<PanelState>,
+    player_q: Query<&PlayerWallet>,
+    mut balance_q: Query<(&mut Text, &BalanceLine)>,
+    tx_list_q: Query<Entity, With<TxList>>,
+    utxo_list_q: Query<Entity, With<UtxoList>>,
+) {
+    let player_synced = synced_e
+        .read()
+        .filter(|synced| synced.wallet == WalletId::Player)
+        .count();
+    if !state.open || (player_synced == 0 && !state.stale) {
+        return;
+    }
+    let Ok(player) = player_q.single() else {
+        return;
+    };
+    let Ok(wallet) = player.wallet.lock() else {
+        return;
+    };
+    state.stale = false;
+
+    let balance = wallet.balance();
+    for (mut text, line) in &mut balance_q {
+        let amount = match line {
+            BalanceLine::Confirmed => balance.confirmed,
+            BalanceLine::TrustedPending => balance.trusted_pending,
+            BalanceLine::UntrustedPending => balance.untrusted_pending,
+            BalanceLine::Immature => balance.immature,
+        };
+        **text = format!("{}: {} sat", line.label(), sats(amount.to_sat()));
+    }
+
+    let tx_rows = transaction_rows(&wallet);
+    let utxo_rows = utxo_rows(&wallet);
+    drop(wallet);
+
+    for (list, rows) in [
+        (tx_list_q.single(), tx_rows),
+        (utxo_list_q.single(), utxo_rows),
+    ] {
+        let Ok(list) = list else {
+            continue;
+        };
+        commands
+            .entity(list)
+            .despawn_related::<Children>()
+            .with_children(|parent| {
+                for row in rows {
+                    parent.spawn((
+                        Text::new(row),
+                        TextFont {
+                            font_size: 14.0,
+                            ..default()
+                        },
+                        TextColor(ROW_TEXT),
+                    ));
+                }
+            });
+    }
+}
+
+/// Newest first: txid, what it did to the balance, its fee and its confirmations.
+fn transaction_rows(wallet: &Wallet) -> Vec<String> {
+    let tip = wallet.latest_checkpoint().height();
+    let mut rows: Vec<(u32, String)> = wallet
+        .transactions()
+        .map(|tx| {
+            let (sent, received) = wallet.sent_and_received(&tx.tx_node.tx);
+            let net = received.to_sat() as i64 - sent.to_sat() as i64;
+            // Only known when the wallet has every input's prevout
+            let fee = wallet
+                .calculate_fee(&tx.tx_node.tx)
+                .map_or("?".to_string(), |fee| sats(fee.to_sat()));
+            let confirmations = confirmations(&tx.chain_position, tip);
+            let row = format!(
+                "{}  {:>12} sat  fee {:>6}  {} conf",
+                short_txid(&tx.tx_node.txid.to_string()),
+                signed_sats(net),
+                fee,
+                confirmations
+            );
+            (confirmations, row)
+        })
+        .collect();
+    rows.sort_by_key(|(confirmations, _)| *confirmations);
+    rows.into_iter()
+        .take(MAX_ROWS)
+        .map(|(_, row)| row)
+        .collect()
+}
+
+/// Biggest first: outpoint, value, the address index it's at and its confirmations.
+fn utxo_rows(wallet: &Wallet) -> Vec<String> {
+    let tip = wallet.latest_checkpoint().height();
+    let mut utxos: Vec<_> = wallet.list_unspent().collect();
+    utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.txout.value));
+    utxos
+        .into_iter()
+        .take(MAX_ROWS)
+        .map(|utxo| {
+            let keychain = match utxo.keychain {
+                KeychainKind::External => "receive",
+                KeychainKind::Internal => "change",
+            };
+            format!(
+                "{}:{}  {:>12} sat  {} #{}  {} conf",
+                short_txid(&utxo.outpoint.txid.to_string()),
+                utxo.outpoint.vout,
+                sats(utxo.txout.value.to_sat()),
+                keychain,
+                utxo.derivation_index,
+                confirmations(&utxo.chain_position, tip)
+            )
+        })
+        .collect()
+}
+
+fn confirmations(position: &ChainPosition<ConfirmationBlockTime>, tip: u32) -> u32 {
+    match position {
+        ChainPosition::Confirmed { anchor, .. } => (tip + 1).saturating_sub(anchor.block_id.height),
+        ChainPosition::Unconfirmed { .. } => 0,
+    }
+}
+
+fn short_txid(txid: &str) -> String {
+    format!("{}..{}", &txid[..8], &txid[txid.len() - 8..])
+}
+
+fn sats(sat: u64) -> String {
+    sat.to_formatted_string(&Locale::en)
+}
+
+fn signed_sats(sat: i64) -> String {
+    let sign = if sat > 0 { "+" } else { "" };
+    format!("{sign}{}", sat.to_formatted_string(&Locale::en))
+}
+
+fn scroll_lists(
+    mut wheel_e: EventReader<MouseWheel>,
+    mut list_q: Query<(&mut ScrollPosition, &Interaction), With<ScrollList>>,
+) {
+    for wheel in wheel_e.read() {
+        let dy = match wheel.unit {
+            MouseScrollUnit::Line => wheel.y * LINE_HEIGHT,
+            MouseScrollUnit::Pixel => wheel.y,
+        };
+        for (mut scroll, interaction) in &mut list_q {
+            if *interaction != Interaction::None {
+                scroll.offset_y -= dy;
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bdk_wallet::KeychainKind;
+use bdk_wallet::chain::spk_client::{SyncRequest, SyncResponse};
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender, unbounded};
+
+use crate::chain_backend::Chain;
+use crate::electrum_wallet::{PlayerWallet, TouristWallet, WalletId, shared_wallet};
+use crate::game_state::GameState;
+use crate::supervisor::electrs_running;
+
+/// Keeps the game's wallets in sync with the chain from a long-lived background worker.
+pub struct WalletSync;
+
+impl Plugin for WalletSync {
+    fn build(&self, app: &mut App) {
+        app.add_event::<WalletSynced>()
+            .add_event::<SyncFailed>()
+            .init_resource::<SyncSchedule>()
+            .add_systems(Startup, start_worker)
+            .add_systems(
+                Update,
+                (request_syncs.run_if(electrs_running), apply_syncs)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(7);
+const RETRY_BASE: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// A wallet's update was applied.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct WalletSynced {
+    pub wallet: WalletId,
+}
+
+/// A wallet couldn't be synced; it's tried again after `retry_in`.
+#[derive(Event, Clone, Debug)]
+pub struct SyncFailed {
+    pub wallet: WalletId,
+    pub error: String,
+    pub attempt: u32,
+    pub retry_in: Duration,
+}
+
+struct SyncJob {
+    wallet: WalletId,
+    request: SyncRequest<(KeychainKind, u32)>,
+}
+
+struct SyncResult {
+    wallet: WalletId,
+    result: eyre::Result<SyncResponse>,
+}
+
+#[derive(Resource)]
+struct SyncWorker {
+    jobs: Sender<SyncJob>,
+    results: Receiver<SyncResult>,
+}
+
+struct WalletSchedule {
+    timer: Timer,
+    in_flight: bool,
+    failures: u32,
+}
+
+/// When each wallet is synced next. A wallet only has one request in flight at a time.
+#[derive(Resource, Deref, DerefMut)]
+struct SyncSchedule(HashMap<WalletId, WalletSchedule>);
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        Self(
+            WalletId::ALL
+                .into_iter()
+                .map(|wallet| {
+                    let schedule = WalletSchedule {
+                        timer: Timer::new(SYNC_INTERVAL, TimerMode::Once),
+                        in_flight: false,
+                        failures: 0,
+                    };
+                    (wallet, schedule)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The delay before the given retry, doubling up to a minute.
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX)
+}
+
+fn start_worker(mut commands: Commands, chain: Res<Chain>) {
+    let (jobs_tx, jobs_rx) = unbounded::<SyncJob>();
+    let (results_tx, results_rx) = unbounded::<SyncResult>();
+    let chain = chain.clone();
+
+    // Runs until the game drops its end of either channel
+    std::thread::spawn(move || {
+        for SyncJob { wallet, request } in jobs_rx.iter() {
+            let result = chain.sync(request);
+            if results_tx.send(SyncResult { wallet, result }).is_err() {
+                return;
+            }
+        }
+    });
+
+    commands.insert_resource(SyncWorker {
+        jobs: jobs_tx,
+        results: results_rx,
+    });
+}
+
+fn request_syncs(
+    time: Res<Time>,
+    mut schedule: ResMut<SyncSchedule>,
+    worker: Res<SyncWorker>,
+    tourist_q: Query<&TouristWallet>,
+    player_q: Query<&PlayerWallet>,
+) {
+    for (wallet, entry) in schedule.iter_mut() {
+        if entry.in_flight || !entry.timer.tick(time.delta()).finished() {
+            continue;
+        }
+
+        let request = shared_wallet(*wallet, &tourist_q, &player_q)
+            .and_then(|shared| Ok(shared.lock()?.start_sync_with_revealed_spks().build()));
+        let Ok(request) = request else {
+            continue;
+        };
+
+        let job = SyncJob {
+            wallet: *wallet,
+            request,
+        };
+        if worker.jobs.send(job).is_err() {
+            warn!("The wallet sync worker has stopped");
+            continue;
+        }
+        entry.in_flight = true;
+    }
+}
+
+fn apply_syncs(
+    worker: Res<SyncWorker>,
+    mut schedule: ResMut<SyncSchedule>,
+    tourist_q: Query<&TouristWallet>,
+    player_q: Query<&PlayerWallet>,
+    mut synced_e: EventWriter<WalletSynced>,
+    mut failed_e: EventWriter<SyncFailed>,
+) {
+    for SyncResult { wallet, result } in worker.results.try_iter() {
+        let applied = result.and_then(|response| {
+            let shared = shared_wallet(wallet, &tourist_q, &player_q)?;
+            let mut stored = shared.lock()?;
+            stored.apply_update(response)?;
+            if let Err(err) = stored.persist() {
+                warn!("Failed to save the {} wallet: {err}", wallet.name());
+            }
+            Ok(())
+        });
+
+        let Some(entry) = schedule.get_mut(&wallet) else {
+            continue;
+        };
+        entry.in_flight = false;
+
+        match applied {
+            Ok(()) => {
+                entry.failures = 0;
+                entry.timer = Timer::new(SYNC_INTERVAL, TimerMode::Once);
+                synced_e.write(WalletSynced { wallet });
+            }
+            Err(err) => {
+                entry.failures += 1;
+                let retry_in = retry_delay(entry.failures);
+                warn!(
+                    "Syncing the {} wallet failed (attempt {}), retrying in {:?}: {err}",
+                    wallet.name(),
+                    entry.failures,
+                    retry_in
+                );
+                entry.timer = Timer::new(retry_in, TimerMode::Once);
+                failed_e.write(SyncFailed {
+                    wallet,
+                    error: err.to_string(),
+                    attempt: entry.failures,
+                    retry_in,
+                });
+            }
+        }
+    }
+}